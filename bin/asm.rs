@@ -0,0 +1,15 @@
+use mychip8::cli::AsmCli;
+
+use structopt::StructOpt;
+
+fn main() {
+    let args = AsmCli::from_args();
+
+    if let Err(err) = mychip8::asm(args) {
+        eprintln!("ERROR: {}.", err);
+        if let Some(source) = err.source() {
+            eprintln!("SOURCE: {}.", source);
+        }
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,12 @@
+use mychip8::cli::DisasmCli;
+
+use structopt::StructOpt;
+
+fn main() {
+    let args = DisasmCli::from_args();
+
+    if let Err(err) = mychip8::disasm(args) {
+        eprintln!("ERROR: {}.", err);
+        std::process::exit(1);
+    }
+}
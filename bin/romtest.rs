@@ -0,0 +1,16 @@
+use mychip8::cli::RomTestCli;
+
+use structopt::StructOpt;
+
+fn main() {
+    let args = RomTestCli::from_args();
+
+    match mychip8::romtest(args) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("ERROR: {}.", err);
+            std::process::exit(2);
+        }
+    }
+}
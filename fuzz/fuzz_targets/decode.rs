@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mychip8::chip8::operation::{Op, OpCode};
+
+// every opcode decodes to an instruction or to None, and an instruction encodes back to its opcode
+fuzz_target!(|opcode: u16| {
+    if let Some(op) = Op::decode(&OpCode::new(opcode)) {
+        let encoded = op.encode();
+        assert_eq!(Op::decode(&encoded), Some(op));
+        // the operands the instruction ignores, e.g. the low nibble of 5xy0, may differ
+        if !matches!(op, Op::SEV { .. } | Op::SNE { .. }) {
+            assert_eq!(encoded.get_opcode(), opcode);
+        }
+        let _ = op.to_string();
+    }
+});
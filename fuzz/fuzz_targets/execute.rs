@@ -0,0 +1,40 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mychip8::chip8::CHIP8;
+
+/// the frames a ROM runs, 5 seconds at 60 Hz
+const FRAMES: u64 = 300;
+
+// the first byte selects the quirk flags and a key held down, the rest is the ROM.
+// a ROM may fault, but it must never panic
+fuzz_target!(|data: &[u8]| {
+    let (&flags, rom) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    let mut chip8 = CHIP8::new("FUZZ");
+    let mut settings = chip8.settings().clone();
+    settings.shift_vx_ignore_vy = flags & 0b0001 != 0;
+    settings.increment_i_register = flags & 0b0010 != 0;
+    settings.set_vf_when_overflow = flags & 0b0100 != 0;
+    settings.vertical_wrap = flags & 0b1000 != 0;
+    chip8.set_settings(settings);
+    chip8.seed(0);
+    if chip8.load(rom).is_err() {
+        return;
+    }
+
+    for frame in 0..FRAMES {
+        // tap the key every second, to get past the instructions waiting for it
+        match frame % 60 {
+            30 => chip8.press_key(flags >> 4),
+            33 => chip8.release_key(flags >> 4),
+            _ => {}
+        }
+        if chip8.run_frame().is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::memory::{MEMORY_SIZE, PROGRAM_START};
+use crate::chip8::operation::Op;
+use crate::error::AssembleError;
+
+/// the maximum nesting of INCLUDE directives, deeper nesting is most likely a cycle
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// where a statement comes from
+#[derive(Clone)]
+struct Location {
+    file: PathBuf,
+    line: usize,
+}
+
+impl Location {
+    /// create a syntax error at the location
+    fn error(&self, message: String) -> AssembleError {
+        AssembleError::SyntaxError {
+            file: self.file.clone(),
+            line: self.line,
+            message,
+        }
+    }
+}
+
+/// statement which emits bytes
+enum Statement {
+    Instruction(String, Vec<String>), // mnemonic and operands, resolved in the second pass
+    Bytes(Vec<String>),               // DB expressions
+    Words(Vec<String>),               // DW expressions
+    Sprite(Vec<u8>),                  // SPRITE rows
+}
+
+impl Statement {
+    /// return the number of bytes emitted by the statement
+    fn size(&self) -> u32 {
+        match self {
+            Statement::Instruction(..) => 2,
+            Statement::Bytes(bytes) => bytes.len() as u32,
+            Statement::Words(words) => 2 * words.len() as u32,
+            Statement::Sprite(rows) => rows.len() as u32,
+        }
+    }
+}
+
+/// instruction operand
+enum Operand {
+    V(u8),     // register Vx
+    I,         // register I
+    IndirectI, // [I], memory at I
+    DT,        // delay timer
+    ST,        // sound timer
+    K,         // key press
+    F,         // digit sprite location
+    B,         // BCD representation
+    Value(i64),
+}
+
+/// two-pass assembler of Cowgod's mnemonics, the syntax printed by the disassembler.
+///
+/// besides the instructions it understands:
+/// * `name:` labels and `NAME EQU expr` constants
+/// * `DB expr, ...` bytes and `DW expr, ...` big-endian words
+/// * `SPRITE ####.... #..#....` sprite rows, `#`, `X` or `1` for a lit pixel
+/// * `INCLUDE "file"` relative to the including file
+/// * numbers as `42`, `0x2A`, `#2A`, `0b101010` or `%101010`, added or subtracted with `+` and `-`
+pub struct Assembler {
+    symbols: HashMap<String, i64>, // labels and constants
+    statements: Vec<(Location, Statement)>,
+    address: u32,
+}
+
+impl Assembler {
+    /// create an assembler instance
+    pub fn new() -> Assembler {
+        Assembler {
+            symbols: HashMap::new(),
+            statements: vec![],
+            address: PROGRAM_START as u32,
+        }
+    }
+
+    /// read a source file, the first pass
+    pub fn load(&mut self, path: &Path) -> Result<(), AssembleError> {
+        self.load_file(path, 0)
+    }
+
+    /// read source text, the first pass
+    pub fn load_source(&mut self, source: &str, file: &Path) -> Result<(), AssembleError> {
+        self.load_text(source, file, 0)
+    }
+
+    fn load_file(&mut self, path: &Path, depth: usize) -> Result<(), AssembleError> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| AssembleError::ReadFileError(path.to_path_buf(), e))?;
+        self.load_text(&source, path, depth)
+    }
+
+    fn load_text(&mut self, source: &str, file: &Path, depth: usize) -> Result<(), AssembleError> {
+        for (i, line) in source.lines().enumerate() {
+            let location = Location {
+                file: file.to_path_buf(),
+                line: i + 1,
+            };
+            self.parse_line(line, &location, depth)?;
+        }
+        Ok(())
+    }
+
+    /// parse a line, define its labels and constants and lay out its statement
+    fn parse_line(
+        &mut self,
+        line: &str,
+        location: &Location,
+        depth: usize,
+    ) -> Result<(), AssembleError> {
+        let mut text = line.split(';').next().unwrap_or("").trim();
+
+        // leading labels
+        while let Some(colon) = text.find(':') {
+            let name = text[..colon].trim();
+            if !is_identifier(name) {
+                break;
+            }
+            self.define(name, self.address as i64, location)?;
+            text = text[colon + 1..].trim();
+        }
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let (word, rest) = split_word(text);
+        let (second, value) = split_word(rest);
+        if second.eq_ignore_ascii_case("EQU") {
+            if !is_identifier(word) {
+                return Err(location.error(format!("invalid constant name `{}`", word)));
+            }
+            let value = self.evaluate(value).map_err(|e| location.error(e))?;
+            return self.define(word, value, location);
+        }
+
+        let statement = match word.to_ascii_uppercase().as_str() {
+            "INCLUDE" => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(location.error(String::from("too many nested includes")));
+                }
+                let name = rest.trim_matches('"');
+                let path = location
+                    .file
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(name);
+                return self.load_file(&path, depth + 1).map_err(|e| match e {
+                    AssembleError::ReadFileError(..) => {
+                        location.error(format!("can not include `{}`", path.display()))
+                    }
+                    e => e,
+                });
+            }
+            "DB" => Statement::Bytes(split_operands(rest)),
+            "DW" => Statement::Words(split_operands(rest)),
+            "SPRITE" => Statement::Sprite(
+                rest.split_whitespace()
+                    .map(sprite_row)
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| location.error(e))?,
+            ),
+            mnemonic => Statement::Instruction(mnemonic.to_string(), split_operands(rest)),
+        };
+
+        let size = statement.size();
+        if self.address + size > MEMORY_SIZE as u32 {
+            return Err(location.error(String::from("program does not fit in memory")));
+        }
+        self.statements.push((location.clone(), statement));
+        self.address += size;
+        Ok(())
+    }
+
+    /// define a label or a constant
+    fn define(&mut self, name: &str, value: i64, location: &Location) -> Result<(), AssembleError> {
+        if is_reserved(name) {
+            return Err(location.error(format!("`{}` is a reserved name", name)));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(location.error(format!("`{}` is defined more than once", name)));
+        }
+        Ok(())
+    }
+
+    /// resolve the operands of every statement and return the ROM, the second pass
+    pub fn assemble(&self) -> Result<Vec<u8>, AssembleError> {
+        let mut rom = vec![];
+        for (location, statement) in self.statements.iter() {
+            match statement {
+                Statement::Instruction(mnemonic, operands) => {
+                    let op = self
+                        .instruction(mnemonic, operands)
+                        .map_err(|e| location.error(e))?;
+                    rom.extend_from_slice(&op.encode().get_opcode().to_be_bytes());
+                }
+                Statement::Bytes(bytes) => {
+                    for byte in bytes {
+                        let value = self.evaluate(byte).map_err(|e| location.error(e))?;
+                        rom.push(to_byte(value).map_err(|e| location.error(e))?);
+                    }
+                }
+                Statement::Words(words) => {
+                    for word in words {
+                        let value = self.evaluate(word).map_err(|e| location.error(e))?;
+                        if !(-0x8000..=0xFFFF).contains(&value) {
+                            return Err(location.error(format!("{} does not fit in a word", value)));
+                        }
+                        rom.extend_from_slice(&(value as u16).to_be_bytes());
+                    }
+                }
+                Statement::Sprite(rows) => rom.extend_from_slice(rows),
+            }
+        }
+        Ok(rom)
+    }
+
+    /// return the labels and constants with their values
+    pub fn symbols(&self) -> &HashMap<String, i64> {
+        &self.symbols
+    }
+
+    /// build the instruction from its mnemonic and operands
+    fn instruction(&self, mnemonic: &str, operands: &[String]) -> Result<Op, String> {
+        use Operand::*;
+
+        let operands = operands
+            .iter()
+            .map(|operand| self.operand(operand))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let op = match (mnemonic, operands.as_slice()) {
+            ("CLS", []) => Op::CLS,
+            ("RET", []) => Op::RET,
+            ("SYS", [Value(a)]) => Op::SYS {
+                address: to_address(*a)?,
+            },
+            ("JP", [Value(a)]) => Op::JP {
+                address: to_address(*a)?,
+            },
+            ("JP", [V(0), Value(a)]) => Op::JPV {
+                address: to_address(*a)?,
+            },
+            ("CALL", [Value(a)]) => Op::CALL {
+                address: to_address(*a)?,
+            },
+            ("SE", [V(reg), Value(b)]) => Op::SE {
+                reg: *reg,
+                byte: to_byte(*b)?,
+            },
+            ("SE", [V(reg_x), V(reg_y)]) => Op::SEV {
+                reg_x: *reg_x,
+                reg_y: *reg_y,
+            },
+            ("SNE", [V(reg), Value(b)]) => Op::SNE2 {
+                reg: *reg,
+                byte: to_byte(*b)?,
+            },
+            ("SNE", [V(reg_x), V(reg_y)]) => Op::SNE {
+                reg_x: *reg_x,
+                reg_y: *reg_y,
+            },
+            ("LD", [V(reg), Value(b)]) => Op::LD {
+                reg: *reg,
+                byte: to_byte(*b)?,
+            },
+            ("LD", [V(reg_x), V(reg_y)]) => Op::LDR {
+                reg_x: *reg_x,
+                reg_y: *reg_y,
+            },
+            ("LD", [I, Value(a)]) => Op::LDA {
+                address: to_address(*a)?,
+            },
+            ("LD", [V(reg), DT]) => Op::LDT { reg: *reg },
+            ("LD", [V(reg), K]) => Op::LDK { reg: *reg },
+            ("LD", [DT, V(reg)]) => Op::LDF { reg: *reg },
+            ("LD", [ST, V(reg)]) => Op::LDS { reg: *reg },
+            ("LD", [F, V(reg)]) => Op::LDX { reg: *reg },
+            ("LD", [B, V(reg)]) => Op::LDB { reg: *reg },
+            ("LD", [IndirectI, V(reg)]) => Op::LDI { reg: *reg },
+            ("LD", [V(reg), IndirectI]) => Op::LDJ { reg: *reg },
+            ("ADD", [V(reg), Value(b)]) => Op::ADD {
+                reg: *reg,
+                byte: to_byte(*b)?,
+            },
+            ("ADD", [V(reg_x), V(reg_y)]) => Op::ADD2 {
+                reg_x: *reg_x,
+                reg_y: *reg_y,
+            },
+            ("ADD", [I, V(reg)]) => Op::ADDI { reg: *reg },
+            ("OR", [V(reg_x), V(reg_y)]) => Op::OR {
+                reg_x: *reg_x,
+                reg_y: *reg_y,
+            },
+            ("AND", [V(reg_x), V(reg_y)]) => Op::AND {
+                reg_x: *reg_x,
+                reg_y: *reg_y,
+            },
+            ("XOR", [V(reg_x), V(reg_y)]) => Op::XOR {
+                reg_x: *reg_x,
+                reg_y: *reg_y,
+            },
+            ("SUB", [V(reg_x), V(reg_y)]) => Op::SUB {
+                reg_x: *reg_x,
+                reg_y: *reg_y,
+            },
+            ("SUBN", [V(reg_x), V(reg_y)]) => Op::SUBN {
+                reg_x: *reg_x,
+                reg_y: *reg_y,
+            },
+            ("SHR", [V(reg)]) => Op::SHR {
+                reg_x: *reg,
+                reg_y: *reg,
+            },
+            ("SHR", [V(reg_x), V(reg_y)]) => Op::SHR {
+                reg_x: *reg_x,
+                reg_y: *reg_y,
+            },
+            ("SHL", [V(reg)]) => Op::SHL {
+                reg_x: *reg,
+                reg_y: *reg,
+            },
+            ("SHL", [V(reg_x), V(reg_y)]) => Op::SHL {
+                reg_x: *reg_x,
+                reg_y: *reg_y,
+            },
+            ("RND", [V(reg), Value(b)]) => Op::RND {
+                reg: *reg,
+                byte: to_byte(*b)?,
+            },
+            ("DRW", [V(reg_x), V(reg_y), Value(n)]) => Op::DRW {
+                reg_x: *reg_x,
+                reg_y: *reg_y,
+                n: to_nibble(*n)?,
+            },
+            ("SKP", [V(reg)]) => Op::SKP { reg: *reg },
+            ("SKNP", [V(reg)]) => Op::SKNP { reg: *reg },
+            (
+                "CLS" | "RET" | "SYS" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR" | "AND"
+                | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP",
+                _,
+            ) => return Err(format!("invalid operands for {}", mnemonic)),
+            _ => return Err(format!("unknown instruction `{}`", mnemonic)),
+        };
+        Ok(op)
+    }
+
+    /// parse an operand
+    fn operand(&self, text: &str) -> Result<Operand, String> {
+        let upper = text.to_ascii_uppercase();
+        let operand = match upper.as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::DT,
+            "ST" => Operand::ST,
+            "K" => Operand::K,
+            "F" => Operand::F,
+            "B" => Operand::B,
+            _ => match register(&upper) {
+                Some(reg) => Operand::V(reg),
+                None => Operand::Value(self.evaluate(text)?),
+            },
+        };
+        Ok(operand)
+    }
+
+    /// evaluate an expression of numbers and symbols joined by `+` and `-`
+    fn evaluate(&self, expression: &str) -> Result<i64, String> {
+        let expression = expression.trim();
+        if expression.is_empty() {
+            return Err(String::from("missing value"));
+        }
+
+        let mut total = 0i64;
+        let mut sign = 1i64;
+        let mut term = String::new();
+        for c in expression.chars().chain(std::iter::once('+')) {
+            if c == '+' || c == '-' {
+                let term = std::mem::take(&mut term);
+                let term = term.trim();
+                if term.is_empty() {
+                    // a unary sign
+                    sign = if c == '-' { -sign } else { sign };
+                    continue;
+                }
+                total += sign * self.value(term)?;
+                sign = if c == '-' { -1 } else { 1 };
+            } else {
+                term.push(c);
+            }
+        }
+        Ok(total)
+    }
+
+    /// return the value of a number or a symbol
+    fn value(&self, term: &str) -> Result<i64, String> {
+        let number = if let Some(hex) = term.strip_prefix("0x").or_else(|| term.strip_prefix('#')) {
+            i64::from_str_radix(hex, 16)
+        } else if let Some(bin) = term.strip_prefix("0b").or_else(|| term.strip_prefix('%')) {
+            i64::from_str_radix(bin, 2)
+        } else if term.starts_with(|c: char| c.is_ascii_digit()) {
+            term.parse::<i64>()
+        } else {
+            return self
+                .symbols
+                .get(term)
+                .copied()
+                .ok_or_else(|| format!("undefined symbol `{}`", term));
+        };
+        number.map_err(|_| format!("invalid number `{}`", term))
+    }
+}
+
+impl Default for Assembler {
+    fn default() -> Assembler {
+        Assembler::new()
+    }
+}
+
+/// assemble a source file into a ROM
+pub fn assemble(path: &Path) -> Result<Vec<u8>, AssembleError> {
+    let mut assembler = Assembler::new();
+    assembler.load(path)?;
+    assembler.assemble()
+}
+
+/// split the first word from the rest of the text
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    }
+}
+
+/// split comma separated operands
+fn split_operands(text: &str) -> Vec<String> {
+    if text.trim().is_empty() {
+        return vec![];
+    }
+    text.split(',').map(|s| s.trim().to_string()).collect()
+}
+
+/// check whether the name can be used as a label or a constant
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// check whether the name is a register or an operand keyword
+fn is_reserved(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    register(&upper).is_some() || matches!(upper.as_str(), "I" | "DT" | "ST" | "K" | "F" | "B")
+}
+
+/// parse a register name V0 to VF
+fn register(upper: &str) -> Option<u8> {
+    let digit = upper.strip_prefix('V')?;
+    if digit.len() == 1 {
+        u8::from_str_radix(digit, 16).ok()
+    } else {
+        None
+    }
+}
+
+/// parse a sprite row of up to 8 pixels
+fn sprite_row(row: &str) -> Result<u8, String> {
+    if row.is_empty() || row.len() > 8 {
+        return Err(format!("sprite row `{}` must have 1 to 8 pixels", row));
+    }
+    row.chars()
+        .enumerate()
+        .try_fold(0u8, |byte, (i, c)| match c {
+            '#' | 'X' | 'x' | '1' => Ok(byte | 0x80 >> i),
+            '.' | '_' | '-' | '0' => Ok(byte),
+            _ => Err(format!("invalid sprite pixel `{}`", c)),
+        })
+}
+
+fn to_address(value: i64) -> Result<u16, String> {
+    if (0..0x1000).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(format!("address {:#X} is out of range", value))
+    }
+}
+
+fn to_byte(value: i64) -> Result<u8, String> {
+    if (-0x80..=0xFF).contains(&value) {
+        Ok(value as u8)
+    } else {
+        Err(format!("{} does not fit in a byte", value))
+    }
+}
+
+fn to_nibble(value: i64) -> Result<u8, String> {
+    if (0..=0xF).contains(&value) {
+        Ok(value as u8)
+    } else {
+        Err(format!("{} does not fit in a nibble", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::{Disassembler, Syntax};
+
+    #[test]
+    fn disassembled_games_assemble_to_the_same_rom() {
+        let games = Path::new(env!("CARGO_MANIFEST_DIR")).join("c8games");
+        for entry in fs::read_dir(games).unwrap() {
+            let path = entry.unwrap().path();
+            let rom = fs::read(&path).unwrap();
+            let listing = Disassembler::new(&rom).listing(Syntax::Cowgod);
+
+            let mut assembler = Assembler::new();
+            assembler.load_source(&listing, &path).unwrap();
+            assert_eq!(assembler.assemble().unwrap(), rom, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn words_which_do_not_encode_back_are_disassembled_as_data() {
+        // 5121 and 9235 would assemble back as 5120 and 9230
+        let rom = [0x60, 0x01, 0x51, 0x21, 0x92, 0x35, 0x12, 0x04];
+        let listing = Disassembler::new(&rom).listing(Syntax::Cowgod);
+        assert!(listing.contains("DB 0x51"), "{}", listing);
+
+        let mut assembler = Assembler::new();
+        assembler
+            .load_source(&listing, Path::new("test.asm"))
+            .unwrap();
+        assert_eq!(assembler.assemble().unwrap(), rom);
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+/// the pitch of the beep in Hz
+pub const TONE: f32 = 440.0;
+
+/// the volume of the beep, from 0 to 1
+pub const VOLUME: f32 = 0.25;
+
+/// the sample rate of the captured audio
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// a square wave generator, the sound of the beeper
+pub struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+}
+
+impl SquareWave {
+    /// create the generator of the beep at the sample rate
+    pub fn new(sample_rate: u32) -> SquareWave {
+        SquareWave {
+            phase_inc: TONE / sample_rate as f32,
+            phase: 0.0,
+            volume: VOLUME,
+        }
+    }
+
+    /// return the next sample
+    pub fn sample(&mut self) -> f32 {
+        let sample = if self.phase <= 0.5 {
+            self.volume
+        } else {
+            -self.volume
+        };
+        self.phase = (self.phase + self.phase_inc) % 1.0;
+        sample
+    }
+}
+
+/// generate the beeper output a sound timer tick at a time, without an audio device
+pub struct Beeper {
+    wave: SquareWave,
+    sample_rate: u32,
+    tick_rate: u16, // the sound timer ticks per second
+    ticks: u64,     // the number of ticks generated
+}
+
+impl Beeper {
+    /// create a beeper of the sound timer frequency
+    pub fn new(sample_rate: u32, tick_rate: u16) -> Beeper {
+        Beeper {
+            wave: SquareWave::new(sample_rate),
+            sample_rate,
+            tick_rate: tick_rate.max(1),
+            ticks: 0,
+        }
+    }
+
+    /// return the samples of the next tick, the beep or silence.
+    /// the number of samples of a tick varies so that the total follows the emulated time, e.g. 735 at 44100 Hz and 60 ticks
+    pub fn tick(&mut self, beep: bool) -> Vec<f32> {
+        let start = self.ticks * self.sample_rate as u64 / self.tick_rate as u64;
+        self.ticks += 1;
+        let end = self.ticks * self.sample_rate as u64 / self.tick_rate as u64;
+        (start..end)
+            .map(|_| if beep { self.wave.sample() } else { 0.0 })
+            .collect()
+    }
+}
+
+/// capture the beeper output to a 16-bit mono WAV file
+pub struct AudioRecorder {
+    path: PathBuf,
+    beeper: Beeper,
+    writer: WavWriter<BufWriter<File>>,
+    ticks: u64, // the number of ticks captured
+    beeps: u64, // the number of ticks the beep sounded
+}
+
+impl AudioRecorder {
+    /// create the WAV file of a beeper ticking at the sound timer frequency
+    pub fn create(path: &Path, tick_rate: u16) -> io::Result<AudioRecorder> {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let writer = WavWriter::create(path, spec).map_err(io::Error::other)?;
+        Ok(AudioRecorder {
+            path: path.to_path_buf(),
+            beeper: Beeper::new(SAMPLE_RATE, tick_rate),
+            writer,
+            ticks: 0,
+            beeps: 0,
+        })
+    }
+
+    /// return the path of the WAV file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// return the number of ticks captured
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// return the number of ticks the beep sounded
+    pub fn beeps(&self) -> u64 {
+        self.beeps
+    }
+
+    /// capture the next tick of the sound timer
+    pub fn tick(&mut self, beep: bool) -> io::Result<()> {
+        self.ticks += 1;
+        self.beeps += beep as u64;
+        for sample in self.beeper.tick(beep) {
+            self.writer
+                .write_sample((sample * i16::MAX as f32) as i16)
+                .map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    /// write the length of the audio and close the file
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.finalize().map_err(io::Error::other)
+    }
+}
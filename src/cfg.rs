@@ -0,0 +1,681 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::chip8::memory::{MAX_PROGRAM_SIZE, PROGRAM_START};
+use crate::chip8::operation::{Op, OpCode};
+use crate::symbols::Symbols;
+
+/// output format of the control-flow graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,  // Graphviz DOT
+    Json, // JSON
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<GraphFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "dot" => Ok(GraphFormat::Dot),
+            "json" => Ok(GraphFormat::Json),
+            _ => Err(format!("unknown graph format: {}", s)),
+        }
+    }
+}
+
+/// how control gets from one block to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Next, // the next instruction, including a skip which is not taken
+    Skip, // a skip which is taken
+    Jump, // JP
+    Call, // CALL, the block also continues with the return address
+}
+
+impl EdgeKind {
+    /// return the name of the edge kind
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Next => "next",
+            EdgeKind::Skip => "skip",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Call => "call",
+        }
+    }
+}
+
+/// an edge of the graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub target: u16,
+    pub kind: EdgeKind,
+}
+
+/// a sequence of instructions which is only entered at its start
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub start: u16,
+    pub end: u16,                     // the address after the last instruction
+    pub instructions: Vec<(u16, Op)>, // the instructions with their addresses
+    pub successors: Vec<Edge>,        // the blocks control can continue with
+    pub unresolved: Option<u16>,      // the base address of a BNNN jump, its target depends on V0
+}
+
+/// an instruction which writes to memory, LD B, Vx or LD [I], Vx
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub address: u16,               // the address of the instruction
+    pub target: Option<(u16, u16)>, // the first and last address written, None if I is not known
+    pub overlaps_code: bool, // the written range contains instructions, the program modifies itself
+}
+
+/// what is known about the register I at an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Index {
+    Known(u16),
+    Unknown,
+}
+
+impl Index {
+    /// combine the values coming from two paths
+    fn join(self, other: Index) -> Index {
+        if self == other {
+            self
+        } else {
+            Index::Unknown
+        }
+    }
+}
+
+/// control-flow graph of a ROM loaded at 0x200
+pub struct Cfg<'a> {
+    rom: &'a [u8],
+    code: BTreeSet<u16>,          // addresses where a reached instruction starts
+    blocks: BTreeMap<u16, Block>, // the basic blocks by their start
+    subroutines: BTreeSet<u16>,   // the CALL targets
+    branches: BTreeSet<u16>,      // the JP targets and the BNNN base addresses
+    data: BTreeSet<u16>,          // the addresses loaded into I
+    writes: Vec<MemoryWrite>,     // the instructions writing to memory
+}
+
+impl<'a> Cfg<'a> {
+    /// build the graph of everything reached from the program start
+    pub fn new(rom: &'a [u8]) -> Cfg<'a> {
+        Cfg::with_entries(rom, &[])
+    }
+
+    /// build the graph of everything reached from the program start and other entry points,
+    /// e.g. the instructions executed in a run. the bytes which do not fit in memory are left out
+    pub fn with_entries(rom: &'a [u8], entries: &[u16]) -> Cfg<'a> {
+        let mut cfg = Cfg {
+            rom: &rom[..rom.len().min(MAX_PROGRAM_SIZE)],
+            code: BTreeSet::new(),
+            blocks: BTreeMap::new(),
+            subroutines: BTreeSet::new(),
+            branches: BTreeSet::new(),
+            data: BTreeSet::new(),
+            writes: vec![],
+        };
+        cfg.trace(entries);
+        cfg.build_blocks(entries);
+        cfg.find_writes(entries);
+        cfg
+    }
+
+    /// return the word at address as it is in the ROM, None if it is outside the ROM
+    pub fn opcode(&self, address: u16) -> Option<u16> {
+        let offset = address.checked_sub(PROGRAM_START)? as usize;
+        if offset + 2 > self.rom.len() {
+            return None;
+        }
+        Some((self.rom[offset] as u16) << 8 | self.rom[offset + 1] as u16)
+    }
+
+    /// decode the instruction at address, return None if it is outside the ROM or invalid.
+    /// a word which does not encode back to the same bytes is invalid too, so that it is listed as data
+    /// and the listing assembles back to the ROM
+    pub fn decode(&self, address: u16) -> Option<Op> {
+        let opcode = self.opcode(address)?;
+        Op::decode(&OpCode::new(opcode)).filter(|op| op.encode().get_opcode() == opcode)
+    }
+
+    /// check whether the address is inside the ROM
+    pub fn in_rom(&self, address: u16) -> bool {
+        address >= PROGRAM_START && ((address - PROGRAM_START) as usize) < self.rom.len()
+    }
+
+    /// check whether a reached instruction starts at the address
+    pub fn is_code(&self, address: u16) -> bool {
+        self.code.contains(&address)
+    }
+
+    /// return the number of reached instructions
+    pub fn instruction_count(&self) -> usize {
+        self.code.len()
+    }
+
+    /// return the basic blocks in address order
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    /// return the block containing the address
+    pub fn block_at(&self, address: u16) -> Option<&Block> {
+        self.blocks
+            .range(..=address)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| address < block.end)
+    }
+
+    /// return the CALL targets inside the ROM
+    pub fn subroutines(&self) -> &BTreeSet<u16> {
+        &self.subroutines
+    }
+
+    /// return the JP targets and BNNN base addresses inside the ROM
+    pub fn branches(&self) -> &BTreeSet<u16> {
+        &self.branches
+    }
+
+    /// return the addresses inside the ROM loaded into I
+    pub fn data(&self) -> &BTreeSet<u16> {
+        &self.data
+    }
+
+    /// return the instructions writing to memory
+    pub fn writes(&self) -> &[MemoryWrite] {
+        &self.writes
+    }
+
+    /// return the next instructions control can continue with after the instruction at address
+    fn successors(address: u16, op: Op) -> Vec<Edge> {
+        let next = address.wrapping_add(2);
+        let edge = |target, kind| Edge { target, kind };
+        match op {
+            Op::JP { address: target } => vec![edge(target, EdgeKind::Jump)],
+            Op::CALL { address: target } => {
+                vec![edge(target, EdgeKind::Call), edge(next, EdgeKind::Next)]
+            }
+            Op::RET | Op::JPV { .. } => vec![],
+            Op::SE { .. }
+            | Op::SNE2 { .. }
+            | Op::SEV { .. }
+            | Op::SNE { .. }
+            | Op::SKP { .. }
+            | Op::SKNP { .. } => vec![
+                edge(next, EdgeKind::Next),
+                edge(next.wrapping_add(2), EdgeKind::Skip),
+            ],
+            _ => vec![edge(next, EdgeKind::Next)],
+        }
+    }
+
+    /// follow every path from the entry points, marking the instructions reached as code
+    fn trace(&mut self, entries: &[u16]) {
+        let mut pending = vec![PROGRAM_START];
+        pending.extend(entries.iter().copied());
+        while let Some(address) = pending.pop() {
+            if self.code.contains(&address) {
+                continue;
+            }
+            let op = match self.decode(address) {
+                Some(op) => op,
+                None => continue,
+            };
+            self.code.insert(address);
+
+            match op {
+                Op::JP { address: target } if self.in_rom(target) => {
+                    self.branches.insert(target);
+                }
+                Op::CALL { address: target } if self.in_rom(target) => {
+                    self.subroutines.insert(target);
+                }
+                // the target depends on V0, so the path can not be followed
+                Op::JPV { address: target } if self.in_rom(target) => {
+                    self.branches.insert(target);
+                }
+                Op::LDA { address: target } if self.in_rom(target) => {
+                    self.data.insert(target);
+                }
+                _ => {}
+            }
+            pending.extend(Cfg::successors(address, op).iter().map(|edge| edge.target));
+        }
+    }
+
+    /// split the reached instructions into basic blocks
+    fn build_blocks(&mut self, entries: &[u16]) {
+        // a block starts at an entry point, a target of an edge, or after an instruction with other successors
+        let mut leaders: BTreeSet<u16> = entries.iter().copied().collect();
+        leaders.insert(PROGRAM_START);
+        for &address in self.code.iter() {
+            let op = self.decode(address).unwrap();
+            let successors = Cfg::successors(address, op);
+            let plain = matches!(
+                successors.as_slice(),
+                [Edge {
+                    kind: EdgeKind::Next,
+                    ..
+                }]
+            );
+            if !plain {
+                leaders.extend(successors.iter().map(|edge| edge.target));
+                leaders.insert(address.wrapping_add(2));
+            }
+        }
+        // a block also starts where the previous instruction was not reached
+        for &address in self.code.iter() {
+            if address < 2 || !self.code.contains(&(address - 2)) {
+                leaders.insert(address);
+            }
+        }
+        leaders.retain(|address| self.code.contains(address));
+
+        for &start in leaders.iter() {
+            let mut block = Block {
+                start,
+                end: start,
+                instructions: vec![],
+                successors: vec![],
+                unresolved: None,
+            };
+            let mut address = start;
+            loop {
+                let op = self.decode(address).unwrap();
+                block.instructions.push((address, op));
+                block.end = address.wrapping_add(2);
+                let successors = Cfg::successors(address, op);
+                let plain = matches!(
+                    successors.as_slice(),
+                    [Edge {
+                        kind: EdgeKind::Next,
+                        ..
+                    }]
+                );
+                if let Op::JPV { address: base } = op {
+                    block.unresolved = Some(base);
+                }
+                if !plain || leaders.contains(&block.end) || !self.code.contains(&block.end) {
+                    block.successors = successors;
+                    break;
+                }
+                address = block.end;
+            }
+            self.blocks.insert(start, block);
+        }
+    }
+
+    /// find the instructions writing to memory, following the value of I through the graph
+    fn find_writes(&mut self, entries: &[u16]) {
+        // the value of I at the start of each block, None until a path reaches it
+        let mut states: BTreeMap<u16, Option<Index>> =
+            self.blocks.keys().map(|&start| (start, None)).collect();
+
+        // nothing is known where the program starts, where subroutines are entered from different callers,
+        // and where blocks are only reached at run time
+        let targets: BTreeSet<u16> = self
+            .blocks
+            .values()
+            .flat_map(|block| block.successors.iter())
+            .filter(|edge| edge.kind != EdgeKind::Call)
+            .map(|edge| edge.target)
+            .collect();
+        let mut pending: Vec<u16> = self
+            .blocks
+            .keys()
+            .copied()
+            .filter(|start| {
+                *start == PROGRAM_START
+                    || entries.contains(start)
+                    || self.subroutines.contains(start)
+                    || !targets.contains(start)
+            })
+            .collect();
+        pending.iter().for_each(|start| {
+            states.insert(*start, Some(Index::Unknown));
+        });
+
+        while let Some(start) = pending.pop() {
+            let block = &self.blocks[&start];
+            let mut index = states[&start].unwrap_or(Index::Unknown);
+            for &(_, op) in block.instructions.iter() {
+                index = Cfg::transfer(index, op);
+            }
+            // a subroutine may change I before it returns
+            if matches!(block.instructions.last(), Some((_, Op::CALL { .. }))) {
+                index = Index::Unknown;
+            }
+            for edge in block.successors.iter().filter(|e| e.kind != EdgeKind::Call) {
+                if let Some(state) = states.get_mut(&edge.target) {
+                    let joined = state.map_or(index, |current| current.join(index));
+                    if *state != Some(joined) {
+                        *state = Some(joined);
+                        pending.push(edge.target);
+                    }
+                }
+            }
+        }
+
+        let mut writes = vec![];
+        for block in self.blocks.values() {
+            let mut index = states[&block.start].unwrap_or(Index::Unknown);
+            for &(address, op) in block.instructions.iter() {
+                let len = match op {
+                    Op::LDB { .. } => Some(3),
+                    Op::LDI { reg } => Some(reg as u16 + 1),
+                    _ => None,
+                };
+                if let Some(len) = len {
+                    let target = match index {
+                        Index::Known(i) => Some((i, i.saturating_add(len - 1))),
+                        Index::Unknown => None,
+                    };
+                    let overlaps_code = target.is_some_and(|(first, last)| {
+                        // an instruction starting one byte before the range overlaps it too
+                        self.code
+                            .range(first.saturating_sub(1)..=last)
+                            .next()
+                            .is_some()
+                    });
+                    writes.push(MemoryWrite {
+                        address,
+                        target,
+                        overlaps_code,
+                    });
+                }
+                index = Cfg::transfer(index, op);
+            }
+        }
+        self.writes = writes;
+    }
+
+    /// return what is known about I after the instruction
+    fn transfer(index: Index, op: Op) -> Index {
+        match op {
+            Op::LDA { address } => Index::Known(address),
+            // ADD I depends on a register, LD F on a digit, LD [I] and LD Vx, [I] on the load/store quirk
+            Op::ADDI { .. } | Op::LDX { .. } | Op::LDI { .. } | Op::LDJ { .. } => Index::Unknown,
+            _ => index,
+        }
+    }
+
+    /// return the label of a block for the graph outputs
+    fn block_name(&self, address: u16, symbols: &Symbols) -> String {
+        match symbols.name(address) {
+            Some(name) => name.to_string(),
+            None if address == PROGRAM_START => String::from("main"),
+            None if self.subroutines.contains(&address) => format!("sub_{:03X}", address),
+            None => format!("label_{:03X}", address),
+        }
+    }
+
+    /// return the graph in Graphviz DOT format, subroutine entries are drawn bold and
+    /// instructions modifying code red
+    pub fn dot(&self, symbols: &Symbols) -> String {
+        let modifying: BTreeSet<u16> = self
+            .writes
+            .iter()
+            .filter(|w| w.overlaps_code)
+            .map(|w| w.address)
+            .collect();
+
+        let mut dot = String::new();
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box fontname=\"monospace\"];").unwrap();
+        for block in self.blocks.values() {
+            let mut label = format!("{}:\\l", self.block_name(block.start, symbols));
+            for &(address, op) in block.instructions.iter() {
+                write!(label, "{:03X}: {}\\l", address, symbols.instruction(op)).unwrap();
+            }
+            let mut attributes = vec![];
+            if block.start == PROGRAM_START || self.subroutines.contains(&block.start) {
+                attributes.push(String::from("style=bold"));
+            }
+            if block
+                .instructions
+                .iter()
+                .any(|(a, _)| modifying.contains(a))
+            {
+                attributes.push(String::from("color=red"));
+            }
+            writeln!(
+                dot,
+                "    b{:03X} [label=\"{}\"{}];",
+                block.start,
+                label.replace('"', "\\\""),
+                attributes
+                    .iter()
+                    .map(|a| format!(" {}", a))
+                    .collect::<String>()
+            )
+            .unwrap();
+        }
+        for block in self.blocks.values() {
+            for edge in block.successors.iter() {
+                if self.blocks.contains_key(&edge.target) {
+                    writeln!(
+                        dot,
+                        "    b{:03X} -> b{:03X} [label=\"{}\"{}];",
+                        block.start,
+                        edge.target,
+                        edge.kind.name(),
+                        if edge.kind == EdgeKind::Call {
+                            " style=dashed"
+                        } else {
+                            ""
+                        }
+                    )
+                    .unwrap();
+                }
+            }
+            if let Some(base) = block.unresolved {
+                writeln!(
+                    dot,
+                    "    u{:03X} [label=\"0x{:03X} + V0\" shape=ellipse style=dotted];",
+                    block.start, base
+                )
+                .unwrap();
+                writeln!(
+                    dot,
+                    "    b{:03X} -> u{:03X} [label=\"unresolved\" style=dotted];",
+                    block.start, block.start
+                )
+                .unwrap();
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    /// return the graph as JSON, addresses are numbers
+    pub fn json(&self, symbols: &Symbols) -> String {
+        let mut json = String::new();
+        writeln!(json, "{{").unwrap();
+        writeln!(json, "  \"entry\": {},", PROGRAM_START).unwrap();
+
+        writeln!(json, "  \"blocks\": [").unwrap();
+        for (i, block) in self.blocks.values().enumerate() {
+            let instructions: Vec<String> = block
+                .instructions
+                .iter()
+                .map(|&(address, op)| {
+                    format!(
+                        "{{\"address\": {}, \"opcode\": {}, \"text\": {}}}",
+                        address,
+                        self.opcode(address).unwrap(),
+                        quote(&op.to_string())
+                    )
+                })
+                .collect();
+            let successors: Vec<String> = block
+                .successors
+                .iter()
+                .map(|edge| {
+                    format!(
+                        "{{\"target\": {}, \"kind\": \"{}\"}}",
+                        edge.target,
+                        edge.kind.name()
+                    )
+                })
+                .collect();
+            writeln!(json, "    {{").unwrap();
+            writeln!(json, "      \"start\": {},", block.start).unwrap();
+            writeln!(json, "      \"end\": {},", block.end).unwrap();
+            writeln!(
+                json,
+                "      \"name\": {},",
+                quote(&self.block_name(block.start, symbols))
+            )
+            .unwrap();
+            writeln!(
+                json,
+                "      \"instructions\": [{}],",
+                instructions.join(", ")
+            )
+            .unwrap();
+            writeln!(json, "      \"successors\": [{}],", successors.join(", ")).unwrap();
+            writeln!(
+                json,
+                "      \"unresolved\": {}",
+                block
+                    .unresolved
+                    .map_or(String::from("null"), |base| base.to_string())
+            )
+            .unwrap();
+            writeln!(
+                json,
+                "    }}{}",
+                if i + 1 < self.blocks.len() { "," } else { "" }
+            )
+            .unwrap();
+        }
+        writeln!(json, "  ],").unwrap();
+
+        let list = |set: &BTreeSet<u16>| {
+            set.iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        writeln!(json, "  \"subroutines\": [{}],", list(&self.subroutines)).unwrap();
+        writeln!(json, "  \"data\": [{}],", list(&self.data)).unwrap();
+
+        let writes: Vec<String> = self
+            .writes
+            .iter()
+            .map(|write| {
+                let (first, last) = match write.target {
+                    Some((first, last)) => (first.to_string(), last.to_string()),
+                    None => (String::from("null"), String::from("null")),
+                };
+                format!(
+                    "    {{\"address\": {}, \"first\": {}, \"last\": {}, \"overlaps_code\": {}}}",
+                    write.address, first, last, write.overlaps_code
+                )
+            })
+            .collect();
+        if writes.is_empty() {
+            writeln!(json, "  \"writes\": []").unwrap();
+        } else {
+            writeln!(json, "  \"writes\": [\n{}\n  ]", writes.join(",\n")).unwrap();
+        }
+        writeln!(json, "}}").unwrap();
+        json
+    }
+}
+
+/// quote a string for JSON
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_continue_at_the_next_and_the_skipped_instruction() {
+        let rom = [
+            0x30, 0x00, // 0x200: SE V0, 0x00
+            0x60, 0x01, // 0x202: LD V0, 0x01
+            0x12, 0x04, // 0x204: JP 0x204
+        ];
+        let cfg = Cfg::new(&rom);
+        let starts: Vec<u16> = cfg.blocks().map(|block| block.start).collect();
+        assert_eq!(starts, [0x200, 0x202, 0x204]);
+        let edge = |target, kind| Edge { target, kind };
+        assert_eq!(
+            cfg.block_at(0x200).unwrap().successors,
+            [edge(0x202, EdgeKind::Next), edge(0x204, EdgeKind::Skip)]
+        );
+        assert_eq!(
+            cfg.block_at(0x202).unwrap().successors,
+            [edge(0x204, EdgeKind::Next)]
+        );
+        assert_eq!(
+            cfg.block_at(0x204).unwrap().successors,
+            [edge(0x204, EdgeKind::Jump)]
+        );
+    }
+
+    #[test]
+    fn bnnn_jumps_are_unresolved() {
+        let rom = [
+            0x60, 0x02, // 0x200: LD V0, 0x02
+            0xB2, 0x06, // 0x202: JP V0, 0x206
+            0x00, 0xE0, // 0x204: CLS
+            0x00, 0xE0, // 0x206: CLS
+        ];
+        let cfg = Cfg::new(&rom);
+        let block = cfg.block_at(0x200).unwrap();
+        assert_eq!(block.unresolved, Some(0x206));
+        assert!(block.successors.is_empty());
+        assert!(cfg.branches().contains(&0x206));
+        // the target depends on V0, so nothing after the jump is followed
+        assert!(!cfg.is_code(0x204));
+        assert!(!cfg.is_code(0x206));
+    }
+
+    #[test]
+    fn stores_over_instructions_modify_the_program() {
+        let rom = [
+            0xA2, 0x08, // 0x200: LD I, 0x208
+            0xF1, 0x55, // 0x202: LD [I], V1
+            0xA2, 0x0E, // 0x204: LD I, 0x20E
+            0xF0, 0x55, // 0x206: LD [I], V0
+            0x12, 0x0A, // 0x208: JP 0x20A
+            0xF0, 0x1E, // 0x20A: ADD I, V0
+            0xF0, 0x55, // 0x20C: LD [I], V0
+            0x00, // 0x20E: data
+        ];
+        let cfg = Cfg::new(&rom);
+        let write = |address, target, overlaps_code| MemoryWrite {
+            address,
+            target,
+            overlaps_code,
+        };
+        assert_eq!(
+            cfg.writes(),
+            [
+                write(0x202, Some((0x208, 0x209)), true),
+                write(0x206, Some((0x20E, 0x20E)), false),
+                // I is not known after ADD I
+                write(0x20C, None, false),
+            ]
+        );
+    }
+}
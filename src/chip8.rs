@@ -1,29 +1,54 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use clock::Clock;
+use coverage::Coverage;
 use cpu::Cpu;
+use debugger::Debugger;
 use display::Display;
+use history::{History, Input, Snapshot};
 use keyboard::KeyBoard;
 use keymap::KeyMap;
 use memory::Memory;
 use operation::{Op, OpCode};
+use profiler::Profiler;
 use settings::Settings;
+use trace::{fnv1a, Record, Tracer};
 
-use crate::manager::{Manager, ManagerEvent};
+use crate::audio::AudioRecorder;
+use crate::disasm::{Disassembler, Syntax};
+use crate::error::Fault;
+use crate::framebuffer::Framebuffer;
+use crate::manager::{self, Manager, ManagerEvent};
+use crate::octo;
+use crate::palette::Palette;
+use crate::phosphor::Phosphor;
+use crate::recorder::{self, Recorder};
+use crate::symbols::Symbols;
+use crate::terminal::Terminal;
 
 pub mod clock;
+pub mod coverage;
 pub mod cpu;
+pub mod debugger;
 pub mod display;
+pub mod history;
 pub mod keyboard;
 pub mod keymap;
 pub mod memory;
 pub mod operation;
+pub mod profiler;
 pub mod settings;
+pub mod trace;
+
+/// the frames a key typed in a terminal is held, unless the terminal reports its release.
+/// a held key repeats its press before the hold ends, once the key repeat of the terminal starts
+const HOLD_FRAMES: u64 = 10;
 
 /// the chip-8 interpreter
 pub struct CHIP8 {
@@ -36,6 +61,22 @@ pub struct CHIP8 {
     cpu_clock: Clock,
     dt_clock: Clock,
     st_clock: Clock,
+    sound_due: u32, // the sound timer frequency added up over the frames without a window, a tick per delay timer frequency
+    history: History,
+    cycles: u64, // the number of cpu cycles executed
+    symbols: Symbols,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    rom: Vec<u8>, // the program loaded to memory
+    recorder: Option<Recorder>,
+    record_scale: usize, // the scale of the recordings started by the hotkey
+    window_scale: u32,   // the window pixels per display pixel of a new window
+    integer_scale: bool, // whether the window scales the display by whole numbers only
+    audio: Option<AudioRecorder>,
+    completed: Option<Display>, // the picture cleared by the latest CLS of the frame, when presenting after draw
+    presented: Framebuffer,     // the picture of the latest frame
+    phosphor: Phosphor,         // the screen the pictures are shown on
 }
 
 impl CHIP8 {
@@ -45,7 +86,7 @@ impl CHIP8 {
         let display = Display::default();
         let memory = Memory::default();
         let keyboard = KeyBoard::default();
-        let keymap = KeyMap::default();
+        let keymap = KeyMap;
         let settings = Settings::new(game_name);
         let cpu_clock = Clock::new(settings.cpu_freq);
         let st_clock = Clock::new(settings.sound_timer_freq);
@@ -60,61 +101,884 @@ impl CHIP8 {
             cpu_clock,
             st_clock,
             dt_clock,
+            sound_due: 0,
+            history: History::default(),
+            cycles: 0,
+            symbols: Symbols::default(),
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            rom: vec![],
+            recorder: None,
+            record_scale: recorder::DEFAULT_SCALE,
+            window_scale: manager::DEFAULT_SCALE,
+            integer_scale: false,
+            audio: None,
+            completed: None,
+            presented: Framebuffer::new(),
+            phosphor: Phosphor::new(0, false),
         }
     }
 
-    /// run chip-8 emulator
-    pub fn run(
+    /// return the settings
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// replace the settings, the clocks follow the new frequencies
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.cpu_clock = Clock::new(settings.cpu_freq);
+        self.st_clock = Clock::new(settings.sound_timer_freq);
+        self.dt_clock = Clock::new(settings.delay_timer_freq);
+        self.sound_due = 0;
+        self.phosphor = Phosphor::new(settings.phosphor_fade, settings.blend_frames);
+        self.settings = settings;
+    }
+
+    /// seed the random number generator, runs with the same seed and inputs are identical
+    pub fn seed(&mut self, seed: u64) {
+        self.cpu.seed(seed);
+    }
+
+    /// return the display
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    /// return the program loaded to memory
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// load the symbol map of the game
+    pub fn load_symbols(&mut self, path: &Path) -> io::Result<()> {
+        self.symbols = Symbols::load(path)?;
+        println!("INFO: Symbols were loaded successfully.");
+        Ok(())
+    }
+
+    /// trace the executed instructions
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// count the executed instructions, the report is written next to the game on exit
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::default());
+    }
+
+    /// track how the bytes of the ROM are used, the coverage is written next to the game on exit
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::default());
+    }
+
+    /// record the display a frame at a time to an animated GIF (.gif) or raw RGB frames (any other extension),
+    /// scaled up through the filters of the settings in the colors of the palette
+    pub fn start_recording(
         &mut self,
-        game_path: &Path,
-        print_instruction: bool,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        self.memory.load_rom(game_path)?;
+        path: &Path,
+        palette: &Palette,
+        scale: usize,
+    ) -> io::Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::create(
+            path,
+            palette,
+            &self.settings.filters,
+            scale,
+        )?);
+        self.record_scale = scale;
+        println!("INFO: Recording to {}.", path.display());
+        Ok(())
+    }
+
+    /// set the scale of the recordings started by the hotkey
+    pub fn set_record_scale(&mut self, scale: usize) {
+        self.record_scale = scale;
+    }
+
+    /// set the scale of a new window, and whether it scales the display by whole numbers only
+    pub fn set_window_scale(&mut self, scale: u32, integer_scale: bool) {
+        self.window_scale = scale.max(1);
+        self.integer_scale = integer_scale;
+    }
+
+    /// finish the recording, if there is one
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        if let Some(recorder) = self.recorder.take() {
+            let (path, frames) = (recorder.path().to_path_buf(), recorder.frames());
+            recorder.finish()?;
+            println!(
+                "INFO: Recording of {} frames saved to {}.",
+                frames,
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// check whether the display is being recorded
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// record the picture of the frame, a failed recording is stopped
+    fn record_frame(&mut self) {
+        let recorder = match self.recorder.as_mut() {
+            Some(recorder) => recorder,
+            None => return,
+        };
+        if let Err(err) = recorder.frame(&self.presented) {
+            println!("ERROR: Record failed, {}.", err);
+            self.recorder = None;
+        }
+    }
+
+    /// capture the beeper output to a WAV file, a sound timer tick at a time
+    pub fn start_audio_capture(&mut self, path: &Path) -> io::Result<()> {
+        self.stop_audio_capture()?;
+        self.audio = Some(AudioRecorder::create(path, self.settings.sound_timer_freq)?);
+        println!("INFO: Capturing audio to {}.", path.display());
+        Ok(())
+    }
+
+    /// finish the audio capture, if there is one
+    pub fn stop_audio_capture(&mut self) -> io::Result<()> {
+        if let Some(audio) = self.audio.take() {
+            let path = audio.path().to_path_buf();
+            let (ticks, beeps) = (audio.ticks(), audio.beeps());
+            audio.finish()?;
+            println!(
+                "INFO: Audio of {} ticks, {} with the beep, saved to {}.",
+                ticks,
+                beeps,
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// check whether the beeper sounds, which it does while the sound timer is active
+    pub fn is_beeping(&self) -> bool {
+        self.cpu.st() > 0 && !self.settings.mute
+    }
+
+    /// tick the sound timer, return whether the beeper sounds until the next tick
+    fn tick_sound(&mut self) -> bool {
+        let beep = self.input(Input::SoundTimer) && !self.settings.mute;
+        let audio = match self.audio.as_mut() {
+            Some(audio) => audio,
+            None => return beep,
+        };
+        if let Err(err) = audio.tick(beep) {
+            println!("ERROR: Capture audio failed, {}.", err);
+            self.audio = None;
+        }
+        beep
+    }
+
+    /// load a game to memory, an Octo source (.8o) is compiled first
+    pub fn load_game(&mut self, game_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let rom = if game_path.extension().is_some_and(|ext| ext == "8o") {
+            let program = octo::compile(game_path)?;
+            println!("INFO: Octo source was compiled successfully.");
+            if self.symbols.is_empty() {
+                self.symbols = Symbols::from_program(&program);
+            }
+            program.rom
+        } else {
+            fs::read(game_path)?
+        };
+        self.load(&rom)?;
         println!("INFO: Game was loaded to memory successfully.");
+        Ok(())
+    }
+
+    /// load the program bytes to memory
+    pub fn load(&mut self, rom: &[u8]) -> io::Result<()> {
+        self.memory.load_bytes(rom)?;
+        self.rom = rom.to_vec();
+        Ok(())
+    }
+
+    /// press a key of the keypad
+    pub fn press_key(&mut self, key: u8) {
+        self.input(Input::KeyDown(key));
+    }
+
+    /// release a key of the keypad
+    pub fn release_key(&mut self, key: u8) {
+        self.input(Input::KeyUp(key));
+    }
+
+    /// return the number of cpu cycles executed
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// return the number of cpu cycles of a frame, at least one
+    pub fn cycles_per_frame(&self) -> u16 {
+        (self.settings.cpu_freq / self.settings.delay_timer_freq).max(1)
+    }
+
+    /// emulate the timers ticking at the start of a new frame.
+    /// the sound timer keeps its own frequency, it ticks as often as it is due in the emulated time of a frame
+    pub fn start_frame(&mut self) {
+        self.input(Input::DelayTimer);
+        let frame_freq = self.settings.delay_timer_freq.max(1) as u32;
+        self.sound_due += self.settings.sound_timer_freq as u32;
+        while self.sound_due >= frame_freq {
+            self.sound_due -= frame_freq;
+            self.tick_sound();
+        }
+    }
+
+    /// emulate the timers of a new frame and the cpu cycles until the next one, without a window
+    pub fn run_frame(&mut self) -> Result<(), Fault> {
+        self.start_frame();
+        for _ in 0..self.cycles_per_frame() {
+            self.step()?;
+        }
+        self.end_frame();
+        Ok(())
+    }
+
+    /// finish a frame at the vertical blank, return whether the screen changed and needs to be drawn.
+    /// a picture cleared by CLS in the frame is presented instead of the one still being drawn,
+    /// which stays waiting to be presented at the end of a frame without a clear
+    pub fn end_frame(&mut self) -> bool {
+        let frame = match self.completed.take() {
+            Some(completed) => Some(completed),
+            None if self.display.redraw() => {
+                self.display.presented();
+                Some(self.display.clone())
+            }
+            None => None,
+        };
+        if let Some(display) = frame.as_ref() {
+            self.presented = Framebuffer::from_display(display);
+        }
+        self.record_frame();
+        self.phosphor.frame(&self.presented)
+    }
+
+    /// run chip-8 emulator with the loaded game, the reports are written next to the game
+    pub fn run(&mut self, game_path: &Path, debug: bool) -> Result<(), Box<dyn std::error::Error>> {
         println!("INFO: Start running the CHIP-8 emulator.");
 
-        let mut manager = Manager::new()?;
+        let mut manager = Manager::new(
+            self.settings.palette,
+            self.settings.filters,
+            self.window_scale,
+            self.integer_scale,
+        )?;
+        manager.draw(&self.phosphor)?;
+        let mut debugger = if debug { Some(Debugger::new()) } else { None };
+        let mut fault = None;
 
         loop {
             match manager.poll_event(&self.keymap) {
                 ManagerEvent::KeyDown(key) => {
-                    self.keyboard.press_key(key);
+                    self.input(Input::KeyDown(key));
+                }
+                ManagerEvent::KeyUp(key) => {
+                    self.input(Input::KeyUp(key));
+                }
+                ManagerEvent::Screenshot => {
+                    self.screenshot_hotkey(game_path, manager.palette(), manager.scale());
+                }
+                ManagerEvent::Record => {
+                    self.record_hotkey(game_path, manager.palette());
+                }
+                ManagerEvent::Palette => {
+                    let palette = palette_hotkey(manager.palette());
+                    manager.set_palette(palette);
+                    manager.draw(&self.phosphor)?;
+                }
+                ManagerEvent::Redraw => {
+                    manager.draw(&self.phosphor)?;
                 }
-                ManagerEvent::KeyUp(key) => self.keyboard.release_key(key),
                 ManagerEvent::Quit => {
                     break;
                 }
                 ManagerEvent::None => {
+                    if let Some(debugger) = debugger.as_mut() {
+                        if !debugger.update(self) {
+                            break;
+                        }
+                        if debugger.paused() {
+                            // a stepped picture is shown as it is, even halfway drawn
+                            manager.beep(false);
+                            if self.display.redraw() {
+                                self.display.presented();
+                                self.presented = Framebuffer::from_display(&self.display);
+                                self.phosphor.show(&self.presented);
+                                manager.draw(&self.phosphor)?;
+                            }
+                            continue;
+                        }
+                    }
+
                     if self.st_clock.tick() {
-                        let beep = self.cpu.cycle_st();
-                        manager.beep(beep && !self.settings.mute);
+                        let beep = self.tick_sound();
+                        manager.beep(beep);
                     }
 
                     if self.dt_clock.tick() {
-                        self.cpu.cycle_dt();
+                        self.input(Input::DelayTimer);
+                        if self.end_frame() {
+                            manager.draw(&self.phosphor)?;
+                        }
                     }
 
                     if self.cpu_clock.tick() {
-                        self.cpu.pipeline_operation(
-                            &mut self.memory,
-                            &mut self.display,
-                            &mut self.keyboard,
-                            &self.settings,
-                            print_instruction,
-                        );
+                        if let Err(err) = self.step() {
+                            match debugger.as_mut() {
+                                Some(debugger) => debugger.fault(self, err),
+                                None => {
+                                    fault = Some(err);
+                                    break;
+                                }
+                            }
+                        }
+
+                        if let Some(debugger) = debugger.as_mut() {
+                            debugger.check(self);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.flush_trace();
+        self.stop_recording()?;
+        self.stop_audio_capture()?;
+        self.write_reports(game_path)?;
+        if let Some(fault) = fault {
+            return Err(Box::new(fault));
+        }
+        println!("INFO: Game over.");
+        println!("INFO: Exist the CHIP-8 emulator.");
+        Ok(())
+    }
 
-                        if self.display.redraw() {
-                            manager.clear();
-                            self.display.draw(&mut manager)?;
-                            manager.present();
+    /// run chip-8 emulator with the loaded game in the terminal, the reports are written next to the game
+    pub fn run_terminal(&mut self, game_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        println!("INFO: Start running the CHIP-8 emulator in the terminal.");
+
+        let game_name = game_path.file_name().unwrap_or_default().to_string_lossy();
+        let mut terminal = Terminal::new(&game_name, self.settings.palette)?;
+        let frame_time = Duration::from_secs(1) / self.settings.delay_timer_freq.max(1) as u32;
+        // the frame each held key is released at, a terminal without key releases only repeats the presses
+        let mut held: [Option<u64>; 16] = [None; 16];
+        let mut frame = 0;
+        let mut next_frame = Instant::now();
+        let mut fault = None;
+
+        'run: loop {
+            // handle the events until the next frame is due
+            loop {
+                let timeout = next_frame.saturating_duration_since(Instant::now());
+                match terminal.poll_event(&self.keymap, timeout)? {
+                    ManagerEvent::KeyDown(key) => {
+                        if held[key as usize].is_none() {
+                            self.input(Input::KeyDown(key));
                         }
+                        held[key as usize] = Some(if terminal.reports_releases() {
+                            u64::MAX
+                        } else {
+                            frame + HOLD_FRAMES
+                        });
                     }
+                    ManagerEvent::KeyUp(key) => {
+                        if held[key as usize].take().is_some() {
+                            self.input(Input::KeyUp(key));
+                        }
+                    }
+                    ManagerEvent::Screenshot => {
+                        self.screenshot_hotkey(
+                            game_path,
+                            terminal.palette(),
+                            manager::DEFAULT_SCALE as usize,
+                        );
+                        terminal.refresh();
+                    }
+                    ManagerEvent::Record => {
+                        self.record_hotkey(game_path, terminal.palette());
+                        terminal.refresh();
+                    }
+                    ManagerEvent::Palette => {
+                        let palette = palette_hotkey(terminal.palette());
+                        terminal.set_palette(palette);
+                    }
+                    ManagerEvent::Redraw => terminal.refresh(),
+                    ManagerEvent::Quit => break 'run,
+                    ManagerEvent::None => {}
+                }
+                if Instant::now() >= next_frame {
+                    break;
+                }
+            }
+            // a terminal too slow to keep up skips the frames instead of catching up
+            next_frame = (next_frame + frame_time).max(Instant::now());
+
+            for (key, release) in held.iter_mut().enumerate() {
+                if release.is_some_and(|release| release <= frame) {
+                    *release = None;
+                    self.input(Input::KeyUp(key as u8));
                 }
             }
+            if let Err(err) = self.run_frame() {
+                fault = Some(err);
+                break;
+            }
+            terminal.draw(&self.phosphor, self.is_beeping())?;
+            frame += 1;
         }
 
+        drop(terminal);
+        self.flush_trace();
+        self.stop_recording()?;
+        self.stop_audio_capture()?;
+        self.write_reports(game_path)?;
+        if let Some(fault) = fault {
+            return Err(Box::new(fault));
+        }
         println!("INFO: Game over.");
         println!("INFO: Exist the CHIP-8 emulator.");
         Ok(())
     }
+
+    /// save a screenshot next to the game, at the scale of the window
+    fn screenshot_hotkey(&self, game_path: &Path, palette: &Palette, scale: usize) {
+        let path = capture_path(game_path, "png");
+        match self.screenshot(&path, palette, scale) {
+            Ok(()) => println!("INFO: Screenshot saved to {}.", path.display()),
+            Err(err) => println!("ERROR: Save screenshot failed, {}.", err),
+        }
+    }
+
+    /// start recording next to the game, or stop the recording
+    fn record_hotkey(&mut self, game_path: &Path, palette: &Palette) {
+        let result = if self.is_recording() {
+            self.stop_recording()
+        } else {
+            let path = capture_path(game_path, "gif");
+            self.start_recording(&path, palette, self.record_scale)
+        };
+        if let Err(err) = result {
+            println!("ERROR: Record failed, {}.", err);
+        }
+    }
+
+    /// save the picture of the latest frame scaled up through the filters of the settings in the colors of the palette,
+    /// as a PNG (.png) or a PPM (.ppm)
+    pub fn screenshot(&self, path: &Path, palette: &Palette, scale: usize) -> io::Result<()> {
+        self.presented
+            .save_image(path, palette, &self.settings.filters, scale)
+    }
+
+    /// write the profile and the coverage of the run next to the game
+    fn write_reports(&self, game_path: &Path) -> io::Result<()> {
+        let game_name = game_path.file_name().unwrap_or_default().to_string_lossy();
+
+        if let Some(profiler) = self.profiler.as_ref() {
+            let report = profiler.report(
+                &game_name,
+                self.settings.cpu_freq,
+                self.settings.delay_timer_freq,
+                &self.symbols,
+            );
+            let profile_path = game_path.with_extension("prof");
+            fs::write(&profile_path, report)?;
+            println!("INFO: Profile written to {}.", profile_path.display());
+        }
+
+        if let Some(coverage) = self.coverage.as_ref() {
+            let coverage_path = game_path.with_extension("cov");
+            fs::write(&coverage_path, coverage.report(&game_name, self.rom.len()))?;
+            let mut disassembler = Disassembler::with_coverage(&self.rom, coverage);
+            disassembler.set_symbols(&self.symbols);
+            let listing_path = game_path.with_extension("cov.asm");
+            fs::write(&listing_path, disassembler.listing(Syntax::Cowgod))?;
+            println!(
+                "INFO: Coverage written to {} and {}.",
+                coverage_path.display(),
+                listing_path.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// apply an input to the machine and record it
+    /// return whether the sound timer is still active
+    fn input(&mut self, input: Input) -> bool {
+        if self.cycles < self.history.head() {
+            // the input changes a replayed past, so the recorded future is no longer valid
+            self.history.truncate(self.cycles);
+        }
+        self.take_snapshot();
+        self.history.push_input(self.cycles, input);
+        if let (Input::DelayTimer, Some(profiler)) = (input, self.profiler.as_mut()) {
+            profiler.frame(self.cycles);
+        }
+        self.apply(input)
+    }
+
+    /// apply an input to the machine without recording it
+    /// return whether the sound timer is still active
+    fn apply(&mut self, input: Input) -> bool {
+        match input {
+            Input::KeyDown(key) => self.keyboard.press_key(key),
+            Input::KeyUp(key) => self.keyboard.release_key(key),
+            Input::DelayTimer => self.cpu.cycle_dt(),
+            Input::SoundTimer => return self.cpu.cycle_st(),
+        }
+        false
+    }
+
+    /// execute one cpu cycle and record it.
+    /// if the cycle is in a replayed past, the recorded inputs of the cycle are applied first
+    pub fn step(&mut self) -> Result<(), Fault> {
+        self.take_snapshot();
+        if self.cycles < self.history.head() {
+            let inputs: Vec<Input> = self.history.inputs_at(self.cycles).collect();
+            inputs.into_iter().for_each(|input| {
+                self.apply(input);
+            });
+        }
+
+        if self.execute()? {
+            self.history.push_collision(self.cycles - 1);
+        }
+        self.history.advance(self.cycles);
+        Ok(())
+    }
+
+    /// execute one cpu cycle without recording it.
+    /// only the first run of a cycle is traced, covered and profiled, not its replays.
+    /// return whether the cycle was a DRW instruction which set VF, or the fault which stopped it
+    fn execute(&mut self) -> Result<bool, Fault> {
+        let pc = self.cpu.pc();
+        let opcode = self.memory.read16(pc);
+        let op = Op::decode(&opcode).ok_or(Fault::InvalidOpcode {
+            pc,
+            opcode: opcode.get_opcode(),
+        })?;
+
+        let first_run = self.cycles == self.history.head();
+        if first_run {
+            self.trace(pc, opcode.get_opcode(), op);
+        }
+        if let (true, Some(coverage)) = (first_run, self.coverage.as_mut()) {
+            coverage.record(pc, op, &self.cpu, &self.settings);
+        }
+
+        if op == Op::CLS && self.settings.present_after_draw {
+            self.completed = Some(self.display.clone());
+        }
+        self.cpu.pipeline_operation(
+            &mut self.memory,
+            &mut self.display,
+            &mut self.keyboard,
+            &self.settings,
+        )?;
+        if let (true, Some(profiler)) = (first_run, self.profiler.as_mut()) {
+            profiler.record(self.cycles, pc, opcode, op, self.cpu.pc());
+        }
+        self.cycles += 1;
+
+        Ok(matches!(op, Op::DRW { .. }) && self.cpu.v(0xF) == 1)
+    }
+
+    /// write the instruction about to be executed to the trace
+    fn trace(&mut self, pc: u16, opcode: u16, op: Op) {
+        let tracer = match self.tracer.as_mut() {
+            Some(tracer) if tracer.matches(self.cycles, pc, op) => tracer,
+            _ => return,
+        };
+        let mut record = Record::new(self.cycles, &self.cpu, &self.memory, &self.display, opcode);
+        record.changes = tracer.changes(&self.memory, &self.display);
+        let comment = format!(
+            "{}: {}",
+            self.symbols.describe(pc),
+            self.symbols.instruction(op)
+        );
+        if let Err(err) = tracer.write(&record, &comment) {
+            println!("ERROR: Write trace failed, {}.", err);
+            self.tracer = None;
+        }
+    }
+
+    /// write the buffered trace records
+    fn flush_trace(&mut self) {
+        if let Some(Err(err)) = self.tracer.as_mut().map(|tracer| tracer.flush()) {
+            println!("ERROR: Write trace failed, {}.", err);
+            self.tracer = None;
+        }
+    }
+
+    /// keep a snapshot if one is due at the cycle, before any input of the cycle is applied,
+    /// so that replaying from it applies each input once
+    fn take_snapshot(&mut self) {
+        if self.history.need_snapshot(self.cycles) {
+            self.history.push_snapshot(self.snapshot());
+        }
+    }
+
+    /// capture the current machine state
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            cycle: self.cycles,
+            cpu: self.cpu.clone(),
+            memory: self.memory.clone(),
+            display: self.display.clone(),
+            keyboard: self.keyboard.clone(),
+        }
+    }
+
+    /// restore the machine to the state before the cycle was executed,
+    /// by re-executing the recorded inputs from the nearest snapshot.
+    /// return false if the cycle is no longer in the history
+    fn rewind(&mut self, cycle: u64) -> bool {
+        let snapshot = match self.history.snapshot_before(cycle) {
+            Some(snapshot) => snapshot.clone(),
+            None => return false,
+        };
+
+        self.cycles = snapshot.cycle;
+        self.cpu = snapshot.cpu;
+        self.memory = snapshot.memory;
+        self.display = snapshot.display;
+        self.keyboard = snapshot.keyboard;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.rewind();
+        }
+
+        while self.cycles < cycle {
+            let inputs: Vec<Input> = self.history.inputs_at(self.cycles).collect();
+            inputs.into_iter().for_each(|input| {
+                self.apply(input);
+            });
+            // the cycles were executed before, they do not fault again
+            if self.execute().is_err() {
+                break;
+            }
+        }
+
+        // the rewound picture is presented as it is
+        self.completed = None;
+        self.display.refresh();
+        true
+    }
+}
+
+/// return the named palette after the one shown, for the hotkey switching between them
+fn palette_hotkey(palette: &Palette) -> Palette {
+    let palette = palette.next();
+    println!("INFO: Palette {}.", palette);
+    palette
+}
+
+/// return the first free path of a screenshot or a recording next to the game, e.g. `BRIX-1.png`
+fn capture_path(game_path: &Path, extension: &str) -> PathBuf {
+    let game_name = game_path.file_stem().unwrap_or_default().to_string_lossy();
+    (1..)
+        .map(|n| game_path.with_file_name(format!("{}-{}.{}", game_name, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trace::{TraceFilter, TraceFormat, TraceReader};
+
+    /// a program which keeps reloading the timers with 0xFF whenever the delay timer runs out
+    const TIMERS: [u8; 14] = [
+        0x6A, 0xFF, // 0x200: LD VA, 0xFF
+        0xFA, 0x15, // 0x202: LD DT, VA
+        0xFA, 0x18, // 0x204: LD ST, VA
+        0xFB, 0x07, // 0x206: LD VB, DT
+        0x3B, 0x00, // 0x208: SE VB, 0
+        0x12, 0x06, // 0x20A: JP 0x206
+        0x12, 0x02, // 0x20C: JP 0x202
+    ];
+
+    /// a machine running the timer program with fixed random numbers
+    fn machine() -> CHIP8 {
+        let mut chip8 = CHIP8::new("TEST");
+        chip8.load(&TIMERS).unwrap();
+        chip8.seed(0);
+        chip8
+    }
+
+    /// rewind to the cycle and run forward again to where the machine was, which must be the same state
+    fn assert_replays(chip8: &mut CHIP8, cycle: u64) {
+        let (cpu, memory) = (chip8.cpu.clone(), chip8.memory.clone());
+        let framebuffer = Framebuffer::from_display(&chip8.display);
+        let head = chip8.cycles;
+
+        assert!(chip8.rewind(cycle));
+        assert_eq!(chip8.cycles, cycle);
+        while chip8.cycles < head {
+            chip8.step().unwrap();
+        }
+
+        assert_eq!(chip8.cpu, cpu);
+        assert_eq!(chip8.memory, memory);
+        assert_eq!(Framebuffer::from_display(&chip8.display), framebuffer);
+    }
+
+    #[test]
+    fn rewind_to_snapshot_replays_exactly() {
+        let mut chip8 = machine();
+        // a frame is 11 cycles, so the snapshot at cycle 5500 is also the start of a frame with timer inputs
+        for _ in 0..520 {
+            chip8.run_frame().unwrap();
+        }
+        assert!(chip8.history.inputs_at(5500).count() > 0);
+        assert_replays(&mut chip8, 5500);
+    }
+
+    #[test]
+    fn rewind_between_snapshots_replays_exactly() {
+        let mut chip8 = machine();
+        for _ in 0..520 {
+            chip8.run_frame().unwrap();
+        }
+        assert_replays(&mut chip8, 5503);
+        assert_replays(&mut chip8, 5511);
+    }
+
+    #[test]
+    fn rewind_restores_the_timers_of_the_cycle() {
+        let mut chip8 = machine();
+        for _ in 0..500 {
+            chip8.run_frame().unwrap();
+        }
+        // after 500 frames of 11 cycles the machine is at the snapshot of cycle 5500, before the timers of the next frame tick
+        let (dt, cycle) = (chip8.cpu.dt(), chip8.cycles);
+        for _ in 0..5 {
+            chip8.run_frame().unwrap();
+        }
+        assert!(chip8.rewind(cycle));
+        assert_eq!(chip8.cpu.dt(), dt);
+    }
+
+    #[test]
+    fn coverage_marks_the_bytes_the_cpu_accesses() {
+        let mut chip8 = CHIP8::new("TEST");
+        let mut settings = chip8.settings().clone();
+        settings.vertical_wrap = false;
+        chip8.set_settings(settings);
+        chip8.enable_coverage();
+        chip8
+            .load(&[
+                0xAF, 0xFE, // 0x200: LD I, 0xFFE
+                0xF3, 0x55, // 0x202: LD [I], V3
+                0x61, 0x1E, // 0x204: LD V1, 30
+                0xA3, 0x00, // 0x206: LD I, 0x300
+                0xD0, 0x15, // 0x208: DRW V0, V1, 5
+            ])
+            .unwrap();
+        (0..5).for_each(|_| chip8.step().unwrap());
+
+        let coverage = chip8.coverage.as_ref().unwrap();
+        // LD [I], V3 at 0xFFE wraps around to 0x000
+        for address in [0xFFE, 0xFFF, 0x000, 0x001] {
+            assert_eq!(
+                coverage.flags(address),
+                coverage::WRITTEN,
+                "{:03X}",
+                address
+            );
+        }
+        assert_eq!(coverage.flags(0x002), 0);
+        // only the two rows above the bottom of the screen are drawn
+        assert_eq!(coverage.flags(0x300), coverage::READ);
+        assert_eq!(coverage.flags(0x301), coverage::READ);
+        assert_eq!(coverage.flags(0x302), 0);
+    }
+
+    #[test]
+    fn frames_tick_the_sound_timer_at_its_own_frequency() {
+        let mut chip8 = CHIP8::new("TEST");
+        let mut settings = chip8.settings().clone();
+        settings.sound_timer_freq = 90;
+        chip8.set_settings(settings);
+        chip8
+            .load(&[
+                0x6A, 0xFF, // 0x200: LD VA, 0xFF
+                0xFA, 0x18, // 0x202: LD ST, VA
+                0x12, 0x04, // 0x204: JP 0x204
+            ])
+            .unwrap();
+        // the sound timer is set in the first frame, and ticks 1.5 times per frame in the next 10
+        for _ in 0..11 {
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(chip8.cpu.st(), 0xFF - 15);
+    }
+
+    #[test]
+    fn rewind_before_the_frame_keeps_profiling() {
+        let mut chip8 = machine();
+        chip8.enable_profiler();
+        for _ in 0..520 {
+            chip8.run_frame().unwrap();
+        }
+        // the cycles and the frames counted in the profile
+        let counts = |chip8: &CHIP8| {
+            let report = chip8
+                .profiler
+                .as_ref()
+                .unwrap()
+                .report("TEST", 500, 60, &Symbols::new());
+            let words: Vec<&str> = report.lines().nth(1).unwrap().split(' ').collect();
+            let count = |word: &str| word.trim_end_matches(',').parse::<u64>().unwrap();
+            (count(words[0]), count(words[words.len() - 2]))
+        };
+        let (cycles, frames) = counts(&chip8);
+        let head = chip8.cycles;
+
+        // the counts are kept, the replayed cycles are not counted again
+        assert!(chip8.rewind(5500));
+        assert_eq!(counts(&chip8), (cycles, frames));
+        while chip8.cycles < head {
+            chip8.step().unwrap();
+        }
+        assert_eq!(counts(&chip8), (cycles, frames));
+
+        // the frame in progress was forgotten, so the first frame tick after the rewind starts measuring again
+        for _ in 0..5 {
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(counts(&chip8).1, frames + 4);
+        assert!(counts(&chip8).0 > cycles);
+    }
+
+    #[test]
+    fn replayed_cycles_are_not_traced_again() {
+        let path = std::env::temp_dir().join(format!("chip8-replay-{}.trace", std::process::id()));
+        let mut chip8 = machine();
+        chip8.set_tracer(Tracer::create(&path, TraceFormat::Text, TraceFilter::default()).unwrap());
+        for _ in 0..3 {
+            chip8.run_frame().unwrap();
+        }
+        let head = chip8.cycles;
+        assert!(chip8.rewind(head - 20));
+        for _ in 0..30 {
+            chip8.step().unwrap();
+        }
+        chip8.flush_trace();
+
+        let cycles: Vec<u64> = TraceReader::open(&path)
+            .unwrap()
+            .map(|record| record.unwrap().cycle)
+            .collect();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(cycles, (0..head + 10).collect::<Vec<_>>());
+    }
 }
@@ -0,0 +1,139 @@
+use std::fmt::Write;
+
+use crate::chip8::memory::{MEMORY_SIZE, PROGRAM_START};
+use crate::chip8::{Cpu, Memory, Op, Settings};
+
+/// the byte was executed as part of an instruction
+pub const EXECUTED: u8 = 0b001;
+
+/// the byte was read as data by DRW or LD Vx, [I]
+pub const READ: u8 = 0b010;
+
+/// the byte was written by LD B, Vx or LD [I], Vx
+pub const WRITTEN: u8 = 0b100;
+
+/// the byte is the first one of an executed instruction
+const INSTRUCTION: u8 = 0b1000;
+
+/// how the bytes of the memory were used while running
+pub struct Coverage {
+    flags: Vec<u8>, // the usage flags of each address
+}
+
+impl Coverage {
+    /// create a coverage instance where nothing is used yet
+    pub fn new() -> Coverage {
+        Coverage {
+            flags: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    /// mark the bytes of memory used by the instruction at pc, with the cpu before executing it
+    pub fn record(&mut self, pc: u16, op: Op, cpu: &Cpu, settings: &Settings) {
+        let i = cpu.i();
+        self.mark(pc, 1, INSTRUCTION);
+        self.mark(pc, 2, EXECUTED);
+        match op {
+            // the rows clipped at the bottom of the screen are not read
+            Op::DRW { reg_y, n, .. } => {
+                self.mark(i, cpu.sprite_rows(reg_y, n, settings) as u16, READ)
+            }
+            Op::LDJ { reg } => self.mark(i, reg as u16 + 1, READ),
+            Op::LDB { .. } => self.mark(i, 3, WRITTEN),
+            Op::LDI { reg } => self.mark(i, reg as u16 + 1, WRITTEN),
+            _ => {}
+        }
+    }
+
+    /// add a flag to the bytes from address on, wrapped around the memory like the cpu accesses them
+    fn mark(&mut self, address: u16, len: u16, flag: u8) {
+        for offset in 0..len {
+            self.flags[Memory::wrap(address.wrapping_add(offset)) as usize] |= flag;
+        }
+    }
+
+    /// return the usage flags of the address
+    pub fn flags(&self, address: u16) -> u8 {
+        self.flags
+            .get(address as usize)
+            .map_or(0, |f| f & (EXECUTED | READ | WRITTEN))
+    }
+
+    /// check whether an instruction starting at the address was executed
+    pub fn instruction(&self, address: u16) -> bool {
+        self.flags
+            .get(address as usize)
+            .is_some_and(|f| f & INSTRUCTION != 0)
+    }
+
+    /// format usage flags as `xrw`, a `-` for each one missing
+    pub fn describe(flags: u8) -> String {
+        [(EXECUTED, 'x'), (READ, 'r'), (WRITTEN, 'w')]
+            .iter()
+            .map(|&(flag, c)| if flags & flag != 0 { c } else { '-' })
+            .collect()
+    }
+
+    /// write the coverage file of a ROM of the size: a summary and the address ranges with the same usage
+    pub fn report(&self, game_name: &str, rom_size: usize) -> String {
+        let start = PROGRAM_START as usize;
+        let end = (start + rom_size).min(MEMORY_SIZE);
+        let rom: Vec<u8> = (start..end).map(|a| self.flags(a as u16)).collect();
+        let count = |flag: u8| rom.iter().filter(|&&f| f & flag != 0).count();
+        let percent = |n: usize| {
+            if rom.is_empty() {
+                0.0
+            } else {
+                n as f64 * 100.0 / rom.len() as f64
+            }
+        };
+
+        let mut report = String::new();
+        let executed = count(EXECUTED);
+        let unused = rom.iter().filter(|&&f| f == 0).count();
+        writeln!(
+            report,
+            "# coverage of {}: {} bytes, {} executed ({:.1}%), {} read as data, {} written, {} never used ({:.1}%)",
+            game_name,
+            rom.len(),
+            executed,
+            percent(executed),
+            count(READ),
+            count(WRITTEN),
+            unused,
+            percent(unused)
+        )
+        .unwrap();
+        writeln!(
+            report,
+            "# x executed as code, r read as data by DRW or LD Vx, [I], w written by LD B, Vx or LD [I], Vx, - not"
+        )
+        .unwrap();
+
+        let mut first = 0;
+        while first < rom.len() {
+            let last = first
+                + rom[first..]
+                    .iter()
+                    .take_while(|&&f| f == rom[first])
+                    .count()
+                - 1;
+            writeln!(
+                report,
+                "{:04X}-{:04X} {}",
+                start + first,
+                start + last,
+                Coverage::describe(rom[first])
+            )
+            .unwrap();
+            first = last + 1;
+        }
+        report
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
@@ -1,6 +1,9 @@
-use crate::chip8::{Display, KeyBoard, Memory, Op, Rng, Settings};
+use std::fmt;
+
+use crate::chip8::{Display, Fault, KeyBoard, Memory, Op, Rng, SeedableRng, Settings, StdRng};
 
 /// chip-8 cpu
+#[derive(Clone, Debug, PartialEq)]
 pub struct Cpu {
     v: [u8; 16], // general purpose 8-bit registers(from V0 to VF, and the VF is used as a flag by some instructions)
     i: u16,      // generally used to store memory address
@@ -9,6 +12,7 @@ pub struct Cpu {
     pc: u16,     // store the currently executing address
     sp: u8,      // point to the topmost level of the stack
     stack: [u16; 16], // stack is an array of 16 16-bit values, used to store the address that the interpreter returns to when finished with a subroutine
+    rng: StdRng, // random number generator, part of the state so that a replay gives the same numbers
 }
 
 impl Cpu {
@@ -22,9 +26,55 @@ impl Cpu {
             pc: 0x200, // chip-8 programs start at location 0x200
             sp: 0,
             stack: [0; 16],
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// return the program counter
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// return the value of the register Vx
+    pub fn v(&self, reg: u8) -> u8 {
+        self.v[reg as usize]
+    }
+
+    /// return the value of the register I
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    /// return the number of sprite rows DRW draws, the rows below the screen are clipped without vertical wrap
+    pub fn sprite_rows(&self, reg_y: u8, n: u8, settings: &Settings) -> u8 {
+        let origin_y = self.v[reg_y as usize] as usize;
+        if settings.vertical_wrap {
+            n
+        } else {
+            n.min(Display::display_height().saturating_sub(origin_y) as u8)
         }
     }
 
+    /// return the stack pointer
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    /// return the value of the delay timer
+    pub fn dt(&self) -> u8 {
+        self.dt
+    }
+
+    /// return the value of the sound timer
+    pub fn st(&self) -> u8 {
+        self.st
+    }
+
+    /// seed the random number generator, runs with the same seed and inputs give the same numbers
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
     pub fn cycle_dt(&mut self) {
         if self.dt > 0 {
             self.dt -= 1;
@@ -39,27 +89,34 @@ impl Cpu {
     }
 
     /// fetch, decode and execute instruction
+    /// return the fault if the instruction can not be executed, the cpu is left before it
     pub fn pipeline_operation(
         &mut self,
         memory: &mut Memory,
         display: &mut Display,
         keyboard: &mut KeyBoard,
         settings: &Settings,
-        print_instruction: bool,
-    ) {
+    ) -> Result<(), Fault> {
         // read 2 bytes opcode at program counter
-        let opcode = memory.read16(self.pc);
-
-        // increment the program counter
-        self.pc += 2;
+        let pc = self.pc;
+        let opcode = memory.read16(pc);
 
         // decode the instruction
-        let op = Op::decode(&opcode);
-
-        if print_instruction {
-            println!("{:04X}: {:04X} {:?}", self.pc - 2, opcode.get_opcode(), op);
+        let op = Op::decode(&opcode).ok_or(Fault::InvalidOpcode {
+            pc,
+            opcode: opcode.get_opcode(),
+        })?;
+        match op {
+            Op::CALL { .. } if self.sp as usize == self.stack.len() => {
+                return Err(Fault::StackOverflow { pc })
+            }
+            Op::RET if self.sp == 0 => return Err(Fault::StackUnderflow { pc }),
+            _ => {}
         }
 
+        // increment the program counter
+        self.pc += 2;
+
         match op {
             Op::SYS { address } => self.sys(address),
             Op::CLS => self.cls(display),
@@ -97,6 +154,9 @@ impl Cpu {
             Op::LDI { reg } => self.ldi(reg, memory, settings),
             Op::LDJ { reg } => self.ldj(reg, memory, settings),
         }
+        // the program counter wraps around the memory
+        self.pc = Memory::wrap(self.pc);
+        Ok(())
     }
 
     fn sys(&mut self, _address: u16) {
@@ -214,7 +274,7 @@ impl Cpu {
     }
 
     fn rnd(&mut self, reg: u8, byte: u8) {
-        self.v[reg as usize] = byte & rand::thread_rng().gen_range(0..=255);
+        self.v[reg as usize] = byte & self.rng.gen_range(0..=255);
     }
 
     fn drw(
@@ -228,21 +288,19 @@ impl Cpu {
     ) {
         let origin_x = self.v[reg_x as usize] as usize; // origin x coordinate
         let origin_y = self.v[reg_y as usize] as usize; // origin y coordinate
-        let n = n as usize; // read n bytes
+        let n = self.sprite_rows(reg_y, n, settings) as usize; // read n bytes
 
         let mut pixel_erased = false;
 
         // offset on the y coordinate
         for y_offset in 0..n {
             let y = origin_y + y_offset; // y coordinate
-            if y >= Display::display_height() && !settings.vertical_wrap {
-                break;
-            }
+
             // read one byte
-            let byte = memory.read8(self.i + y_offset as u16);
+            let byte = memory.read8(self.i.wrapping_add(y_offset as u16));
 
             // offset on the x coordinate
-            (0..8).into_iter().for_each(|x_offset| {
+            (0..8).for_each(|x_offset| {
                 let x = origin_x + x_offset; // x coordinate
                 let pixel = (byte >> (7 - x_offset) & 1) == 1; // get the pixel on the (x, y) coordinate
                 pixel_erased |= display.set_pixel(x, y, pixel);
@@ -303,30 +361,44 @@ impl Cpu {
 
     fn ldb(&mut self, reg: u8, memory: &mut Memory) {
         memory.write(self.i, self.v[reg as usize] / 100);
-        memory.write(self.i + 1, (self.v[reg as usize] / 10) % 10);
-        memory.write(self.i + 2, self.v[reg as usize] % 10)
+        memory.write(self.i.wrapping_add(1), (self.v[reg as usize] / 10) % 10);
+        memory.write(self.i.wrapping_add(2), self.v[reg as usize] % 10)
     }
 
     fn ldi(&mut self, reg: u8, memory: &mut Memory, settings: &Settings) {
-        (0..=reg).into_iter().for_each(|i| {
-            let address = self.i + i as u16;
+        (0..=reg).for_each(|i| {
+            let address = self.i.wrapping_add(i as u16);
             memory.write(address, self.v[i as usize])
         });
 
         if settings.increment_i_register {
-            self.i += reg as u16 + 1;
+            self.i = self.i.wrapping_add(reg as u16 + 1);
         }
     }
 
     fn ldj(&mut self, reg: u8, memory: &Memory, settings: &Settings) {
-        (0..=reg).into_iter().for_each(|i| {
-            let address = self.i + i as u16;
+        (0..=reg).for_each(|i| {
+            let address = self.i.wrapping_add(i as u16);
             self.v[i as usize] = memory.read8(address);
         });
 
         if settings.increment_i_register {
-            self.i += reg as u16 + 1;
+            self.i = self.i.wrapping_add(reg as u16 + 1);
+        }
+    }
+}
+
+impl fmt::Display for Cpu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PC={:04X} I={:04X} SP={:X} DT={:02X} ST={:02X} V=",
+            self.pc, self.i, self.sp, self.dt, self.st
+        )?;
+        for (reg, value) in self.v.iter().enumerate() {
+            write!(f, "{}{:02X}", if reg == 0 { "" } else { " " }, value)?;
         }
+        Ok(())
     }
 }
 
@@ -335,3 +407,511 @@ impl Default for Cpu {
         Cpu::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a machine with a program at 0x200 and the settings without quirks
+    struct Machine {
+        cpu: Cpu,
+        memory: Memory,
+        display: Display,
+        keyboard: KeyBoard,
+        settings: Settings,
+    }
+
+    impl Machine {
+        fn new(program: &[u16]) -> Machine {
+            let mut memory = Memory::new();
+            let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+            memory.load_bytes(&rom).unwrap();
+            let mut cpu = Cpu::new();
+            cpu.seed(0);
+            let mut settings = Settings::new("TEST");
+            settings.shift_vx_ignore_vy = false;
+            settings.increment_i_register = false;
+            settings.set_vf_when_overflow = false;
+            settings.vertical_wrap = false;
+            Machine {
+                cpu,
+                memory,
+                display: Display::new(),
+                keyboard: KeyBoard::new(),
+                settings,
+            }
+        }
+
+        /// execute the next instruction
+        fn step(&mut self) -> Result<(), Fault> {
+            self.cpu.pipeline_operation(
+                &mut self.memory,
+                &mut self.display,
+                &mut self.keyboard,
+                &self.settings,
+            )
+        }
+
+        /// execute the next n instructions, which must not fault
+        fn run(&mut self, n: usize) {
+            for _ in 0..n {
+                self.step().unwrap();
+            }
+        }
+    }
+
+    /// execute one instruction with the registers set before, return the machine after it
+    fn exec(opcode: u16, registers: &[(u8, u8)]) -> Machine {
+        let mut machine = Machine::new(&[opcode]);
+        for &(reg, value) in registers {
+            machine.cpu.v[reg as usize] = value;
+        }
+        machine.run(1);
+        machine
+    }
+
+    #[test]
+    fn sys_is_ignored() {
+        let machine = exec(0x0123, &[]);
+        assert_eq!(machine.cpu.pc, 0x202);
+        assert_eq!(machine.cpu.v, [0; 16]);
+    }
+
+    #[test]
+    fn cls_clears_the_display() {
+        let mut machine = Machine::new(&[0x00E0]);
+        machine.display.set_pixel(3, 4, true);
+        machine.run(1);
+        assert!(!machine.display.pixel(3, 4));
+        assert_eq!(machine.display.lit(), 0);
+    }
+
+    #[test]
+    fn jp_sets_the_program_counter() {
+        let machine = exec(0x1ABC, &[]);
+        assert_eq!(machine.cpu.pc, 0xABC);
+    }
+
+    #[test]
+    fn call_and_ret_use_the_stack() {
+        // 200: CALL 206, 202: JP 202, 204: -, 206: RET
+        let mut machine = Machine::new(&[0x2206, 0x1202, 0x0000, 0x00EE]);
+        machine.run(1);
+        assert_eq!(machine.cpu.pc, 0x206);
+        assert_eq!(machine.cpu.sp, 1);
+        assert_eq!(machine.cpu.stack[0], 0x202);
+        machine.run(1);
+        assert_eq!(machine.cpu.pc, 0x202);
+        assert_eq!(machine.cpu.sp, 0);
+    }
+
+    #[test]
+    fn call_faults_when_the_stack_is_full() {
+        // a subroutine calling itself
+        let mut machine = Machine::new(&[0x2200]);
+        machine.run(16);
+        assert_eq!(machine.cpu.sp, 16);
+        assert_eq!(machine.step(), Err(Fault::StackOverflow { pc: 0x200 }));
+        assert_eq!(machine.cpu.pc, 0x200);
+        assert_eq!(machine.cpu.sp, 16);
+    }
+
+    #[test]
+    fn ret_faults_when_the_stack_is_empty() {
+        let mut machine = Machine::new(&[0x00EE]);
+        assert_eq!(machine.step(), Err(Fault::StackUnderflow { pc: 0x200 }));
+        assert_eq!(machine.cpu.pc, 0x200);
+    }
+
+    #[test]
+    fn invalid_opcodes_fault() {
+        for opcode in [0x8008, 0x800F, 0xE000, 0xF0FF] {
+            let mut machine = Machine::new(&[opcode]);
+            assert_eq!(
+                machine.step(),
+                Err(Fault::InvalidOpcode { pc: 0x200, opcode })
+            );
+            assert_eq!(machine.cpu.pc, 0x200);
+        }
+    }
+
+    #[test]
+    fn se_skips_when_equal() {
+        assert_eq!(exec(0x3142, &[(1, 0x42)]).cpu.pc, 0x204);
+        assert_eq!(exec(0x3142, &[(1, 0x41)]).cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn sne2_skips_when_not_equal() {
+        assert_eq!(exec(0x4142, &[(1, 0x41)]).cpu.pc, 0x204);
+        assert_eq!(exec(0x4142, &[(1, 0x42)]).cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn sev_skips_when_the_registers_are_equal() {
+        assert_eq!(exec(0x5120, &[(1, 7), (2, 7)]).cpu.pc, 0x204);
+        assert_eq!(exec(0x5120, &[(1, 7), (2, 8)]).cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn sne_skips_when_the_registers_differ() {
+        assert_eq!(exec(0x9120, &[(1, 7), (2, 8)]).cpu.pc, 0x204);
+        assert_eq!(exec(0x9120, &[(1, 7), (2, 7)]).cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn ld_sets_the_register() {
+        assert_eq!(exec(0x6A5C, &[]).cpu.v[0xA], 0x5C);
+    }
+
+    #[test]
+    fn add_wraps_without_touching_vf() {
+        let machine = exec(0x7102, &[(1, 0xFF), (0xF, 0x33)]);
+        assert_eq!(machine.cpu.v[1], 0x01);
+        assert_eq!(machine.cpu.v[0xF], 0x33);
+    }
+
+    #[test]
+    fn ldr_copies_the_register() {
+        assert_eq!(exec(0x8120, &[(2, 0x99)]).cpu.v[1], 0x99);
+    }
+
+    #[test]
+    fn bitwise_operations() {
+        let registers = [(1, 0b1100), (2, 0b1010), (0xF, 0x55)];
+        let or = exec(0x8121, &registers);
+        assert_eq!(or.cpu.v[1], 0b1110);
+        let and = exec(0x8122, &registers);
+        assert_eq!(and.cpu.v[1], 0b1000);
+        let xor = exec(0x8123, &registers);
+        assert_eq!(xor.cpu.v[1], 0b0110);
+        // VF is not reset by the bitwise operations
+        for machine in [or, and, xor] {
+            assert_eq!(machine.cpu.v[0xF], 0x55);
+        }
+    }
+
+    #[test]
+    fn add2_sets_vf_on_carry() {
+        let carry = exec(0x8124, &[(1, 0xF0), (2, 0x20)]);
+        assert_eq!(carry.cpu.v[1], 0x10);
+        assert_eq!(carry.cpu.v[0xF], 1);
+
+        let no_carry = exec(0x8124, &[(1, 0xF0), (2, 0x0F), (0xF, 1)]);
+        assert_eq!(no_carry.cpu.v[1], 0xFF);
+        assert_eq!(no_carry.cpu.v[0xF], 0);
+
+        let exact = exec(0x8124, &[(1, 0xFF), (2, 0x01)]);
+        assert_eq!(exact.cpu.v[1], 0x00);
+        assert_eq!(exact.cpu.v[0xF], 1);
+    }
+
+    #[test]
+    fn sub_clears_vf_on_borrow() {
+        let borrow = exec(0x8125, &[(1, 0x10), (2, 0x20)]);
+        assert_eq!(borrow.cpu.v[1], 0xF0);
+        assert_eq!(borrow.cpu.v[0xF], 0);
+
+        let no_borrow = exec(0x8125, &[(1, 0x20), (2, 0x10)]);
+        assert_eq!(no_borrow.cpu.v[1], 0x10);
+        assert_eq!(no_borrow.cpu.v[0xF], 1);
+
+        // equal values do not borrow
+        let equal = exec(0x8125, &[(1, 0x20), (2, 0x20)]);
+        assert_eq!(equal.cpu.v[1], 0x00);
+        assert_eq!(equal.cpu.v[0xF], 1);
+    }
+
+    #[test]
+    fn subn_clears_vf_on_borrow() {
+        let borrow = exec(0x8127, &[(1, 0x20), (2, 0x10)]);
+        assert_eq!(borrow.cpu.v[1], 0xF0);
+        assert_eq!(borrow.cpu.v[0xF], 0);
+
+        let no_borrow = exec(0x8127, &[(1, 0x10), (2, 0x20)]);
+        assert_eq!(no_borrow.cpu.v[1], 0x10);
+        assert_eq!(no_borrow.cpu.v[0xF], 1);
+
+        let equal = exec(0x8127, &[(1, 0x20), (2, 0x20)]);
+        assert_eq!(equal.cpu.v[1], 0x00);
+        assert_eq!(equal.cpu.v[0xF], 1);
+    }
+
+    #[test]
+    fn arithmetic_flag_wins_over_the_result_in_vf() {
+        // VF is written after the result, so VF holds the flag when x = F
+        assert_eq!(exec(0x8F14, &[(0xF, 0xF0), (1, 0x20)]).cpu.v[0xF], 1);
+        assert_eq!(exec(0x8F14, &[(0xF, 0x10), (1, 0x20)]).cpu.v[0xF], 0);
+        assert_eq!(exec(0x8F15, &[(0xF, 0x10), (1, 0x20)]).cpu.v[0xF], 0);
+        assert_eq!(exec(0x8F15, &[(0xF, 0x30), (1, 0x20)]).cpu.v[0xF], 1);
+        assert_eq!(exec(0x8F17, &[(0xF, 0x30), (1, 0x20)]).cpu.v[0xF], 0);
+        assert_eq!(exec(0x8F17, &[(0xF, 0x10), (1, 0x20)]).cpu.v[0xF], 1);
+    }
+
+    #[test]
+    fn shift_result_wins_over_the_flag_in_vf() {
+        // VF is written before the result, so VF holds the shifted value when x = F
+        assert_eq!(exec(0x8F16, &[(1, 0x05)]).cpu.v[0xF], 0x02);
+        assert_eq!(exec(0x8F1E, &[(1, 0x81)]).cpu.v[0xF], 0x02);
+    }
+
+    #[test]
+    fn shr_shifts_vy_into_vx() {
+        let machine = exec(0x8126, &[(1, 0xFF), (2, 0x05)]);
+        assert_eq!(machine.cpu.v[1], 0x02);
+        assert_eq!(machine.cpu.v[2], 0x05);
+        assert_eq!(machine.cpu.v[0xF], 1);
+
+        let even = exec(0x8126, &[(1, 0xFF), (2, 0x04)]);
+        assert_eq!(even.cpu.v[1], 0x02);
+        assert_eq!(even.cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn shl_shifts_vy_into_vx() {
+        let machine = exec(0x812E, &[(1, 0x01), (2, 0x81)]);
+        assert_eq!(machine.cpu.v[1], 0x02);
+        assert_eq!(machine.cpu.v[2], 0x81);
+        assert_eq!(machine.cpu.v[0xF], 1);
+
+        let low = exec(0x812E, &[(1, 0xFF), (2, 0x41)]);
+        assert_eq!(low.cpu.v[1], 0x82);
+        assert_eq!(low.cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn shifts_ignore_vy_with_the_quirk() {
+        for (opcode, result, flag) in [(0x8126, 0x7F, 1), (0x812E, 0xFE, 1)] {
+            let mut machine = Machine::new(&[opcode]);
+            machine.settings.shift_vx_ignore_vy = true;
+            machine.cpu.v[1] = 0xFF;
+            machine.cpu.v[2] = 0x00;
+            machine.run(1);
+            assert_eq!(machine.cpu.v[1], result);
+            assert_eq!(machine.cpu.v[0xF], flag);
+        }
+    }
+
+    #[test]
+    fn lda_sets_i() {
+        assert_eq!(exec(0xA123, &[]).cpu.i, 0x123);
+    }
+
+    #[test]
+    fn jpv_adds_v0() {
+        assert_eq!(exec(0xB300, &[(0, 0x12)]).cpu.pc, 0x312);
+        // the register in the second nibble is ignored
+        assert_eq!(exec(0xB300, &[(0, 0x12), (3, 0x40)]).cpu.pc, 0x312);
+    }
+
+    #[test]
+    fn rnd_masks_the_random_byte() {
+        assert_eq!(exec(0xC100, &[(1, 0xFF)]).cpu.v[1], 0);
+        for _ in 0..32 {
+            assert_eq!(exec(0xC10F, &[]).cpu.v[1] & 0xF0, 0);
+        }
+        // the same seed gives the same numbers
+        assert_eq!(exec(0xC1FF, &[]).cpu.v[1], exec(0xC1FF, &[]).cpu.v[1]);
+    }
+
+    #[test]
+    fn drw_draws_a_sprite() {
+        // the sprite of the digit 0 at (2, 3)
+        let machine = exec(0xD125, &[(1, 2), (2, 3)]);
+        let rows = [0xF0u8, 0x90, 0x90, 0x90, 0xF0];
+        for (dy, row) in rows.iter().enumerate() {
+            for dx in 0..8 {
+                let lit = row >> (7 - dx) & 1 == 1;
+                assert_eq!(machine.display.pixel(2 + dx, 3 + dy), lit);
+            }
+        }
+        assert_eq!(machine.display.lit(), 14);
+        assert_eq!(machine.cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn drw_sets_vf_on_collision() {
+        // the same sprite twice erases it
+        let mut machine = Machine::new(&[0xD011, 0xD011, 0xD011]);
+        machine.run(1);
+        assert_eq!(machine.cpu.v[0xF], 0);
+        machine.run(1);
+        assert_eq!(machine.cpu.v[0xF], 1);
+        assert_eq!(machine.display.lit(), 0);
+        // drawing on a blank screen clears VF again
+        machine.run(1);
+        assert_eq!(machine.cpu.v[0xF], 0);
+    }
+
+    #[test]
+    fn drw_wraps_horizontally() {
+        let machine = exec(0xD121, &[(1, 60), (2, 0)]);
+        // the top row of digit 0 is 11110000
+        assert!((60..64).all(|x| machine.display.pixel(x, 0)));
+        assert!(!(0..4).any(|x| machine.display.pixel(x, 0)));
+
+        let mut wrapped = Machine::new(&[0xD121]);
+        wrapped.memory.write(0, 0xFF);
+        wrapped.cpu.v[1] = 60;
+        wrapped.run(1);
+        assert!((0..4).all(|x| wrapped.display.pixel(x, 0)));
+    }
+
+    #[test]
+    fn drw_clips_vertically_unless_wrapping() {
+        let clipped = exec(0xD125, &[(1, 0), (2, 30)]);
+        assert_eq!(clipped.display.lit(), 4 + 2);
+        assert!(!clipped.display.pixel(0, 0));
+
+        let mut wrapped = Machine::new(&[0xD125]);
+        wrapped.settings.vertical_wrap = true;
+        wrapped.cpu.v[2] = 30;
+        wrapped.run(1);
+        assert_eq!(wrapped.display.lit(), 14);
+        assert!(wrapped.display.pixel(0, 0));
+    }
+
+    #[test]
+    fn skp_and_sknp_check_the_key() {
+        for (pressed, skp, sknp) in [(true, 0x204, 0x202), (false, 0x202, 0x204)] {
+            for (opcode, pc) in [(0xE19E, skp), (0xE1A1, sknp)] {
+                let mut machine = Machine::new(&[opcode]);
+                machine.cpu.v[1] = 0xA;
+                if pressed {
+                    machine.keyboard.press_key(0xA);
+                }
+                machine.run(1);
+                assert_eq!(machine.cpu.pc, pc);
+            }
+        }
+    }
+
+    #[test]
+    fn ldk_waits_for_a_key_press() {
+        let mut machine = Machine::new(&[0xF30A]);
+        // a key held before the wait does not count
+        machine.keyboard.press_key(0x1);
+        machine.run(1);
+        assert_eq!(machine.cpu.pc, 0x200);
+        machine.run(1);
+        assert_eq!(machine.cpu.pc, 0x200);
+
+        machine.keyboard.press_key(0xB);
+        machine.run(1);
+        assert_eq!(machine.cpu.v[3], 0xB);
+        assert_eq!(machine.cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn ldk_ignores_a_key_released_before_it_is_read() {
+        let mut machine = Machine::new(&[0xF30A]);
+        machine.run(1);
+        machine.keyboard.press_key(0x4);
+        machine.keyboard.release_key(0x4);
+        machine.run(1);
+        assert_eq!(machine.cpu.pc, 0x200);
+    }
+
+    #[test]
+    fn timers_are_loaded_and_read() {
+        let mut machine = Machine::new(&[0xF115, 0xF218, 0xF307]);
+        machine.cpu.v[1] = 3;
+        machine.cpu.v[2] = 2;
+        machine.run(2);
+        assert_eq!(machine.cpu.dt, 3);
+        assert_eq!(machine.cpu.st, 2);
+
+        machine.cpu.cycle_dt();
+        machine.run(1);
+        assert_eq!(machine.cpu.v[3], 2);
+    }
+
+    #[test]
+    fn timers_count_down_to_zero() {
+        let mut cpu = Cpu::new();
+        cpu.dt = 1;
+        cpu.st = 2;
+        cpu.cycle_dt();
+        cpu.cycle_dt();
+        assert_eq!(cpu.dt, 0);
+        // the sound timer reports whether it is still active
+        assert!(cpu.cycle_st());
+        assert!(!cpu.cycle_st());
+        assert!(!cpu.cycle_st());
+        assert_eq!(cpu.st, 0);
+    }
+
+    #[test]
+    fn addi_adds_vx_to_i() {
+        let mut machine = Machine::new(&[0xF11E]);
+        machine.cpu.i = 0x0FFF;
+        machine.cpu.v[1] = 0x02;
+        machine.cpu.v[0xF] = 0x77;
+        machine.run(1);
+        assert_eq!(machine.cpu.i, 0x1001);
+        assert_eq!(machine.cpu.v[0xF], 0x77);
+    }
+
+    #[test]
+    fn addi_sets_vf_on_overflow_with_the_quirk() {
+        for (i, flag) in [(0xFFFF, 1), (0x0FFF, 0)] {
+            let mut machine = Machine::new(&[0xF11E]);
+            machine.settings.set_vf_when_overflow = true;
+            machine.cpu.i = i;
+            machine.cpu.v[1] = 0x01;
+            machine.cpu.v[0xF] = 0x77;
+            machine.run(1);
+            assert_eq!(machine.cpu.i, i.wrapping_add(1));
+            assert_eq!(machine.cpu.v[0xF], flag);
+        }
+    }
+
+    #[test]
+    fn ldx_points_i_to_the_digit_sprite() {
+        let machine = exec(0xF129, &[(1, 0xA)]);
+        assert_eq!(machine.cpu.i, 50);
+        assert_eq!(machine.memory.read8(machine.cpu.i), 0xF0);
+    }
+
+    #[test]
+    fn ldb_stores_the_decimal_digits() {
+        for (value, digits) in [
+            (234, [2, 3, 4]),
+            (7, [0, 0, 7]),
+            (100, [1, 0, 0]),
+            (255, [2, 5, 5]),
+        ] {
+            let mut machine = Machine::new(&[0xF133]);
+            machine.cpu.i = 0x300;
+            machine.cpu.v[1] = value;
+            machine.run(1);
+            let stored: Vec<u8> = (0..3)
+                .map(|offset| machine.memory.read8(0x300 + offset))
+                .collect();
+            assert_eq!(stored, digits);
+            assert_eq!(machine.cpu.i, 0x300);
+        }
+    }
+
+    #[test]
+    fn ldi_and_ldj_store_and_load_the_registers() {
+        for (increment, i) in [(false, 0x300), (true, 0x303)] {
+            let mut machine = Machine::new(&[0xF255, 0xF265]);
+            machine.settings.increment_i_register = increment;
+            machine.cpu.i = 0x300;
+            machine.cpu.v[..4].copy_from_slice(&[1, 2, 3, 4]);
+            machine.run(1);
+            let stored: Vec<u8> = (0..4)
+                .map(|offset| machine.memory.read8(0x300 + offset))
+                .collect();
+            // only V0 to V2 are stored
+            assert_eq!(stored, [1, 2, 3, 0]);
+            assert_eq!(machine.cpu.i, i);
+
+            machine.cpu.i = 0x301;
+            machine.run(1);
+            assert_eq!(&machine.cpu.v[..4], &[2, 3, 0, 4]);
+            assert_eq!(machine.cpu.i, i + 1);
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::cfg::Cfg;
+use crate::chip8::memory::PROGRAM_START;
+use crate::chip8::{Fault, Op, CHIP8};
+
+const HELP: &str = "\
+commands:
+    c, continue        resume running
+    p, pause           pause running
+    s, step [n]        execute n instructions (default 1)
+    f, frame           run to the start of the next frame
+    b, back [n]        step back n instructions (default 1)
+    bf, backframe      step back to the start of the previous frame
+    bc, backcollision  step back to the last DRW instruction which set VF
+    r, regs            print the registers
+    block              print the basic block of the next instruction and where it continues
+    break <addr>       pause when the program counter reaches the address
+    delete <addr>      remove a breakpoint
+    watch <expr>       pause when the value of Vx, I or the byte at an address changes
+    unwatch <expr>     remove a watch
+    addresses are hexadecimal or symbols, optionally with an offset like `draw_player+0x4`
+    q, quit            exit the emulator
+    h, help            print this help";
+
+/// debugger command
+enum Command {
+    Continue,
+    Pause,
+    Step(u64),
+    Frame,
+    Back(u64),
+    BackFrame,
+    BackCollision,
+    Registers,
+    Block,
+    Break(String),
+    Delete(String),
+    Watch(String),
+    Unwatch(String),
+    Quit,
+    Help,
+}
+
+impl Command {
+    /// parse a command line
+    fn parse(line: &str) -> Result<Command, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let arg = words.next();
+
+        let count = || match arg {
+            Some(n) => n
+                .parse::<u64>()
+                .map_err(|_| format!("invalid count: {}", n)),
+            None => Ok(1),
+        };
+        let expression = || match arg {
+            Some(e) => Ok(e.to_string()),
+            None => Err(String::from("missing address")),
+        };
+
+        match name {
+            "c" | "continue" => Ok(Command::Continue),
+            "p" | "pause" => Ok(Command::Pause),
+            "s" | "step" => Ok(Command::Step(count()?)),
+            "f" | "frame" => Ok(Command::Frame),
+            "b" | "back" => Ok(Command::Back(count()?)),
+            "bf" | "backframe" => Ok(Command::BackFrame),
+            "bc" | "backcollision" => Ok(Command::BackCollision),
+            "r" | "regs" => Ok(Command::Registers),
+            "block" => Ok(Command::Block),
+            "break" => Ok(Command::Break(expression()?)),
+            "delete" => Ok(Command::Delete(expression()?)),
+            "watch" => Ok(Command::Watch(expression()?)),
+            "unwatch" => Ok(Command::Unwatch(expression()?)),
+            "q" | "quit" => Ok(Command::Quit),
+            "h" | "help" => Ok(Command::Help),
+            _ => Err(format!("unknown command: {}", name)),
+        }
+    }
+}
+
+/// a value which pauses the emulator when it changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Watch {
+    Register(u8), // the register Vx
+    Index,        // the register I
+    Memory(u16),  // the byte at an address
+}
+
+impl Watch {
+    /// parse a watch expression, `V3`, `I` or an address expression
+    fn parse(expression: &str, chip8: &CHIP8) -> Result<Watch, String> {
+        let register = expression
+            .strip_prefix(['v', 'V'])
+            .filter(|reg| reg.len() == 1)
+            .and_then(|reg| u8::from_str_radix(reg, 16).ok());
+        match register {
+            Some(reg) => Ok(Watch::Register(reg)),
+            None if expression.eq_ignore_ascii_case("i") => Ok(Watch::Index),
+            None => chip8.symbols.resolve(expression).map(Watch::Memory),
+        }
+    }
+
+    /// return the current value
+    fn value(&self, chip8: &CHIP8) -> u16 {
+        match *self {
+            Watch::Register(reg) => chip8.cpu.v(reg) as u16,
+            Watch::Index => chip8.cpu.i(),
+            Watch::Memory(address) => chip8.memory.read8(address) as u16,
+        }
+    }
+
+    /// describe the watched value
+    fn describe(&self, chip8: &CHIP8) -> String {
+        match *self {
+            Watch::Register(reg) => format!("V{:X}", reg),
+            Watch::Index => String::from("I"),
+            Watch::Memory(address) => format!("[{}]", chip8.symbols.describe(address)),
+        }
+    }
+}
+
+/// interactive debugger reading commands from stdin
+pub struct Debugger {
+    commands: Receiver<String>, // command lines read by the stdin thread
+    paused: bool,
+    breakpoints: Vec<u16>,
+    watches: Vec<(Watch, u16)>, // watched values with the last value seen
+}
+
+impl Debugger {
+    /// create a debugger instance, the emulator starts paused
+    pub fn new() -> Debugger {
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        println!("DEBUG: Paused, enter `help` to list the commands.");
+        Debugger {
+            commands,
+            paused: true,
+            breakpoints: vec![],
+            watches: vec![],
+        }
+    }
+
+    /// check whether the emulator is paused
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// execute the pending commands
+    /// return false if the emulator should quit
+    pub fn update(&mut self, chip8: &mut CHIP8) -> bool {
+        loop {
+            let line = match self.commands.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return !self.paused,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match Command::parse(&line) {
+                Ok(Command::Quit) => return false,
+                Ok(command) => self.execute(command, chip8),
+                Err(err) => println!("DEBUG: {}.", err),
+            }
+        }
+    }
+
+    /// pause the emulator if the program counter reached a breakpoint or a watched value changed
+    pub fn check(&mut self, chip8: &CHIP8) {
+        if self.stop(chip8) {
+            self.paused = true;
+            Debugger::print_location(chip8);
+        }
+    }
+
+    /// pause the emulator on a fault of the program, the faulting instruction is the next one
+    pub fn fault(&mut self, chip8: &CHIP8, fault: Fault) {
+        println!("DEBUG: {}.", fault);
+        self.paused = true;
+        Debugger::print_location(chip8);
+    }
+
+    /// execute one cycle, return false if it faulted
+    fn step(chip8: &mut CHIP8) -> bool {
+        match chip8.step() {
+            Ok(()) => true,
+            Err(fault) => {
+                println!("DEBUG: {}.", fault);
+                false
+            }
+        }
+    }
+
+    /// report the breakpoints reached and the watched values changed, return true if any
+    fn stop(&mut self, chip8: &CHIP8) -> bool {
+        let mut stop = false;
+        if self.breakpoints.contains(&chip8.cpu.pc()) {
+            println!("DEBUG: Breakpoint reached.");
+            stop = true;
+        }
+        for (watch, last) in self.watches.iter_mut() {
+            let value = watch.value(chip8);
+            if value != *last {
+                println!(
+                    "DEBUG: {} changed from {:02X} to {:02X}.",
+                    watch.describe(chip8),
+                    last,
+                    value
+                );
+                *last = value;
+                stop = true;
+            }
+        }
+        stop
+    }
+
+    fn execute(&mut self, command: Command, chip8: &mut CHIP8) {
+        match command {
+            Command::Continue => {
+                // running live from a replayed past starts a new timeline
+                chip8.history.truncate(chip8.cycles);
+                self.paused = false;
+                return;
+            }
+            Command::Pause => self.paused = true,
+            Command::Step(n) => {
+                self.paused = true;
+                for _ in 0..n {
+                    if !Debugger::step(chip8) || self.stop(chip8) {
+                        break;
+                    }
+                }
+            }
+            Command::Frame => {
+                self.paused = true;
+                match chip8.history.next_frame(chip8.cycles) {
+                    Some(cycle) => while chip8.cycles < cycle && Debugger::step(chip8) {},
+                    None => {
+                        // no recorded frame ahead, emulate the timers of a new frame
+                        if let Err(fault) = chip8.run_frame() {
+                            println!("DEBUG: {}.", fault);
+                        }
+                    }
+                }
+            }
+            Command::Back(n) => {
+                self.paused = true;
+                let cycle = chip8.cycles.saturating_sub(n);
+                if cycle < chip8.history.oldest() || !chip8.rewind(cycle) {
+                    println!("DEBUG: Cycle {} is no longer in the history.", cycle);
+                }
+            }
+            Command::BackFrame => {
+                self.paused = true;
+                match chip8.history.previous_frame(chip8.cycles) {
+                    Some(cycle) if chip8.rewind(cycle) => {}
+                    _ => println!("DEBUG: No previous frame in the history."),
+                }
+            }
+            Command::BackCollision => {
+                self.paused = true;
+                match chip8.history.previous_collision(chip8.cycles) {
+                    Some(cycle) if chip8.rewind(cycle) => {}
+                    _ => println!("DEBUG: No collision in the history."),
+                }
+            }
+            Command::Registers => {}
+            Command::Block => {
+                Debugger::print_block(chip8);
+                return;
+            }
+            Command::Break(expression) => {
+                match chip8.symbols.resolve(&expression) {
+                    Ok(address) => {
+                        if !self.breakpoints.contains(&address) {
+                            self.breakpoints.push(address);
+                        }
+                        println!(
+                            "DEBUG: Breakpoint set at {}.",
+                            chip8.symbols.describe(address)
+                        );
+                    }
+                    Err(err) => println!("DEBUG: {}.", err),
+                }
+                return;
+            }
+            Command::Delete(expression) => {
+                match chip8.symbols.resolve(&expression) {
+                    Ok(address) => {
+                        self.breakpoints.retain(|&a| a != address);
+                        println!(
+                            "DEBUG: Breakpoint removed at {}.",
+                            chip8.symbols.describe(address)
+                        );
+                    }
+                    Err(err) => println!("DEBUG: {}.", err),
+                }
+                return;
+            }
+            Command::Watch(expression) => {
+                match Watch::parse(&expression, chip8) {
+                    Ok(watch) => {
+                        let value = watch.value(chip8);
+                        if !self.watches.iter().any(|&(w, _)| w == watch) {
+                            self.watches.push((watch, value));
+                        }
+                        println!("DEBUG: Watching {} = {:02X}.", watch.describe(chip8), value);
+                    }
+                    Err(err) => println!("DEBUG: {}.", err),
+                }
+                return;
+            }
+            Command::Unwatch(expression) => {
+                match Watch::parse(&expression, chip8) {
+                    Ok(watch) => {
+                        self.watches.retain(|&(w, _)| w != watch);
+                        println!("DEBUG: Watch removed on {}.", watch.describe(chip8));
+                    }
+                    Err(err) => println!("DEBUG: {}.", err),
+                }
+                return;
+            }
+            Command::Quit => return,
+            Command::Help => {
+                println!("{}", HELP);
+                return;
+            }
+        }
+        // the watched values may have moved with the machine, only later changes pause it
+        for (watch, last) in self.watches.iter_mut() {
+            *last = watch.value(chip8);
+        }
+        Debugger::print_location(chip8);
+    }
+
+    /// print the basic block of the next instruction, analysing the memory as it is now
+    fn print_block(chip8: &CHIP8) {
+        let memory: Vec<u8> = (PROGRAM_START..0x1000)
+            .map(|address| chip8.memory.read8(address))
+            .collect();
+        let pc = chip8.cpu.pc();
+        let cfg = Cfg::with_entries(&memory, &[pc]);
+        let block = match cfg.block_at(pc) {
+            Some(block) => block,
+            None => {
+                println!("DEBUG: No instruction at {:04X}.", pc);
+                return;
+            }
+        };
+
+        for &(address, op) in block.instructions.iter() {
+            println!(
+                "DEBUG: {} {}: {}",
+                if address == pc { "=>" } else { "  " },
+                chip8.symbols.describe(address),
+                chip8.symbols.instruction(op)
+            );
+        }
+        for edge in block.successors.iter() {
+            println!(
+                "DEBUG: {} -> {}",
+                edge.kind.name(),
+                chip8.symbols.describe(edge.target)
+            );
+        }
+        if let Some(base) = block.unresolved {
+            println!("DEBUG: unresolved -> {} + V0", chip8.symbols.describe(base));
+        }
+    }
+
+    /// print the cycle, the registers and the next instruction
+    fn print_location(chip8: &CHIP8) {
+        let pc = chip8.cpu.pc();
+        let opcode = chip8.memory.read16(pc);
+        println!("DEBUG: cycle {} {}", chip8.cycles, chip8.cpu);
+        println!(
+            "DEBUG: {}: {:04X} {}",
+            chip8.symbols.describe(pc),
+            opcode.get_opcode(),
+            Op::decode(&opcode).map_or(String::from("???"), |op| chip8.symbols.instruction(op))
+        );
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::Symbols;
+
+    /// run the command lines in a debugger which reads no commands from stdin
+    fn debugger(chip8: &mut CHIP8, lines: &[&str]) -> Debugger {
+        let mut debugger = Debugger {
+            commands: mpsc::channel().1,
+            paused: true,
+            breakpoints: vec![],
+            watches: vec![],
+        };
+        for line in lines {
+            debugger.execute(Command::parse(line).unwrap(), chip8);
+        }
+        debugger
+    }
+
+    #[test]
+    fn breakpoints_and_watches_resolve_symbols() {
+        let mut chip8 = CHIP8::new("TEST");
+        chip8.symbols = Symbols::parse("main = 0x200\nsprite = 0x300\n").unwrap();
+        let debugger = debugger(
+            &mut chip8,
+            &[
+                "break main+4",
+                "break 0x210",
+                "break nowhere",
+                "delete 0x210",
+                "watch sprite+0x2",
+                "watch V3",
+                "watch I",
+                "watch nowhere",
+            ],
+        );
+        assert_eq!(debugger.breakpoints, [0x204]);
+        let watches: Vec<Watch> = debugger.watches.iter().map(|&(watch, _)| watch).collect();
+        assert_eq!(
+            watches,
+            [Watch::Memory(0x302), Watch::Register(3), Watch::Index]
+        );
+    }
+}
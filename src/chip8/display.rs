@@ -1,4 +1,4 @@
-use crate::chip8::Manager;
+use crate::chip8::fnv1a;
 
 // the chip-8 uses a 64x32-pixel monochrome display with this format:
 // (0,0)	       (63,0)
@@ -9,6 +9,7 @@ const DISPLAY_WIDTH: usize = 64;
 const DISPLAY_HEIGHT: usize = 32;
 
 /// screen display
+#[derive(Clone)]
 pub struct Display {
     pixels: [[bool; DISPLAY_HEIGHT]; DISPLAY_WIDTH], // pixel matrix
     redraw: bool,
@@ -23,11 +24,21 @@ impl Display {
         }
     }
 
+    /// return the screen width
+    pub fn display_width() -> usize {
+        DISPLAY_WIDTH
+    }
+
     /// return the screen height
     pub fn display_height() -> usize {
         DISPLAY_HEIGHT
     }
 
+    /// check whether the pixel on the (x, y) is lit
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[x % DISPLAY_WIDTH][y % DISPLAY_HEIGHT]
+    }
+
     /// set pixel on the (x, y)
     /// return a bool value to indicate whether the pixel is erased
     pub fn set_pixel(&mut self, x: usize, y: usize, pixel: bool) -> bool {
@@ -44,26 +55,47 @@ impl Display {
         pixel_erased
     }
 
+    /// return the pixels of a row, the leftmost one in the highest bit
+    pub fn row(&self, y: usize) -> u64 {
+        (0..DISPLAY_WIDTH).fold(0, |row, x| row << 1 | self.pixel(x, y) as u64)
+    }
+
     /// clear the display
     pub fn clear(&mut self) {
         self.pixels = [[false; DISPLAY_HEIGHT]; DISPLAY_WIDTH];
     }
 
+    /// force the display to be redrawn
+    pub fn refresh(&mut self) {
+        self.redraw = true;
+    }
+
+    /// return the FNV-1a hash of the pixels, one byte (0 or 1) per pixel row by row
+    pub fn hash(&self) -> u32 {
+        fnv1a(
+            (0..DISPLAY_HEIGHT)
+                .flat_map(|y| (0..DISPLAY_WIDTH).map(move |x| self.pixels[x][y] as u8)),
+        )
+    }
+
+    /// return the number of lit pixels
+    pub fn lit(&self) -> usize {
+        self.pixels.iter().flatten().filter(|&&pixel| pixel).count()
+    }
+
+    /// return the number of pixels of the screen
+    pub fn size() -> usize {
+        DISPLAY_WIDTH * DISPLAY_HEIGHT
+    }
+
     /// check whether to redraw
     pub fn redraw(&self) -> bool {
         self.redraw
     }
 
-    pub fn draw(&mut self, manager: &mut Manager) -> Result<(), String> {
-        for y in 0..DISPLAY_HEIGHT {
-            for x in 0..DISPLAY_WIDTH {
-                if self.pixels[x][y] {
-                    manager.draw_pixel(x as u8, y as u8)?
-                }
-            }
-        }
+    /// mark the pixels as presented, until they change again
+    pub fn presented(&mut self) {
         self.redraw = false;
-        Ok(())
     }
 }
 
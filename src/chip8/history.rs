@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+
+use crate::chip8::{Cpu, Display, KeyBoard, Memory};
+
+/// cycles between two snapshots
+const SNAPSHOT_INTERVAL: u64 = 500;
+
+/// the maximum number of snapshots kept, older ones are dropped
+const SNAPSHOT_CAPACITY: usize = 1200;
+
+/// everything that changes the machine state besides the cpu itself
+#[derive(Clone, Copy, Debug)]
+pub enum Input {
+    KeyDown(u8), // a key was pressed
+    KeyUp(u8),   // a key was released
+    DelayTimer,  // the delay timer ticked, it also marks the start of a frame
+    SoundTimer,  // the sound timer ticked
+}
+
+/// the state of the machine before a cycle was executed and its inputs applied
+#[derive(Clone)]
+pub struct Snapshot {
+    pub cycle: u64,
+    pub cpu: Cpu,
+    pub memory: Memory,
+    pub display: Display,
+    pub keyboard: KeyBoard,
+}
+
+/// the recorded past of the machine: periodic snapshots plus every input applied since the oldest one.
+/// any earlier cycle can be restored exactly by re-executing from the nearest snapshot
+pub struct History {
+    snapshots: VecDeque<Snapshot>,
+    inputs: VecDeque<(u64, Input)>, // inputs with the cycle they were applied before
+    collisions: VecDeque<u64>,      // cycles of the DRW instructions which set VF
+    head: u64,                      // the latest cycle reached
+}
+
+impl History {
+    /// create a history instance
+    pub fn new() -> History {
+        History {
+            snapshots: VecDeque::new(),
+            inputs: VecDeque::new(),
+            collisions: VecDeque::new(),
+            head: 0,
+        }
+    }
+
+    /// check whether a snapshot should be taken before executing the cycle
+    pub fn need_snapshot(&self, cycle: u64) -> bool {
+        cycle.is_multiple_of(SNAPSHOT_INTERVAL)
+            && self.snapshots.back().is_none_or(|s| s.cycle < cycle)
+    }
+
+    /// keep a snapshot, drop the oldest one and the inputs before it if the history is full
+    pub fn push_snapshot(&mut self, snapshot: Snapshot) {
+        self.snapshots.push_back(snapshot);
+        if self.snapshots.len() > SNAPSHOT_CAPACITY {
+            self.snapshots.pop_front();
+            let oldest = self.oldest();
+            while self.inputs.front().is_some_and(|&(c, _)| c < oldest) {
+                self.inputs.pop_front();
+            }
+            while self.collisions.front().is_some_and(|&c| c < oldest) {
+                self.collisions.pop_front();
+            }
+        }
+    }
+
+    /// record an input applied before the cycle
+    pub fn push_input(&mut self, cycle: u64, input: Input) {
+        self.inputs.push_back((cycle, input));
+    }
+
+    /// record a DRW instruction executed at the cycle which set VF
+    pub fn push_collision(&mut self, cycle: u64) {
+        self.collisions.push_back(cycle);
+    }
+
+    /// mark the cycle as reached
+    pub fn advance(&mut self, cycle: u64) {
+        self.head = self.head.max(cycle);
+    }
+
+    /// return the latest cycle reached
+    pub fn head(&self) -> u64 {
+        self.head
+    }
+
+    /// return the oldest cycle that can be restored
+    pub fn oldest(&self) -> u64 {
+        self.snapshots.front().map_or(0, |s| s.cycle)
+    }
+
+    /// return the nearest snapshot taken at or before the cycle
+    pub fn snapshot_before(&self, cycle: u64) -> Option<&Snapshot> {
+        self.snapshots.iter().rev().find(|s| s.cycle <= cycle)
+    }
+
+    /// return the inputs recorded before the cycle, in the order they were applied
+    pub fn inputs_at(&self, cycle: u64) -> impl Iterator<Item = Input> + '_ {
+        self.inputs
+            .iter()
+            .skip_while(move |&&(c, _)| c < cycle)
+            .take_while(move |&&(c, _)| c == cycle)
+            .map(|&(_, input)| input)
+    }
+
+    /// return the cycle where the frame before the cycle began
+    pub fn previous_frame(&self, cycle: u64) -> Option<u64> {
+        self.inputs
+            .iter()
+            .rev()
+            .filter(|(_, input)| matches!(input, Input::DelayTimer))
+            .map(|&(c, _)| c)
+            .find(|&c| c < cycle)
+    }
+
+    /// return the cycle where the frame after the cycle begins
+    pub fn next_frame(&self, cycle: u64) -> Option<u64> {
+        self.inputs
+            .iter()
+            .filter(|(_, input)| matches!(input, Input::DelayTimer))
+            .map(|&(c, _)| c)
+            .find(|&c| c > cycle)
+    }
+
+    /// return the cycle of the latest DRW instruction before the cycle which set VF
+    pub fn previous_collision(&self, cycle: u64) -> Option<u64> {
+        self.collisions.iter().rev().copied().find(|&c| c < cycle)
+    }
+
+    /// forget everything after the cycle, the machine takes another path from there
+    pub fn truncate(&mut self, cycle: u64) {
+        while self.snapshots.back().is_some_and(|s| s.cycle > cycle) {
+            self.snapshots.pop_back();
+        }
+        while self.inputs.back().is_some_and(|&(c, _)| c >= cycle) {
+            self.inputs.pop_back();
+        }
+        while self.collisions.back().is_some_and(|&c| c >= cycle) {
+            self.collisions.pop_back();
+        }
+        self.head = cycle;
+    }
+}
+
+impl Default for History {
+    fn default() -> History {
+        History::new()
+    }
+}
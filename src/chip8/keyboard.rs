@@ -1,4 +1,5 @@
 /// chip8 keyboard
+#[derive(Clone)]
 pub struct KeyBoard {
     keys: [bool; 16], // chip8 has a 16-key hexadecimal keypad
     key_press: Option<u8>,
@@ -15,12 +16,12 @@ impl KeyBoard {
         }
     }
 
-    /// check whether the key is in the down position
+    /// check whether the key is in the down position, only the low nibble of the key is used
     pub fn check_key(&self, key: u8) -> bool {
-        self.keys[key as usize]
+        self.keys[(key & 0x0F) as usize]
     }
 
-    /// set keys
+    // set keys
     // pub fn set_keys(&mut self, keys: [bool; 16]) {
     //     self.keys = keys;
     // }
@@ -47,7 +48,7 @@ impl KeyBoard {
         if self.key_press_wait {
             self.key_press = Some(key);
         }
-        self.keys[key as usize] = true;
+        self.keys[(key & 0x0F) as usize] = true;
     }
 
     /// release the key
@@ -57,7 +58,7 @@ impl KeyBoard {
                 self.key_press = None;
             }
         }
-        self.keys[key as usize] = false;
+        self.keys[(key & 0x0F) as usize] = false;
     }
 }
 
@@ -6,6 +6,26 @@ use sdl2::keyboard::Keycode;
 // A  0  B  F
 // this layout should be mapped to today's platform to fit the keyboard
 
+/// the keys of the computer, as characters, mapped to the keypad
+const LAYOUT: [(char, u8); 16] = [
+    ('1', 0x1),
+    ('2', 0x2),
+    ('3', 0x3),
+    ('4', 0xC),
+    ('q', 0x4),
+    ('w', 0x5),
+    ('e', 0x6),
+    ('r', 0xD),
+    ('a', 0x7),
+    ('s', 0x8),
+    ('d', 0x9),
+    ('f', 0xE),
+    ('z', 0xA),
+    ('x', 0x0),
+    ('c', 0xB),
+    ('v', 0xF),
+];
+
 /// keyboard mapping
 pub struct KeyMap;
 
@@ -37,6 +57,15 @@ impl KeyMap {
             _ => None,
         }
     }
+
+    /// process keyboard mapping of a character typed in a terminal, the same layout as the keycodes
+    pub fn char(&self, c: char) -> Option<u8> {
+        let c = c.to_ascii_lowercase();
+        LAYOUT
+            .iter()
+            .find(|&&(key, _)| key == c)
+            .map(|&(_, value)| value)
+    }
 }
 
 impl Default for KeyMap {
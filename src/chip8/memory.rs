@@ -1,7 +1,13 @@
-use crate::chip8::{io, File, OpCode, Path, Read};
+use crate::chip8::{fnv1a, io, File, OpCode, Path, Read};
 
 /// chip-8 has a 4KB memory
-const MEMORY_SIZE: usize = 4096;
+pub const MEMORY_SIZE: usize = 4096;
+
+/// chip-8 programs start at location 0x200
+pub const PROGRAM_START: u16 = 0x200;
+
+/// chip-8 programs may fill the memory from their start
+pub const MAX_PROGRAM_SIZE: usize = MEMORY_SIZE - PROGRAM_START as usize;
 
 /// the start address of chip-8's sprites in memory
 const SPRITE_ADDRESS: u16 = 0x0000;
@@ -28,6 +34,7 @@ const SPRITES: [[u8; 5]; 16] = [
 ];
 
 /// chip-8 memory
+#[derive(Clone, Debug, PartialEq)]
 pub struct Memory {
     // use an u8 array to emulate chip8 memory
     mem: [u8; MEMORY_SIZE],
@@ -56,9 +63,25 @@ impl Memory {
         let mut rom_data = vec![];
         File::open(path)?.read_to_end(&mut rom_data)?;
 
-        // chip-8 programs start at location 0x200
+        self.load_bytes(&rom_data)
+    }
+
+    /// check whether the program fits in memory
+    pub fn check_program(rom_data: &[u8]) -> io::Result<()> {
+        if rom_data.len() > MAX_PROGRAM_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the program does not fit in memory",
+            ));
+        }
+        Ok(())
+    }
+
+    /// load the program bytes to memory
+    pub fn load_bytes(&mut self, rom_data: &[u8]) -> io::Result<()> {
+        Memory::check_program(rom_data)?;
         rom_data.iter().enumerate().for_each(|(i, &data)| {
-            self.write(i as u16 + 0x200, data);
+            self.write(i as u16 + PROGRAM_START, data);
         });
 
         Ok(())
@@ -69,21 +92,29 @@ impl Memory {
         SPRITE_ADDRESS + (sprite as u16) * 5
     }
 
+    /// return the FNV-1a hash of the memory from 0x200 on, the interpreter area differs between emulators
+    pub fn hash(&self) -> u32 {
+        fnv1a(self.mem[PROGRAM_START as usize..].iter().copied())
+    }
+
+    /// return the address wrapped around the 4KB memory
+    pub fn wrap(address: u16) -> u16 {
+        address % MEMORY_SIZE as u16
+    }
+
     /// read 1 byte data at address
     pub fn read8(&self, address: u16) -> u8 {
-        self.mem[address as usize]
+        self.mem[Memory::wrap(address) as usize]
     }
 
     /// read 2 bytes data at program counter
     pub fn read16(&self, address: u16) -> OpCode {
-        OpCode::new(
-            (self.mem[address as usize] as u16) << 8 | self.mem[(address as usize) + 1] as u16,
-        )
+        OpCode::new((self.read8(address) as u16) << 8 | self.read8(address.wrapping_add(1)) as u16)
     }
 
     /// write data to memory
     pub fn write(&mut self, address: u16, byte: u8) {
-        self.mem[address as usize] = byte;
+        self.mem[Memory::wrap(address) as usize] = byte;
     }
 }
 
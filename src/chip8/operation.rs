@@ -1,5 +1,7 @@
+use std::fmt;
+
 /// store opcode value
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct OpCode(u16);
 
 impl OpCode {
@@ -15,7 +17,7 @@ impl OpCode {
 }
 
 /// opcode type
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Op {
     SYS { address: u16 },     // opcode: 0nnn, jump to a machine code routine at nnn
     CLS,                      // opcode: 00E0, clear the display
@@ -51,14 +53,14 @@ pub enum Op {
     LDX { reg: u8 },  // opcode: Fx29, set I = location of sprite for digit Vx
     LDB { reg: u8 }, // opcode: Fx33, store BCD representation of Vx in memory locations I, I+1, and I+2
     LDI { reg: u8 }, // opcode: Fx55, store registers V0 through Vx in memory starting at location I
-    LDJ { reg: u8 }, // opcode: Fx65, store BCD representation of Vx in memory locations I, I+1, and I+2
+    LDJ { reg: u8 }, // opcode: Fx65, read registers V0 through Vx from memory starting at location I
 }
 
 impl Op {
-    /// decode the type of opcode
-    pub fn decode(opcode: &OpCode) -> Op {
+    /// decode the type of opcode, return None if the opcode is invalid
+    pub fn decode(opcode: &OpCode) -> Option<Op> {
         let opcode = opcode.get_opcode();
-        match opcode & 0xF000 {
+        let op = match opcode & 0xF000 {
             0x0000 => match opcode & 0x0FFF {
                 0x00E0 => Op::cls(),
                 0x00EE => Op::ret(),
@@ -68,7 +70,10 @@ impl Op {
             0x2000 => Op::call(opcode),
             0x3000 => Op::se(opcode),
             0x4000 => Op::sne2(opcode),
-            0x5000 => Op::sev(opcode),
+            0x5000 => match opcode & 0x000F {
+                0x0000 => Op::sev(opcode),
+                _ => return None,
+            },
             0x6000 => Op::ld(opcode),
             0x7000 => Op::add(opcode),
             0x8000 => match opcode & 0x000F {
@@ -81,9 +86,12 @@ impl Op {
                 0x0006 => Op::shr(opcode),
                 0x0007 => Op::subn(opcode),
                 0x000E => Op::shl(opcode),
-                _ => panic!("invalid opcode: {:04X}", opcode),
+                _ => return None,
+            },
+            0x9000 => match opcode & 0x000F {
+                0x0000 => Op::sne(opcode),
+                _ => return None,
             },
-            0x9000 => Op::sne(opcode),
             0xA000 => Op::lda(opcode),
             0xB000 => Op::jpv(opcode),
             0xC000 => Op::rnd(opcode),
@@ -91,7 +99,7 @@ impl Op {
             0xE000 => match opcode & 0x00FF {
                 0x009E => Op::skp(opcode),
                 0x00A1 => Op::sknp(opcode),
-                _ => panic!("invalid opcode: {:04X}", opcode),
+                _ => return None,
             },
             0xF000 => match opcode & 0x00FF {
                 0x0007 => Op::ldt(opcode),
@@ -103,10 +111,98 @@ impl Op {
                 0x0033 => Op::ldb(opcode),
                 0x0055 => Op::ldi(opcode),
                 0x0065 => Op::ldj(opcode),
-                _ => panic!("invalid opcode: {:04X}", opcode),
+                _ => return None,
             },
-            _ => panic!("invalid opcode: {:04X}", opcode),
-        }
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// return the name of the instruction type, e.g. `DRW`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::SYS { .. } => "SYS",
+            Op::CLS => "CLS",
+            Op::RET => "RET",
+            Op::JP { .. } => "JP",
+            Op::CALL { .. } => "CALL",
+            Op::SE { .. } => "SE",
+            Op::SNE2 { .. } => "SNE2",
+            Op::SEV { .. } => "SEV",
+            Op::LD { .. } => "LD",
+            Op::ADD { .. } => "ADD",
+            Op::LDR { .. } => "LDR",
+            Op::OR { .. } => "OR",
+            Op::AND { .. } => "AND",
+            Op::XOR { .. } => "XOR",
+            Op::ADD2 { .. } => "ADD2",
+            Op::SUB { .. } => "SUB",
+            Op::SHR { .. } => "SHR",
+            Op::SUBN { .. } => "SUBN",
+            Op::SHL { .. } => "SHL",
+            Op::SNE { .. } => "SNE",
+            Op::LDA { .. } => "LDA",
+            Op::JPV { .. } => "JPV",
+            Op::RND { .. } => "RND",
+            Op::DRW { .. } => "DRW",
+            Op::SKP { .. } => "SKP",
+            Op::SKNP { .. } => "SKNP",
+            Op::LDT { .. } => "LDT",
+            Op::LDK { .. } => "LDK",
+            Op::LDF { .. } => "LDF",
+            Op::LDS { .. } => "LDS",
+            Op::ADDI { .. } => "ADDI",
+            Op::LDX { .. } => "LDX",
+            Op::LDB { .. } => "LDB",
+            Op::LDI { .. } => "LDI",
+            Op::LDJ { .. } => "LDJ",
+        }
+    }
+
+    /// encode the instruction into its opcode, the inverse of decode
+    pub fn encode(&self) -> OpCode {
+        let x = |reg: u8| ((reg & 0x0F) as u16) << 8;
+        let xy = |reg_x: u8, reg_y: u8| x(reg_x) | ((reg_y & 0x0F) as u16) << 4;
+        let xkk = |reg: u8, byte: u8| x(reg) | byte as u16;
+
+        let opcode = match *self {
+            Op::SYS { address } => address & 0x0FFF,
+            Op::CLS => 0x00E0,
+            Op::RET => 0x00EE,
+            Op::JP { address } => 0x1000 | address & 0x0FFF,
+            Op::CALL { address } => 0x2000 | address & 0x0FFF,
+            Op::SE { reg, byte } => 0x3000 | xkk(reg, byte),
+            Op::SNE2 { reg, byte } => 0x4000 | xkk(reg, byte),
+            Op::SEV { reg_x, reg_y } => 0x5000 | xy(reg_x, reg_y),
+            Op::LD { reg, byte } => 0x6000 | xkk(reg, byte),
+            Op::ADD { reg, byte } => 0x7000 | xkk(reg, byte),
+            Op::LDR { reg_x, reg_y } => 0x8000 | xy(reg_x, reg_y),
+            Op::OR { reg_x, reg_y } => 0x8001 | xy(reg_x, reg_y),
+            Op::AND { reg_x, reg_y } => 0x8002 | xy(reg_x, reg_y),
+            Op::XOR { reg_x, reg_y } => 0x8003 | xy(reg_x, reg_y),
+            Op::ADD2 { reg_x, reg_y } => 0x8004 | xy(reg_x, reg_y),
+            Op::SUB { reg_x, reg_y } => 0x8005 | xy(reg_x, reg_y),
+            Op::SHR { reg_x, reg_y } => 0x8006 | xy(reg_x, reg_y),
+            Op::SUBN { reg_x, reg_y } => 0x8007 | xy(reg_x, reg_y),
+            Op::SHL { reg_x, reg_y } => 0x800E | xy(reg_x, reg_y),
+            Op::SNE { reg_x, reg_y } => 0x9000 | xy(reg_x, reg_y),
+            Op::LDA { address } => 0xA000 | address & 0x0FFF,
+            Op::JPV { address } => 0xB000 | address & 0x0FFF,
+            Op::RND { reg, byte } => 0xC000 | xkk(reg, byte),
+            Op::DRW { reg_x, reg_y, n } => 0xD000 | xy(reg_x, reg_y) | (n & 0x0F) as u16,
+            Op::SKP { reg } => 0xE09E | x(reg),
+            Op::SKNP { reg } => 0xE0A1 | x(reg),
+            Op::LDT { reg } => 0xF007 | x(reg),
+            Op::LDK { reg } => 0xF00A | x(reg),
+            Op::LDF { reg } => 0xF015 | x(reg),
+            Op::LDS { reg } => 0xF018 | x(reg),
+            Op::ADDI { reg } => 0xF01E | x(reg),
+            Op::LDX { reg } => 0xF029 | x(reg),
+            Op::LDB { reg } => 0xF033 | x(reg),
+            Op::LDI { reg } => 0xF055 | x(reg),
+            Op::LDJ { reg } => 0xF065 | x(reg),
+        };
+        OpCode::new(opcode)
     }
 
     fn sys(opcode: u16) -> Op {
@@ -333,3 +429,71 @@ impl Op {
         }
     }
 }
+
+impl fmt::Display for Op {
+    /// format the instruction with the mnemonics of Cowgod's technical reference
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Op::SYS { address } => write!(f, "SYS 0x{:03X}", address),
+            Op::CLS => write!(f, "CLS"),
+            Op::RET => write!(f, "RET"),
+            Op::JP { address } => write!(f, "JP 0x{:03X}", address),
+            Op::CALL { address } => write!(f, "CALL 0x{:03X}", address),
+            Op::SE { reg, byte } => write!(f, "SE V{:X}, 0x{:02X}", reg, byte),
+            Op::SNE2 { reg, byte } => write!(f, "SNE V{:X}, 0x{:02X}", reg, byte),
+            Op::SEV { reg_x, reg_y } => write!(f, "SE V{:X}, V{:X}", reg_x, reg_y),
+            Op::LD { reg, byte } => write!(f, "LD V{:X}, 0x{:02X}", reg, byte),
+            Op::ADD { reg, byte } => write!(f, "ADD V{:X}, 0x{:02X}", reg, byte),
+            Op::LDR { reg_x, reg_y } => write!(f, "LD V{:X}, V{:X}", reg_x, reg_y),
+            Op::OR { reg_x, reg_y } => write!(f, "OR V{:X}, V{:X}", reg_x, reg_y),
+            Op::AND { reg_x, reg_y } => write!(f, "AND V{:X}, V{:X}", reg_x, reg_y),
+            Op::XOR { reg_x, reg_y } => write!(f, "XOR V{:X}, V{:X}", reg_x, reg_y),
+            Op::ADD2 { reg_x, reg_y } => write!(f, "ADD V{:X}, V{:X}", reg_x, reg_y),
+            Op::SUB { reg_x, reg_y } => write!(f, "SUB V{:X}, V{:X}", reg_x, reg_y),
+            Op::SHR { reg_x, reg_y } => write!(f, "SHR V{:X}, V{:X}", reg_x, reg_y),
+            Op::SUBN { reg_x, reg_y } => write!(f, "SUBN V{:X}, V{:X}", reg_x, reg_y),
+            Op::SHL { reg_x, reg_y } => write!(f, "SHL V{:X}, V{:X}", reg_x, reg_y),
+            Op::SNE { reg_x, reg_y } => write!(f, "SNE V{:X}, V{:X}", reg_x, reg_y),
+            Op::LDA { address } => write!(f, "LD I, 0x{:03X}", address),
+            Op::JPV { address } => write!(f, "JP V0, 0x{:03X}", address),
+            Op::RND { reg, byte } => write!(f, "RND V{:X}, 0x{:02X}", reg, byte),
+            Op::DRW { reg_x, reg_y, n } => write!(f, "DRW V{:X}, V{:X}, {}", reg_x, reg_y, n),
+            Op::SKP { reg } => write!(f, "SKP V{:X}", reg),
+            Op::SKNP { reg } => write!(f, "SKNP V{:X}", reg),
+            Op::LDT { reg } => write!(f, "LD V{:X}, DT", reg),
+            Op::LDK { reg } => write!(f, "LD V{:X}, K", reg),
+            Op::LDF { reg } => write!(f, "LD DT, V{:X}", reg),
+            Op::LDS { reg } => write!(f, "LD ST, V{:X}", reg),
+            Op::ADDI { reg } => write!(f, "ADD I, V{:X}", reg),
+            Op::LDX { reg } => write!(f, "LD F, V{:X}", reg),
+            Op::LDB { reg } => write!(f, "LD B, V{:X}", reg),
+            Op::LDI { reg } => write!(f, "LD [I], V{:X}", reg),
+            Op::LDJ { reg } => write!(f, "LD V{:X}, [I]", reg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_inverts_encode() {
+        for opcode in 0..=u16::MAX {
+            if let Some(op) = Op::decode(&OpCode::new(opcode)) {
+                assert_eq!(Op::decode(&op.encode()), Some(op), "{:04X}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_inverts_decode() {
+        for opcode in 0..=u16::MAX {
+            if let Some(op) = Op::decode(&OpCode::new(opcode)) {
+                assert_eq!(op.encode().get_opcode(), opcode, "{:04X}", opcode);
+            }
+        }
+        assert_eq!(Op::decode(&OpCode::new(0x5121)), None);
+        assert_eq!(Op::decode(&OpCode::new(0x9235)), None);
+    }
+}
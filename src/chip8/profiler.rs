@@ -0,0 +1,496 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+use crate::chip8::{Op, OpCode};
+use crate::symbols::Symbols;
+
+/// the number of rows of the hot address, loop and subroutine tables
+const TOP: usize = 20;
+
+/// the longest distance between the first and the last instruction of a loop waiting for the delay timer
+const MAX_WAIT_LOOP: u16 = 8;
+
+/// the deepest call stack followed, programs which never return would grow it forever
+const MAX_CALL_DEPTH: usize = 64;
+
+/// a running minimum, maximum and sum
+#[derive(Default)]
+struct Stats {
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Stats {
+    /// add a sample
+    fn add(&mut self, value: u64) {
+        self.min = if self.count == 0 {
+            value
+        } else {
+            self.min.min(value)
+        };
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// return the average of the samples
+    fn average(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+}
+
+/// the cycles spent in a subroutine
+#[derive(Default)]
+struct Subroutine {
+    calls: u64,
+    inclusive: u64, // cycles from the call to the return, including nested calls
+    exclusive: u64, // cycles of its own instructions
+}
+
+/// counts where a program spends its cycles
+pub struct Profiler {
+    cycles: u64,
+    addresses: BTreeMap<u16, u64>, // executed instructions per address
+    opcodes: BTreeMap<u16, u16>,   // the last opcode executed at each address
+    ops: BTreeMap<&'static str, u64>, // executed instructions per type
+    loops: HashMap<(u16, u16), u64>, // taken backward branches, from and to
+    subroutines: BTreeMap<u16, Subroutine>, // the call targets
+    calls: Vec<(u16, u64)>,        // the call stack, targets with the cycle of the call
+    ldk_waits: u64,                // LDK instructions executed while no key was pressed
+    frame_start: Option<u64>,      // the cycle the current frame began at
+    frame_draws: u64,              // DRW instructions of the current frame
+    frame_waits: u64,              // LDK waits of the current frame
+    frame_cycles: Stats,           // cycles per frame
+    frame_busy: Stats,             // cycles per frame, without LDK waits
+    draws: Stats,                  // DRW instructions per frame
+}
+
+impl Profiler {
+    /// create a profiler instance
+    pub fn new() -> Profiler {
+        Profiler {
+            cycles: 0,
+            addresses: BTreeMap::new(),
+            opcodes: BTreeMap::new(),
+            ops: BTreeMap::new(),
+            loops: HashMap::new(),
+            subroutines: BTreeMap::new(),
+            calls: vec![],
+            ldk_waits: 0,
+            frame_start: None,
+            frame_draws: 0,
+            frame_waits: 0,
+            frame_cycles: Stats::default(),
+            frame_busy: Stats::default(),
+            draws: Stats::default(),
+        }
+    }
+
+    /// count an instruction executed at the cycle, `next` is the program counter after it
+    pub fn record(&mut self, cycle: u64, pc: u16, opcode: OpCode, op: Op, next: u16) {
+        self.cycles += 1;
+        *self.addresses.entry(pc).or_insert(0) += 1;
+        self.opcodes.insert(pc, opcode.get_opcode());
+        *self.ops.entry(op.name()).or_insert(0) += 1;
+
+        // the cycle belongs to the subroutine running it, or to the main program
+        let owner = self.calls.last().map_or(0x200, |&(target, _)| target);
+        self.subroutines.entry(owner).or_default().exclusive += 1;
+
+        match op {
+            Op::CALL { address } => {
+                self.subroutines.entry(address).or_default().calls += 1;
+                if self.calls.len() == MAX_CALL_DEPTH {
+                    self.calls.remove(0);
+                }
+                self.calls.push((address, cycle));
+            }
+            Op::RET => {
+                if let Some((target, start)) = self.calls.pop() {
+                    self.subroutines.entry(target).or_default().inclusive += cycle - start + 1;
+                }
+            }
+            Op::LDK { .. } if next == pc => {
+                self.ldk_waits += 1;
+                self.frame_waits += 1;
+            }
+            Op::DRW { .. } => self.frame_draws += 1,
+            _ => {}
+        }
+
+        // a taken branch backwards closes a loop
+        if next <= pc && !matches!(op, Op::LDK { .. } | Op::CALL { .. } | Op::RET) {
+            *self.loops.entry((pc, next)).or_insert(0) += 1;
+        }
+    }
+
+    /// mark the start of a frame at the cycle
+    pub fn frame(&mut self, cycle: u64) {
+        if let Some(start) = self.frame_start {
+            let cycles = cycle - start;
+            self.frame_cycles.add(cycles);
+            self.frame_busy.add(cycles - self.frame_waits.min(cycles));
+            self.draws.add(self.frame_draws);
+        }
+        self.frame_start = Some(cycle);
+        self.frame_draws = 0;
+        self.frame_waits = 0;
+    }
+
+    /// forget the frame and the calls in progress, after the machine was rewound before their start.
+    /// the cycles counted so far are kept, the replayed ones are not counted again
+    pub fn rewind(&mut self) {
+        self.calls.clear();
+        self.frame_start = None;
+        self.frame_draws = 0;
+        self.frame_waits = 0;
+    }
+
+    /// return the percentage of all cycles
+    fn percent(&self, count: u64) -> f64 {
+        if self.cycles == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.cycles as f64
+        }
+    }
+
+    /// format the instruction at the address, e.g. `0248 <main+0x48>: D015 DRW V0, V1, 5`
+    fn instruction(&self, address: u16, symbols: &Symbols) -> String {
+        let opcode = self.opcodes.get(&address).copied().unwrap_or(0);
+        let op = Op::decode(&OpCode::new(opcode))
+            .map_or(String::from("???"), |op| symbols.instruction(op));
+        format!("{}: {:04X} {}", symbols.describe(address), opcode, op)
+    }
+
+    /// return the name of a subroutine
+    fn subroutine(address: u16, symbols: &Symbols) -> String {
+        match symbols.name(address) {
+            Some(name) => String::from(name),
+            None if address == 0x200 => String::from("main"),
+            None => format!("sub_{:03X}", address),
+        }
+    }
+
+    /// write the report of the run, with the cpu and delay timer frequencies
+    pub fn report(
+        &self,
+        game_name: &str,
+        cpu_freq: u16,
+        frame_freq: u16,
+        symbols: &Symbols,
+    ) -> String {
+        let mut report = String::new();
+        writeln!(report, "Profile of {}", game_name).unwrap();
+        writeln!(
+            report,
+            "{} cycles, {:.1} seconds at {} Hz, {} frames",
+            self.cycles,
+            self.cycles as f64 / cpu_freq as f64,
+            cpu_freq,
+            self.frame_cycles.count
+        )
+        .unwrap();
+        writeln!(
+            report,
+            "cycles per frame: {:.1} average, {} min, {} max",
+            self.frame_cycles.average(),
+            self.frame_cycles.min,
+            self.frame_cycles.max
+        )
+        .unwrap();
+        writeln!(
+            report,
+            "DRW per frame: {:.2} average, {} max",
+            self.draws.average(),
+            self.draws.max
+        )
+        .unwrap();
+        writeln!(
+            report,
+            "waiting for a key in LDK: {} cycles ({:.1}%), {:.1} seconds",
+            self.ldk_waits,
+            self.percent(self.ldk_waits),
+            self.ldk_waits as f64 / cpu_freq as f64
+        )
+        .unwrap();
+
+        // short loops reading the delay timer are busy-waits for the next frames
+        let mut loops: Vec<(&(u16, u16), &u64)> = self.loops.iter().collect();
+        loops.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let waits_dt = |&(from, to): &(u16, u16)| {
+            from - to <= MAX_WAIT_LOOP
+                && self.opcodes.range(to..=from).any(|(_, &opcode)| {
+                    matches!(Op::decode(&OpCode::new(opcode)), Some(Op::LDT { .. }))
+                })
+        };
+        let waiting: BTreeSet<u16> = loops
+            .iter()
+            .filter(|(range, _)| waits_dt(range))
+            .flat_map(|(&(from, to), _)| self.addresses.range(to..=from).map(|(&a, _)| a))
+            .collect();
+        let dt_waits: u64 = waiting.iter().map(|a| self.addresses[a]).sum();
+        writeln!(
+            report,
+            "waiting for the delay timer in loops: {} cycles ({:.1}%)",
+            dt_waits,
+            self.percent(dt_waits)
+        )
+        .unwrap();
+        if self.frame_cycles.count > 0 {
+            // what is left is the work of a frame, the cpu frequency only has to cover it
+            let dt_waits_per_frame = dt_waits as f64 / self.frame_cycles.count as f64;
+            let work = (self.frame_busy.average() - dt_waits_per_frame).max(0.0);
+            writeln!(
+                report,
+                "working cycles per frame: {:.1} average, a cpu_freq of about {:.0} Hz covers them",
+                work,
+                work * frame_freq as f64
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nInstructions by type:").unwrap();
+        let mut ops: Vec<(&&str, &u64)> = self.ops.iter().collect();
+        ops.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (name, &count) in ops {
+            writeln!(
+                report,
+                "    {:<6}{:>12} {:>6.1}%",
+                name,
+                count,
+                self.percent(count)
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nHot addresses:").unwrap();
+        let mut addresses: Vec<(&u16, &u64)> = self.addresses.iter().collect();
+        addresses.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (&address, &count) in addresses.into_iter().take(TOP) {
+            writeln!(
+                report,
+                "    {:>12} {:>6.1}%  {}",
+                count,
+                self.percent(count),
+                self.instruction(address, symbols)
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nHot loops, by the cycles of their body:").unwrap();
+        let mut bodies: Vec<(u16, u16, u64, u64)> = loops
+            .iter()
+            .map(|(&(from, to), &iterations)| {
+                let cycles = self.addresses.range(to..=from).map(|(_, &c)| c).sum();
+                (from, to, iterations, cycles)
+            })
+            .collect();
+        bodies.sort_by(|a, b| b.3.cmp(&a.3).then((a.1, a.0).cmp(&(b.1, b.0))));
+        for &(from, to, iterations, cycles) in bodies.iter().take(TOP) {
+            writeln!(
+                report,
+                "    {} - {}: {} iterations, {} cycles ({:.1}%){}",
+                symbols.describe(to),
+                symbols.describe(from),
+                iterations,
+                cycles,
+                self.percent(cycles),
+                if from == to {
+                    ", spins in place"
+                } else if waits_dt(&(from, to)) {
+                    ", waits for the delay timer"
+                } else {
+                    ""
+                }
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nSubroutines, by inclusive cycles:").unwrap();
+        let mut subroutines: Vec<(&u16, &Subroutine)> = self.subroutines.iter().collect();
+        // the main program is never returned from, its inclusive cycles are all of them
+        let inclusive = |address: u16, sub: &Subroutine| {
+            if address == 0x200 && sub.calls == 0 {
+                self.cycles
+            } else {
+                sub.inclusive
+            }
+        };
+        subroutines.sort_by(|a, b| {
+            inclusive(*b.0, b.1)
+                .cmp(&inclusive(*a.0, a.1))
+                .then(a.0.cmp(b.0))
+        });
+        for (&address, sub) in subroutines.into_iter().take(TOP) {
+            let total = inclusive(address, sub);
+            writeln!(
+                report,
+                "    {:<24}{:>8} calls {:>12} inclusive ({:>5.1}%) {:>12} exclusive ({:>5.1}%)",
+                Profiler::subroutine(address, symbols),
+                sub.calls,
+                total,
+                self.percent(total),
+                sub.exclusive,
+                self.percent(sub.exclusive)
+            )
+            .unwrap();
+        }
+
+        report
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// record the instructions, each given by its cycle, address, opcode and the address after it
+    fn record(profiler: &mut Profiler, instructions: &[(u64, u16, u16, u16)]) {
+        for &(cycle, pc, opcode, next) in instructions {
+            let opcode = OpCode::new(opcode);
+            profiler.record(cycle, pc, opcode, Op::decode(&opcode).unwrap(), next);
+        }
+    }
+
+    #[test]
+    fn record_counts_the_addresses_and_the_types() {
+        let mut profiler = Profiler::new();
+        record(
+            &mut profiler,
+            &[
+                (0, 0x200, 0x6001, 0x202), // LD V0, 0x01
+                (1, 0x202, 0xD015, 0x204), // DRW V0, V1, 5
+                (2, 0x200, 0x6002, 0x202), // LD V0, 0x02
+            ],
+        );
+        assert_eq!(profiler.cycles, 3);
+        assert_eq!(profiler.addresses[&0x200], 2);
+        assert_eq!(profiler.addresses[&0x202], 1);
+        assert_eq!(profiler.opcodes[&0x200], 0x6002);
+        assert_eq!(profiler.ops["LD"], 2);
+        assert_eq!(profiler.ops["DRW"], 1);
+    }
+
+    #[test]
+    fn frames_count_their_cycles_draws_and_key_waits() {
+        let mut profiler = Profiler::new();
+        // the first frame has no start to measure from
+        profiler.frame(0);
+        assert_eq!(profiler.frame_cycles.count, 0);
+        record(
+            &mut profiler,
+            &[
+                (0, 0x200, 0xD015, 0x202), // DRW V0, V1, 5
+                (1, 0x202, 0xF00A, 0x202), // LD V0, K waiting
+                (2, 0x202, 0xF00A, 0x202), // LD V0, K waiting
+                (3, 0x202, 0xF00A, 0x204), // LD V0, K pressed
+            ],
+        );
+        profiler.frame(10);
+        profiler.frame(14);
+        assert_eq!(profiler.ldk_waits, 2);
+        assert_eq!(
+            (
+                profiler.frame_cycles.count,
+                profiler.frame_cycles.min,
+                profiler.frame_cycles.max
+            ),
+            (2, 4, 10)
+        );
+        assert_eq!(profiler.frame_busy.sum, 8 + 4);
+        assert_eq!((profiler.draws.sum, profiler.draws.max), (1, 1));
+    }
+
+    #[test]
+    fn backward_branches_close_loops() {
+        let mut profiler = Profiler::new();
+        for round in 0..3 {
+            record(
+                &mut profiler,
+                &[
+                    (round * 3, 0x200, 0xF007, 0x202),     // LD V0, DT
+                    (round * 3 + 1, 0x202, 0x3000, 0x204), // SE V0, 0x00
+                    (round * 3 + 2, 0x204, 0x1200, 0x200), // JP 0x200
+                ],
+            );
+        }
+        // a forward jump, a key wait and a call are no loops
+        record(
+            &mut profiler,
+            &[
+                (9, 0x206, 0x120A, 0x20A),  // JP 0x20A
+                (10, 0x20A, 0xF00A, 0x20A), // LD V0, K waiting
+                (11, 0x20C, 0x2200, 0x200), // CALL 0x200
+            ],
+        );
+        assert_eq!(profiler.loops.len(), 1);
+        assert_eq!(profiler.loops[&(0x204, 0x200)], 3);
+        let report = profiler.report("TEST", 500, 60, &Symbols::new());
+        assert!(
+            report.contains("3 iterations, 9 cycles (75.0%), waits for the delay timer"),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn subroutines_count_inclusive_and_exclusive_cycles() {
+        let mut profiler = Profiler::new();
+        record(
+            &mut profiler,
+            &[
+                (0, 0x200, 0x2300, 0x300), // CALL 0x300
+                (1, 0x300, 0x2400, 0x400), // CALL 0x400
+                (2, 0x400, 0x6001, 0x402), // LD V0, 0x01
+                (3, 0x402, 0x00EE, 0x302), // RET
+                (4, 0x302, 0x00EE, 0x202), // RET
+                (5, 0x202, 0x6001, 0x204), // LD V0, 0x01
+            ],
+        );
+        let sub = |address: u16| {
+            let sub = &profiler.subroutines[&address];
+            (sub.calls, sub.inclusive, sub.exclusive)
+        };
+        assert_eq!(sub(0x200), (0, 0, 2));
+        assert_eq!(sub(0x300), (1, 5, 2));
+        assert_eq!(sub(0x400), (1, 3, 2));
+        assert!(profiler.calls.is_empty());
+    }
+
+    #[test]
+    fn rewind_forgets_the_frame_and_the_calls() {
+        let mut profiler = Profiler::new();
+        profiler.frame(0);
+        record(
+            &mut profiler,
+            &[
+                (0, 0x200, 0x2300, 0x300), // CALL 0x300
+                (1, 0x300, 0xD015, 0x302), // DRW V0, V1, 5
+            ],
+        );
+        profiler.rewind();
+        assert!(profiler.calls.is_empty());
+        assert_eq!(profiler.frame_start, None);
+        assert_eq!((profiler.frame_draws, profiler.frame_waits), (0, 0));
+        assert_eq!(profiler.cycles, 2);
+
+        // the next frame starts the measures again, and a return without a call is not counted
+        profiler.frame(1);
+        assert_eq!(profiler.frame_cycles.count, 0);
+        record(&mut profiler, &[(1, 0x302, 0x00EE, 0x202)]);
+        assert_eq!(profiler.subroutines[&0x300].inclusive, 0);
+    }
+}
@@ -1,4 +1,12 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::filter::Filters;
+use crate::palette::Palette;
+
 /// chip8 settings
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Settings {
     pub cpu_freq: u16,
     /// cpu frequency
@@ -19,12 +27,26 @@ pub struct Settings {
 
     /// if the sprite is positioned so part of it is outside the coordinates of the display, it wraps around to the opposite side of the screen
     pub vertical_wrap: bool,
+
+    /// when a game clears the screen in a frame, present the picture completed before the clear instead of a partially drawn one
+    pub present_after_draw: bool,
+
+    /// the frames a pixel turned off takes to fade out on the screen, 0 turns it off at once
+    pub phosphor_fade: u8,
+    /// a pixel lit in the previous frame stays lit on the screen, the last two frames are ORed
+    pub blend_frames: bool,
+
+    /// the colors of the screen
+    pub palette: Palette,
+    /// the filters of the display as it is scaled up in the window, the screenshots and the recordings
+    pub filters: Filters,
 }
 
 impl Settings {
     /// create a settings instance
     pub fn new(game_name: &str) -> Settings {
-        let shift_vx_ignore_vy = matches!(game_name, "BLINKY" | "INVADERS" | "TICTAC");
+        let shift_vx_ignore_vy = Settings::is_known(game_name);
+        let phosphor_fade = if Settings::flickers(game_name) { 4 } else { 0 };
 
         Settings {
             cpu_freq: 700,
@@ -35,6 +57,117 @@ impl Settings {
             set_vf_when_overflow: false,
             mute: false,
             vertical_wrap: false,
+            present_after_draw: false,
+            phosphor_fade,
+            blend_frames: false,
+            palette: Palette::new(),
+            filters: Filters::new(),
         }
     }
+
+    /// load the settings of a game from its config file, with a `key = value` line per setting and `#` comments.
+    /// the settings missing from the file keep the values of `Settings::new`
+    pub fn load(game_name: &str, path: &Path) -> io::Result<Settings> {
+        let mut settings = Settings::new(game_name);
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            settings.set(line).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: {}", path.display(), number + 1, err),
+                )
+            })?;
+        }
+        Ok(settings)
+    }
+
+    /// apply a `key = value` line
+    pub fn set(&mut self, line: &str) -> Result<(), String> {
+        let (key, value) = line
+            .split_once('=')
+            .ok_or(format!("expected `key = value`, found `{}`", line))?;
+        let (key, value) = (key.trim(), value.trim());
+        let frequency = || {
+            value
+                .parse::<u16>()
+                .ok()
+                .filter(|&freq| freq > 0)
+                .ok_or(format!("invalid frequency for {}: {}", key, value))
+        };
+        let frames = || {
+            value
+                .parse::<u8>()
+                .map_err(|_| format!("invalid number of frames for {}: {}", key, value))
+        };
+        let flag = || {
+            value
+                .parse::<bool>()
+                .map_err(|_| format!("expected true or false for {}, found {}", key, value))
+        };
+        match key {
+            "cpu_freq" => self.cpu_freq = frequency()?,
+            "delay_timer_freq" => self.delay_timer_freq = frequency()?,
+            "sound_timer_freq" => self.sound_timer_freq = frequency()?,
+            "increment_i_register" => self.increment_i_register = flag()?,
+            "shift_vx_ignore_vy" => self.shift_vx_ignore_vy = flag()?,
+            "set_vf_when_overflow" => self.set_vf_when_overflow = flag()?,
+            "mute" => self.mute = flag()?,
+            "vertical_wrap" => self.vertical_wrap = flag()?,
+            "present_after_draw" => self.present_after_draw = flag()?,
+            "phosphor_fade" => self.phosphor_fade = frames()?,
+            "blend_frames" => self.blend_frames = flag()?,
+            "palette" => self.palette = value.parse()?,
+            "filters" => self.filters = value.parse()?,
+            _ => return Err(format!("unknown setting: {}", key)),
+        }
+        Ok(())
+    }
+
+    /// write the settings to a config file which `Settings::load` reads back
+    pub fn save(&self, path: &Path, comment: &str) -> io::Result<()> {
+        let config = format!(
+            "# {}\n\
+             cpu_freq = {}\n\
+             delay_timer_freq = {}\n\
+             sound_timer_freq = {}\n\
+             increment_i_register = {}\n\
+             shift_vx_ignore_vy = {}\n\
+             set_vf_when_overflow = {}\n\
+             mute = {}\n\
+             vertical_wrap = {}\n\
+             present_after_draw = {}\n\
+             phosphor_fade = {}\n\
+             blend_frames = {}\n\
+             palette = {}\n\
+             filters = {}\n",
+            comment,
+            self.cpu_freq,
+            self.delay_timer_freq,
+            self.sound_timer_freq,
+            self.increment_i_register,
+            self.shift_vx_ignore_vy,
+            self.set_vf_when_overflow,
+            self.mute,
+            self.vertical_wrap,
+            self.present_after_draw,
+            self.phosphor_fade,
+            self.blend_frames,
+            self.palette,
+            self.filters
+        );
+        fs::write(path, config)
+    }
+
+    /// check whether the game is one of those known to need different quirks
+    pub fn is_known(game_name: &str) -> bool {
+        matches!(game_name, "BLINKY" | "INVADERS" | "TICTAC")
+    }
+
+    /// check whether the game is one of those known to flicker, whose pixels fade out by default
+    pub fn flickers(game_name: &str) -> bool {
+        matches!(game_name, "BLINKY" | "INVADERS")
+    }
 }
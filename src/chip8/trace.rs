@@ -0,0 +1,684 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::chip8::{Cpu, Display, Memory, Op};
+
+/// the first bytes of a binary trace, followed by the format version
+pub const BINARY_MAGIC: &[u8; 4] = b"C8TR";
+
+/// the version of the binary trace format
+pub const BINARY_VERSION: u8 = 1;
+
+/// the size of a record in a binary trace without its changes
+pub const BINARY_RECORD_SIZE: usize = 41;
+
+/// format of the trace file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text, // one line per instruction, e.g. `C=12 PC=0204 OP=6105 I=0000 SP=0 DT=00 ST=00 V=00 ... MEM=.. GFX=..`
+    Binary, // a header and little endian records
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TraceFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(TraceFormat::Text),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(format!("unknown trace format: {}", s)),
+        }
+    }
+}
+
+/// a group of instructions which do similar things
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpClass {
+    Flow,    // SYS, JP, CALL, RET, JP V0
+    Skip,    // SE, SNE, SKP, SKNP
+    Load,    // LD between registers, immediates, I, timers and keys
+    Alu,     // ADD, OR, AND, XOR, SUB, SHR, SUBN, SHL, RND, ADD I
+    Memory,  // LD B, LD [I], LD Vx [I]
+    Display, // CLS, DRW
+}
+
+impl OpClass {
+    /// return the class of the instruction
+    pub fn of(op: Op) -> OpClass {
+        match op {
+            Op::SYS { .. } | Op::JP { .. } | Op::CALL { .. } | Op::RET | Op::JPV { .. } => {
+                OpClass::Flow
+            }
+            Op::SE { .. }
+            | Op::SNE2 { .. }
+            | Op::SEV { .. }
+            | Op::SNE { .. }
+            | Op::SKP { .. }
+            | Op::SKNP { .. } => OpClass::Skip,
+            Op::LD { .. }
+            | Op::LDR { .. }
+            | Op::LDA { .. }
+            | Op::LDT { .. }
+            | Op::LDK { .. }
+            | Op::LDF { .. }
+            | Op::LDS { .. }
+            | Op::LDX { .. } => OpClass::Load,
+            Op::ADD { .. }
+            | Op::OR { .. }
+            | Op::AND { .. }
+            | Op::XOR { .. }
+            | Op::ADD2 { .. }
+            | Op::SUB { .. }
+            | Op::SHR { .. }
+            | Op::SUBN { .. }
+            | Op::SHL { .. }
+            | Op::RND { .. }
+            | Op::ADDI { .. } => OpClass::Alu,
+            Op::LDB { .. } | Op::LDI { .. } | Op::LDJ { .. } => OpClass::Memory,
+            Op::CLS | Op::DRW { .. } => OpClass::Display,
+        }
+    }
+}
+
+impl FromStr for OpClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OpClass, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "flow" => Ok(OpClass::Flow),
+            "skip" => Ok(OpClass::Skip),
+            "load" => Ok(OpClass::Load),
+            "alu" => Ok(OpClass::Alu),
+            "memory" => Ok(OpClass::Memory),
+            "display" => Ok(OpClass::Display),
+            _ => Err(format!("unknown opcode class: {}", s)),
+        }
+    }
+}
+
+/// a list of opcode classes separated by commas, e.g. `flow,display`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpClasses(pub Vec<OpClass>);
+
+impl FromStr for OpClasses {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OpClasses, String> {
+        s.split(',')
+            .map(|class| class.trim().parse())
+            .collect::<Result<Vec<OpClass>, String>>()
+            .map(OpClasses)
+    }
+}
+
+/// an inclusive range written as `START-END`, either end may be left out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window<T>(pub RangeInclusive<T>);
+
+/// parse the ends of a window with the radix of its values
+fn parse_window<T: Copy>(
+    s: &str,
+    min: T,
+    max: T,
+    parse: fn(&str) -> Option<T>,
+) -> Result<RangeInclusive<T>, String> {
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+    let bound = |value: &str, default: T| match value.trim() {
+        "" => Ok(default),
+        value => parse(value).ok_or_else(|| format!("invalid range: {}", s)),
+    };
+    Ok(bound(start, min)?..=bound(end, max)?)
+}
+
+impl FromStr for Window<u16> {
+    type Err = String;
+
+    /// parse a range of hexadecimal addresses, e.g. `200-2FF`
+    fn from_str(s: &str) -> Result<Window<u16>, String> {
+        parse_window(s, 0, u16::MAX, |value| {
+            u16::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+        })
+        .map(Window)
+    }
+}
+
+impl FromStr for Window<u64> {
+    type Err = String;
+
+    /// parse a range of decimal cycles, e.g. `1000-2000` or `1000-`
+    fn from_str(s: &str) -> Result<Window<u64>, String> {
+        parse_window(s, 0, u64::MAX, |value| value.parse().ok()).map(Window)
+    }
+}
+
+/// which instructions are written to the trace
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    pub addresses: Option<RangeInclusive<u16>>, // the program counter range
+    pub classes: Vec<OpClass>,                  // the opcode classes, empty for all of them
+    pub cycles: Option<RangeInclusive<u64>>,    // the cycle window
+}
+
+impl TraceFilter {
+    /// check whether the instruction executed at the cycle is traced
+    pub fn matches(&self, cycle: u64, pc: u16, op: Op) -> bool {
+        self.cycles.as_ref().is_none_or(|c| c.contains(&cycle))
+            && self.addresses.as_ref().is_none_or(|a| a.contains(&pc))
+            && (self.classes.is_empty() || self.classes.contains(&OpClass::of(op)))
+    }
+}
+
+/// return the 32-bit FNV-1a hash of the bytes
+pub fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u32 {
+    bytes.into_iter().fold(0x811C9DC5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+/// the memory bytes and display rows which changed since the previous record of a trace
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub memory: Vec<(u16, u8)>, // the addresses from 0x200 on with their new bytes
+    pub rows: Vec<(u8, u64)>, // the display rows with their new pixels, the leftmost one in the highest bit
+}
+
+impl Changes {
+    /// format the changed memory as runs of bytes, e.g. `0300:010203,0310:FF`
+    fn memory_text(&self) -> String {
+        let mut runs: Vec<(u16, String)> = vec![];
+        for &(address, byte) in self.memory.iter() {
+            match runs.last_mut() {
+                Some((start, bytes)) if *start as usize + bytes.len() / 2 == address as usize => {
+                    bytes.push_str(&format!("{:02X}", byte))
+                }
+                _ => runs.push((address, format!("{:02X}", byte))),
+            }
+        }
+        runs.iter()
+            .map(|(start, bytes)| format!("{:04X}:{}", start, bytes))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// format the changed rows, e.g. `05:00000000F0000000`
+    fn rows_text(&self) -> String {
+        self.rows
+            .iter()
+            .map(|(row, pixels)| format!("{:02X}:{:016X}", row, pixels))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// parse the runs of bytes written by `memory_text`
+    fn parse_memory(s: &str) -> Option<Vec<(u16, u8)>> {
+        let mut memory = vec![];
+        for run in s.split(',') {
+            let (start, bytes) = run.split_once(':')?;
+            let start = u16::from_str_radix(start, 16).ok()?;
+            if bytes.is_empty() || bytes.len() % 2 != 0 || !bytes.is_ascii() {
+                return None;
+            }
+            for offset in 0..bytes.len() / 2 {
+                let byte = u8::from_str_radix(&bytes[offset * 2..offset * 2 + 2], 16).ok()?;
+                memory.push((start.checked_add(offset as u16)?, byte));
+            }
+        }
+        Some(memory)
+    }
+
+    /// parse the rows written by `rows_text`
+    fn parse_rows(s: &str) -> Option<Vec<(u8, u64)>> {
+        s.split(',')
+            .map(|row| {
+                let (row, pixels) = row.split_once(':')?;
+                Some((
+                    u8::from_str_radix(row, 16).ok()?,
+                    u64::from_str_radix(pixels, 16).ok()?,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// the state of the machine before an instruction was executed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub i: u16,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
+    pub v: [u8; 16],
+    pub memory: Option<u32>, // the hash of the memory from 0x200 on, if the trace has it
+    pub display: Option<u32>, // the hash of the display pixels, if the trace has it
+    pub changes: Changes,    // what changed since the previous record, it goes with the hashes
+}
+
+impl Record {
+    /// capture the state of the machine before the cpu executes the opcode
+    pub fn new(cycle: u64, cpu: &Cpu, memory: &Memory, display: &Display, opcode: u16) -> Record {
+        let mut v = [0; 16];
+        (0..16).for_each(|reg| v[reg as usize] = cpu.v(reg));
+        Record {
+            cycle,
+            pc: cpu.pc(),
+            opcode,
+            i: cpu.i(),
+            sp: cpu.sp(),
+            dt: cpu.dt(),
+            st: cpu.st(),
+            v,
+            memory: Some(memory.hash()),
+            display: Some(display.hash()),
+            changes: Changes::default(),
+        }
+    }
+
+    /// format the record as a line of a text trace, without the trailing comment
+    pub fn to_text(&self) -> String {
+        let mut line = format!(
+            "C={} PC={:04X} OP={:04X} I={:04X} SP={:X} DT={:02X} ST={:02X} V=",
+            self.cycle, self.pc, self.opcode, self.i, self.sp, self.dt, self.st
+        );
+        for (reg, value) in self.v.iter().enumerate() {
+            line.push_str(&format!("{}{:02X}", if reg == 0 { "" } else { " " }, value));
+        }
+        if let Some(hash) = self.memory {
+            line.push_str(&format!(" MEM={:08X}", hash));
+        }
+        if let Some(hash) = self.display {
+            line.push_str(&format!(" GFX={:08X}", hash));
+        }
+        if !self.changes.memory.is_empty() {
+            line.push_str(&format!(" WR={}", self.changes.memory_text()));
+        }
+        if !self.changes.rows.is_empty() {
+            line.push_str(&format!(" ROW={}", self.changes.rows_text()));
+        }
+        line
+    }
+
+    /// parse a line of a text trace, the comment after `;` is ignored.
+    /// `C`, `PC`, `OP`, `I`, `SP`, `DT`, `ST` and `V` are required, `MEM`, `GFX`, `WR` and `ROW` are optional
+    pub fn parse_text(line: &str) -> Result<Record, String> {
+        let line = line.split(';').next().unwrap_or("");
+        let mut fields: Vec<(&str, Vec<&str>)> = vec![];
+        for word in line.split_whitespace() {
+            match (word.split_once('='), fields.last_mut()) {
+                (Some((key, value)), _) => fields.push((key, vec![value])),
+                // the registers V0 to VF are separated by spaces
+                (None, Some((_, values))) => values.push(word),
+                (None, None) => return Err(format!("invalid field: {}", word)),
+            }
+        }
+
+        let field = |key: &str| fields.iter().find(|(k, _)| k.eq_ignore_ascii_case(key));
+        let hex = |key: &str| -> Result<Option<u32>, String> {
+            match field(key) {
+                Some((_, values)) if values.len() == 1 => u32::from_str_radix(values[0], 16)
+                    .map(Some)
+                    .map_err(|_| format!("invalid {}: {}", key, values[0])),
+                Some(_) => Err(format!("invalid {}", key)),
+                None => Ok(None),
+            }
+        };
+        let required = |key: &str, max: u32| -> Result<u32, String> {
+            hex(key)?
+                .filter(|&value| value <= max)
+                .ok_or_else(|| format!("missing or invalid {}", key))
+        };
+
+        let cycle = match field("C") {
+            Some((_, values)) => values[0]
+                .parse()
+                .map_err(|_| format!("invalid C: {}", values[0]))?,
+            None => return Err(String::from("missing or invalid C")),
+        };
+        let mut v = [0; 16];
+        match field("V") {
+            Some((_, values)) if values.len() == 16 => {
+                for (reg, value) in values.iter().enumerate() {
+                    v[reg] = u8::from_str_radix(value, 16)
+                        .map_err(|_| format!("invalid V{:X}: {}", reg, value))?;
+                }
+            }
+            _ => return Err(String::from("missing or invalid V")),
+        }
+        let mut changes = Changes::default();
+        for (key, values) in fields.iter() {
+            let parsed = match (key.to_ascii_uppercase().as_str(), values.as_slice()) {
+                ("WR", [value]) => Changes::parse_memory(value).map(|m| changes.memory = m),
+                ("ROW", [value]) => Changes::parse_rows(value).map(|r| changes.rows = r),
+                ("WR" | "ROW", _) => None,
+                _ => Some(()),
+            };
+            parsed.ok_or_else(|| format!("invalid {}: {}", key, values.join(" ")))?;
+        }
+
+        Ok(Record {
+            cycle,
+            pc: required("PC", 0xFFFF)? as u16,
+            opcode: required("OP", 0xFFFF)? as u16,
+            i: required("I", 0xFFFF)? as u16,
+            sp: required("SP", 0xFF)? as u8,
+            dt: required("DT", 0xFF)? as u8,
+            st: required("ST", 0xFF)? as u8,
+            v,
+            memory: hex("MEM")?,
+            display: hex("GFX")?,
+            changes,
+        })
+    }
+
+    /// encode the record as little endian bytes of a binary trace, the changes follow the fixed size part
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; BINARY_RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.cycle.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.pc.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.opcode.to_le_bytes());
+        bytes[12..14].copy_from_slice(&self.i.to_le_bytes());
+        bytes[14] = self.sp;
+        bytes[15] = self.dt;
+        bytes[16] = self.st;
+        bytes[17..33].copy_from_slice(&self.v);
+        bytes[33..37].copy_from_slice(&self.memory.unwrap_or(0).to_le_bytes());
+        bytes[37..41].copy_from_slice(&self.display.unwrap_or(0).to_le_bytes());
+        bytes.extend_from_slice(&(self.changes.memory.len() as u16).to_le_bytes());
+        for &(address, byte) in self.changes.memory.iter() {
+            bytes.extend_from_slice(&address.to_le_bytes());
+            bytes.push(byte);
+        }
+        bytes.push(self.changes.rows.len() as u8);
+        for &(row, pixels) in self.changes.rows.iter() {
+            bytes.push(row);
+            bytes.extend_from_slice(&pixels.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// decode the fixed size part of a record of a binary trace
+    fn from_bytes(bytes: &[u8]) -> Record {
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let mut cycle = [0; 8];
+        cycle.copy_from_slice(&bytes[0..8]);
+        let mut v = [0; 16];
+        v.copy_from_slice(&bytes[17..33]);
+        Record {
+            cycle: u64::from_le_bytes(cycle),
+            pc: u16_at(8),
+            opcode: u16_at(10),
+            i: u16_at(12),
+            sp: bytes[14],
+            dt: bytes[15],
+            st: bytes[16],
+            v,
+            memory: Some(u32_at(33)),
+            display: Some(u32_at(37)),
+            changes: Changes::default(),
+        }
+    }
+
+    /// read a record of a binary trace, return None at the end of the trace
+    fn read_binary(reader: &mut impl Read) -> io::Result<Option<Record>> {
+        let mut bytes = [0; BINARY_RECORD_SIZE];
+        match reader.read_exact(&mut bytes) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let mut record = Record::from_bytes(&bytes);
+
+        let mut count = [0; 2];
+        reader.read_exact(&mut count)?;
+        for _ in 0..u16::from_le_bytes(count) {
+            let mut change = [0; 3];
+            reader.read_exact(&mut change)?;
+            let address = u16::from_le_bytes([change[0], change[1]]);
+            record.changes.memory.push((address, change[2]));
+        }
+        let mut count = [0; 1];
+        reader.read_exact(&mut count)?;
+        for _ in 0..count[0] {
+            let mut change = [0; 9];
+            reader.read_exact(&mut change)?;
+            let pixels = u64::from_le_bytes(change[1..9].try_into().unwrap());
+            record.changes.rows.push((change[0], pixels));
+        }
+        Ok(Some(record))
+    }
+}
+
+/// the source of a trace reader
+enum Source {
+    Text(io::Lines<BufReader<File>>),
+    Binary(BufReader<File>),
+}
+
+/// reads the records of a text or binary trace file one by one
+pub struct TraceReader {
+    source: Source,
+    line: usize, // the number of lines or records read
+}
+
+impl TraceReader {
+    /// open a trace file, binary traces are recognised by their first bytes
+    pub fn open(path: &Path) -> io::Result<TraceReader> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let binary = reader.fill_buf()?.starts_with(BINARY_MAGIC);
+        let source = if binary {
+            let mut header = [0; 5];
+            reader.read_exact(&mut header)?;
+            if header[4] != BINARY_VERSION {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported binary trace version {}", header[4]),
+                ));
+            }
+            Source::Binary(reader)
+        } else {
+            Source::Text(reader.lines())
+        };
+        Ok(TraceReader { source, line: 0 })
+    }
+
+    /// return the number of the line or record read last, starting at 1
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl Iterator for TraceReader {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        let invalid = |line: usize, message: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", line, message),
+            )
+        };
+        match &mut self.source {
+            Source::Text(lines) => loop {
+                let line = match lines.next()? {
+                    Ok(line) => line,
+                    Err(err) => return Some(Err(err)),
+                };
+                self.line += 1;
+                // empty lines and comment lines are skipped
+                if line.trim().is_empty() || line.trim_start().starts_with(';') {
+                    continue;
+                }
+                return Some(Record::parse_text(&line).map_err(|err| invalid(self.line, err)));
+            },
+            Source::Binary(reader) => {
+                self.line += 1;
+                Record::read_binary(reader).transpose()
+            }
+        }
+    }
+}
+
+/// execution trace sink
+pub struct Tracer {
+    writer: BufWriter<Box<dyn Write>>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    previous: Option<(Vec<u8>, Vec<u64>)>, // the memory from 0x200 on and the display rows at the previous record
+}
+
+impl Tracer {
+    /// create a tracer which prints text lines to stdout.
+    /// stdout is buffered by lines already, so the lines keep their order with the other messages
+    pub fn stdout(filter: TraceFilter) -> Tracer {
+        Tracer {
+            writer: BufWriter::with_capacity(0, Box::new(io::stdout())),
+            format: TraceFormat::Text,
+            filter,
+            previous: None,
+        }
+    }
+
+    /// create a tracer which writes to a file
+    pub fn create(path: &Path, format: TraceFormat, filter: TraceFilter) -> io::Result<Tracer> {
+        let mut writer: BufWriter<Box<dyn Write>> = BufWriter::new(Box::new(File::create(path)?));
+        if format == TraceFormat::Binary {
+            writer.write_all(BINARY_MAGIC)?;
+            writer.write_all(&[BINARY_VERSION])?;
+        }
+        Ok(Tracer {
+            writer,
+            format,
+            filter,
+            previous: None,
+        })
+    }
+
+    /// check whether the instruction executed at the cycle is traced
+    pub fn matches(&self, cycle: u64, pc: u16, op: Op) -> bool {
+        self.filter.matches(cycle, pc, op)
+    }
+
+    /// return what changed in the memory and on the display since the previous record, nothing at the first one
+    pub fn changes(&mut self, memory: &Memory, display: &Display) -> Changes {
+        let bytes: Vec<u8> = (0x200..0x1000)
+            .map(|address| memory.read8(address))
+            .collect();
+        let rows: Vec<u64> = (0..Display::display_height())
+            .map(|y| display.row(y))
+            .collect();
+        let mut changes = Changes::default();
+        if let Some((previous_bytes, previous_rows)) = self.previous.as_ref() {
+            changes.memory = (0x200..)
+                .zip(bytes.iter().zip(previous_bytes.iter()))
+                .filter(|(_, (byte, previous))| byte != previous)
+                .map(|(address, (&byte, _))| (address, byte))
+                .collect();
+            changes.rows = (0..)
+                .zip(rows.iter().zip(previous_rows.iter()))
+                .filter(|(_, (pixels, previous))| pixels != previous)
+                .map(|(row, (&pixels, _))| (row, pixels))
+                .collect();
+        }
+        self.previous = Some((bytes, rows));
+        changes
+    }
+
+    /// write a record, text lines end with the comment after a `;`
+    pub fn write(&mut self, record: &Record, comment: &str) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{} ; {}", record.to_text(), comment),
+            TraceFormat::Binary => self.writer.write_all(&record.to_bytes()),
+        }
+    }
+
+    /// write the buffered records
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a record with memory and display changes
+    fn record() -> Record {
+        Record {
+            cycle: 12,
+            pc: 0x218,
+            opcode: 0xF255,
+            i: 0x300,
+            sp: 1,
+            dt: 0x3C,
+            st: 0,
+            v: [1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF],
+            memory: Some(0x5C3A0B12),
+            display: Some(0x050C5D1F),
+            changes: Changes {
+                memory: vec![(0x300, 1), (0x301, 2), (0x302, 3), (0x310, 0xFF)],
+                rows: vec![(5, 0xF0 << 56), (31, 1)],
+            },
+        }
+    }
+
+    #[test]
+    fn text_records_round_trip() {
+        let line = record().to_text();
+        assert!(
+            line.ends_with(" WR=0300:010203,0310:FF ROW=05:F000000000000000,1F:0000000000000001")
+        );
+        assert_eq!(
+            Record::parse_text(&format!("{} ; comment", line)),
+            Ok(record())
+        );
+    }
+
+    #[test]
+    fn text_records_without_changes_have_none() {
+        let line = "C=0 PC=0200 OP=00E0 I=0000 SP=0 DT=00 ST=00 V=00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00";
+        let record = Record::parse_text(line).unwrap();
+        assert_eq!(record.memory, None);
+        assert_eq!(record.changes, Changes::default());
+    }
+
+    #[test]
+    fn invalid_changes_are_rejected() {
+        let line = record().to_text();
+        assert!(Record::parse_text(&line.replace("0300:010203", "0300:01020")).is_err());
+        assert!(Record::parse_text(&line.replace("05:F0", "05F0")).is_err());
+    }
+
+    #[test]
+    fn binary_records_round_trip() {
+        let mut bytes = record().to_bytes();
+        bytes.extend(Record::parse_text("C=1 PC=0202 OP=1202 I=0000 SP=0 DT=00 ST=00 V=00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 MEM=00000000 GFX=00000000").unwrap().to_bytes());
+        let mut reader = bytes.as_slice();
+        assert_eq!(Record::read_binary(&mut reader).unwrap(), Some(record()));
+        assert_eq!(Record::read_binary(&mut reader).unwrap().unwrap().pc, 0x202);
+        assert!(Record::read_binary(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn tracer_lists_the_changes_since_the_previous_record() {
+        let mut tracer = Tracer::stdout(TraceFilter::default());
+        let (mut memory, mut display) = (Memory::new(), Display::new());
+        assert_eq!(tracer.changes(&memory, &display), Changes::default());
+
+        memory.write(0x300, 7);
+        // the interpreter area is not compared, like in the hash
+        memory.write(0x100, 7);
+        display.set_pixel(0, 5, true);
+        display.set_pixel(63, 5, true);
+        let changes = tracer.changes(&memory, &display);
+        assert_eq!(changes.memory, vec![(0x300, 7)]);
+        assert_eq!(changes.rows, vec![(5, 1 << 63 | 1)]);
+
+        assert_eq!(tracer.changes(&memory, &display), Changes::default());
+    }
+}
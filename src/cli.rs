@@ -2,13 +2,96 @@ use std::path::{Path, PathBuf};
 
 use structopt::StructOpt;
 
+use crate::cfg::GraphFormat;
+use crate::chip8::trace::{OpClasses, TraceFilter, TraceFormat, Window};
+use crate::disasm::Syntax;
+use crate::filter::Filters;
+use crate::palette::Palette;
+use crate::romtest::{KeyPress, Length};
+use crate::tracediff::DiffOptions;
+
 /// A CHIP-8 game console emulator
 #[derive(StructOpt, Debug)]
 pub struct Cli {
-    /// Prints the instructions
+    /// Prints the instructions with the registers, subject to the trace filters
     #[structopt(short, long)]
     print_instruction: bool,
 
+    /// Writes the trace of the executed instructions to a file
+    #[structopt(long, parse(from_os_str))]
+    trace: Option<PathBuf>,
+
+    /// The format of the trace file, text or binary
+    #[structopt(long, default_value = "text")]
+    trace_format: TraceFormat,
+
+    /// Traces only the instructions in the hexadecimal address range, e.g. 200-2FF
+    #[structopt(long)]
+    trace_addresses: Option<Window<u16>>,
+
+    /// Traces only the opcode classes, from flow, skip, load, alu, memory and display, e.g. flow,display
+    #[structopt(long)]
+    trace_ops: Option<OpClasses>,
+
+    /// Traces only the instructions in the cycle window, e.g. 1000-2000 or 1000-
+    #[structopt(long)]
+    trace_cycles: Option<Window<u64>>,
+
+    /// Starts paused in the interactive debugger, which reads commands from stdin
+    #[structopt(short, long)]
+    debug: bool,
+
+    /// Counts the executed instructions and writes a profile report next to the game (.prof) on exit
+    #[structopt(long)]
+    profile: bool,
+
+    /// Tracks which ROM bytes are executed, read and written, and writes the coverage (.cov) and an annotated disassembly (.cov.asm) next to the game on exit
+    #[structopt(long)]
+    coverage: bool,
+
+    /// Loads a symbol map, by default the game path with the .sym extension if it exists
+    #[structopt(short, long, parse(from_os_str))]
+    symbols: Option<PathBuf>,
+
+    /// Runs the game briefly under each quirk profile and saves the one which behaves sanely to the config file next to the game,
+    /// which happens by itself for unknown games without a config file
+    #[structopt(long)]
+    detect_quirks: bool,
+
+    /// Records the display to an animated GIF (.gif), or to raw RGB frames at 60 frames per second (any other extension); F10 starts and stops recording
+    #[structopt(long, parse(from_os_str))]
+    record: Option<PathBuf>,
+
+    /// The number of image pixels per display pixel of the recordings
+    #[structopt(long, default_value = "4")]
+    record_scale: usize,
+
+    /// Captures the beeper output to a WAV file, in sync with the emulated time
+    #[structopt(long, parse(from_os_str))]
+    record_audio: Option<PathBuf>,
+
+    /// The colors of the screen instead of those of the config file: default, green, amber, lcd, high-contrast
+    /// or colorblind, or 2 or 4 hex colors, e.g. #000000,#33FF33; F9 switches between the named palettes
+    #[structopt(long)]
+    palette: Option<Palette>,
+
+    /// The number of window pixels per display pixel of the window at the start, which can be resized; F11 toggles fullscreen
+    #[structopt(long, default_value = "20")]
+    scale: u32,
+
+    /// Scales the display by whole numbers of window pixels only, so all its pixels are the same size
+    #[structopt(long)]
+    integer_scale: bool,
+
+    /// The filters of the display instead of those of the config file: none, or scale2x or scale3x, scanlines and grid,
+    /// e.g. scale2x,scanlines; they also apply to the screenshots and the recordings
+    #[structopt(long)]
+    filters: Option<Filters>,
+
+    /// Runs the game in the terminal instead of a window, drawn with block characters
+    #[structopt(long, conflicts_with = "debug")]
+    tui: bool,
+
     /// game path
     #[structopt(name = "GAME_PATH", parse(from_os_str))]
     game_path: PathBuf,
@@ -34,4 +117,338 @@ impl Cli {
     pub fn if_print_instruction(&self) -> bool {
         self.print_instruction
     }
+
+    /// check whether run the debugger
+    pub fn if_debug(&self) -> bool {
+        self.debug
+    }
+
+    /// check whether profile the game
+    pub fn if_profile(&self) -> bool {
+        self.profile
+    }
+
+    /// check whether track the coverage
+    pub fn if_coverage(&self) -> bool {
+        self.coverage
+    }
+
+    /// return the recording path
+    pub fn record_path(&self) -> Option<&Path> {
+        self.record.as_deref()
+    }
+
+    /// return the scale of the recordings
+    pub fn record_scale(&self) -> usize {
+        self.record_scale
+    }
+
+    /// return the palette asked for
+    pub fn palette(&self) -> Option<Palette> {
+        self.palette
+    }
+
+    /// return the filters asked for
+    pub fn filters(&self) -> Option<Filters> {
+        self.filters
+    }
+
+    /// return the scale of the window
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// check whether scale the window by whole numbers only
+    pub fn if_integer_scale(&self) -> bool {
+        self.integer_scale
+    }
+
+    /// check whether run the game in the terminal
+    pub fn if_tui(&self) -> bool {
+        self.tui
+    }
+
+    /// return the audio capture path
+    pub fn record_audio_path(&self) -> Option<&Path> {
+        self.record_audio.as_deref()
+    }
+
+    /// return the trace file path
+    pub fn trace_path(&self) -> Option<&Path> {
+        self.trace.as_deref()
+    }
+
+    /// return the trace file format
+    pub fn trace_format(&self) -> TraceFormat {
+        self.trace_format
+    }
+
+    /// return which instructions are traced
+    pub fn trace_filter(&self) -> TraceFilter {
+        TraceFilter {
+            addresses: self.trace_addresses.clone().map(|window| window.0),
+            classes: self.trace_ops.clone().map_or(vec![], |classes| classes.0),
+            cycles: self.trace_cycles.clone().map(|window| window.0),
+        }
+    }
+
+    /// return the symbol map path
+    pub fn symbols_path(&self) -> Option<PathBuf> {
+        symbols_path(&self.symbols, &self.game_path)
+    }
+
+    /// check whether detect the quirks even if the game has a config file
+    pub fn if_detect_quirks(&self) -> bool {
+        self.detect_quirks
+    }
+
+    /// return the config file path, the game path with the .cfg extension
+    pub fn config_path(&self) -> PathBuf {
+        self.game_path.with_extension("cfg")
+    }
+}
+
+/// A CHIP-8 ROM disassembler
+#[derive(StructOpt, Debug)]
+#[structopt(name = "disasm")]
+pub struct DisasmCli {
+    /// Assembly syntax of the listing: cowgod or octo
+    #[structopt(short, long, default_value = "cowgod")]
+    syntax: Syntax,
+
+    /// Writes the listing to a file instead of stdout
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// Writes the control-flow graph instead of the listing: dot or json
+    #[structopt(short, long)]
+    graph: Option<GraphFormat>,
+
+    /// Writes the quirk-sensitive instructions and the likely settings instead of the listing
+    #[structopt(short, long)]
+    lint: bool,
+
+    /// Loads a symbol map for the labels, by default the rom path with the .sym extension if it exists
+    #[structopt(long, parse(from_os_str))]
+    symbols: Option<PathBuf>,
+
+    /// rom path
+    #[structopt(name = "ROM_PATH", parse(from_os_str))]
+    rom_path: PathBuf,
+}
+
+impl DisasmCli {
+    /// return the rom file path
+    pub fn rom_path(&self) -> &Path {
+        &self.rom_path
+    }
+
+    /// return the output file path
+    pub fn output(&self) -> Option<&Path> {
+        self.output.as_deref()
+    }
+
+    /// return the assembly syntax
+    pub fn syntax(&self) -> Syntax {
+        self.syntax
+    }
+
+    /// return the control-flow graph format, if the graph is written instead of the listing
+    pub fn graph(&self) -> Option<GraphFormat> {
+        self.graph
+    }
+
+    /// return if the quirk report is written instead of the listing
+    pub fn if_lint(&self) -> bool {
+        self.lint
+    }
+
+    /// return the symbol map path
+    pub fn symbols_path(&self) -> Option<PathBuf> {
+        symbols_path(&self.symbols, &self.rom_path)
+    }
+}
+
+/// Compares two execution traces and reports the first diverging instruction
+#[derive(StructOpt, Debug)]
+#[structopt(name = "tracediff")]
+pub struct TraceDiffCli {
+    /// Ignores the delay and sound timers, which tick at different cycles in other emulators
+    #[structopt(long)]
+    ignore_timers: bool,
+
+    /// The number of common instructions shown before the divergence
+    #[structopt(short, long, default_value = "8")]
+    context: usize,
+
+    /// the left trace path, text or binary
+    #[structopt(name = "LEFT", parse(from_os_str))]
+    left: PathBuf,
+
+    /// the right trace path, text or binary
+    #[structopt(name = "RIGHT", parse(from_os_str))]
+    right: PathBuf,
+}
+
+impl TraceDiffCli {
+    /// return the left trace path
+    pub fn left(&self) -> &Path {
+        &self.left
+    }
+
+    /// return the right trace path
+    pub fn right(&self) -> &Path {
+        &self.right
+    }
+
+    /// return what the comparison ignores
+    pub fn options(&self) -> DiffOptions {
+        DiffOptions {
+            ignore_timers: self.ignore_timers,
+            context: self.context,
+        }
+    }
+}
+
+/// A CHIP-8 assembler, also compiles Octo sources (.8o)
+#[derive(StructOpt, Debug)]
+#[structopt(name = "asm")]
+pub struct AsmCli {
+    /// Writes the ROM to this file instead of the source path with the .ch8 extension
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// source path
+    #[structopt(name = "SOURCE_PATH", parse(from_os_str))]
+    source_path: PathBuf,
+}
+
+impl AsmCli {
+    /// return the source file path
+    pub fn source_path(&self) -> &Path {
+        &self.source_path
+    }
+
+    /// return the rom file path
+    pub fn output(&self) -> PathBuf {
+        match &self.output {
+            Some(path) => path.clone(),
+            None => self.source_path.with_extension("ch8"),
+        }
+    }
+}
+
+/// Runs a ROM without a window and compares the display to an expected image
+#[derive(StructOpt, Debug)]
+#[structopt(name = "romtest")]
+pub struct RomTestCli {
+    /// The number of frames to run, 60 per second [default: 60]
+    #[structopt(short, long, conflicts_with = "cycles")]
+    frames: Option<u64>,
+
+    /// The number of cpu cycles to run instead of frames
+    #[structopt(short, long)]
+    cycles: Option<u64>,
+
+    /// Presses a hexadecimal key at a frame and holds it for 6 frames or the frames given, e.g. 60:A or 60:A:30
+    #[structopt(short, long = "key", name = "FRAME:KEY[:FRAMES]", number_of_values = 1)]
+    keys: Vec<KeyPress>,
+
+    /// Changes a setting like a line of the config file, e.g. shift_vx_ignore_vy=true
+    #[structopt(long = "set", name = "KEY=VALUE", number_of_values = 1)]
+    settings: Vec<String>,
+
+    /// Loads the settings from a config file, by default the rom path with the .cfg extension if it exists
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// The seed of the random numbers
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+
+    /// The expected image of the display at the end: a PBM (.pbm), PPM (.ppm), PNG (.png) or ASCII art
+    #[structopt(short, long, parse(from_os_str))]
+    expect: Option<PathBuf>,
+
+    /// Writes the display at the end to a PBM (.pbm), PPM (.ppm), PNG (.png) or ASCII art file
+    #[structopt(short, long, parse(from_os_str))]
+    actual: Option<PathBuf>,
+
+    /// Captures the beeper output to a WAV file
+    #[structopt(long, parse(from_os_str))]
+    audio: Option<PathBuf>,
+
+    /// rom path
+    #[structopt(name = "ROM_PATH", parse(from_os_str))]
+    rom_path: PathBuf,
+}
+
+impl RomTestCli {
+    /// return the rom file path
+    pub fn rom_path(&self) -> &Path {
+        &self.rom_path
+    }
+
+    /// return the rom file name
+    pub fn game_name(&self) -> String {
+        self.rom_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// return how long the test runs
+    pub fn length(&self) -> Length {
+        match self.cycles {
+            Some(cycles) => Length::Cycles(cycles),
+            None => Length::Frames(self.frames.unwrap_or(60)),
+        }
+    }
+
+    /// return the scripted key presses
+    pub fn keys(&self) -> &[KeyPress] {
+        &self.keys
+    }
+
+    /// return the settings changed, as `key = value` lines
+    pub fn settings(&self) -> &[String] {
+        &self.settings
+    }
+
+    /// return the config file path
+    pub fn config_path(&self) -> Option<PathBuf> {
+        match &self.config {
+            Some(path) => Some(path.clone()),
+            None => Some(self.rom_path.with_extension("cfg")).filter(|path| path.exists()),
+        }
+    }
+
+    /// return the seed of the random numbers
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// return the expected image path
+    pub fn expect(&self) -> Option<&Path> {
+        self.expect.as_deref()
+    }
+
+    /// return the path the display is written to
+    pub fn actual(&self) -> Option<&Path> {
+        self.actual.as_deref()
+    }
+
+    /// return the path the audio is captured to
+    pub fn audio(&self) -> Option<&Path> {
+        self.audio.as_deref()
+    }
+}
+
+/// return the given symbol map path, or the program path with the .sym extension if that file exists
+fn symbols_path(symbols: &Option<PathBuf>, program_path: &Path) -> Option<PathBuf> {
+    match symbols {
+        Some(path) => Some(path.clone()),
+        None => Some(program_path.with_extension("sym")).filter(|path| path.exists()),
+    }
 }
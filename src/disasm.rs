@@ -0,0 +1,307 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::cfg::Cfg;
+use crate::chip8::coverage::Coverage;
+use crate::chip8::memory::PROGRAM_START;
+use crate::chip8::operation::Op;
+use crate::symbols::Symbols;
+
+/// the column where the comments of a listing line start
+const COMMENT_COLUMN: usize = 32;
+
+/// assembly syntax of the listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    Cowgod, // mnemonics of Cowgod's technical reference, e.g. `LD V1, 0x05`
+    Octo,   // statements of the Octo language, e.g. `v1 := 0x05`
+}
+
+impl Syntax {
+    /// return the comment prefix of the syntax
+    fn comment(&self) -> &'static str {
+        match self {
+            Syntax::Cowgod => ";",
+            Syntax::Octo => "#",
+        }
+    }
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Syntax, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "cowgod" => Ok(Syntax::Cowgod),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(format!("unknown syntax: {}", s)),
+        }
+    }
+}
+
+/// what a label marks, in order of precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,   // an address loaded into I
+    Branch, // the target of a jump
+    Sub,    // the target of a call
+}
+
+/// one item of the listing
+enum Item {
+    Code(Op),
+    Data(u8),
+}
+
+/// ROM disassembler which follows the control flow to tell code from data
+pub struct Disassembler<'a> {
+    rom: &'a [u8],
+    cfg: Cfg<'a>,                     // the control flow of the ROM
+    labels: BTreeMap<u16, LabelKind>, // addresses referenced by the program
+    items: Vec<(u16, Item)>,          // the layout of the listing
+    symbols: Option<&'a Symbols>,     // names which replace the generated labels
+    coverage: Option<&'a Coverage>,   // how the bytes were used in a run
+}
+
+impl<'a> Disassembler<'a> {
+    /// create a disassembler instance and analyse the ROM
+    pub fn new(rom: &'a [u8]) -> Disassembler<'a> {
+        Disassembler::analyse(rom, Cfg::new(rom), None)
+    }
+
+    /// create a disassembler instance which also follows the instructions executed in a run,
+    /// and annotates the listing with the coverage
+    pub fn with_coverage(rom: &'a [u8], coverage: &'a Coverage) -> Disassembler<'a> {
+        let end = PROGRAM_START + rom.len() as u16;
+        let executed: Vec<u16> = (PROGRAM_START..end)
+            .filter(|&a| coverage.instruction(a))
+            .collect();
+        Disassembler::analyse(rom, Cfg::with_entries(rom, &executed), Some(coverage))
+    }
+
+    /// lay out the ROM after its control flow
+    fn analyse(rom: &'a [u8], cfg: Cfg<'a>, coverage: Option<&'a Coverage>) -> Disassembler<'a> {
+        let mut labels = BTreeMap::new();
+        for (addresses, kind) in [
+            (cfg.data(), LabelKind::Data),
+            (cfg.branches(), LabelKind::Branch),
+            (cfg.subroutines(), LabelKind::Sub),
+        ] {
+            // the kinds are added in order of precedence
+            labels.extend(addresses.iter().map(|&address| (address, kind)));
+        }
+
+        let mut disassembler = Disassembler {
+            rom,
+            cfg,
+            labels,
+            items: vec![],
+            symbols: None,
+            coverage,
+        };
+        disassembler.layout();
+        disassembler
+    }
+
+    /// name the labels after a symbol map
+    pub fn set_symbols(&mut self, symbols: &'a Symbols) {
+        self.symbols = Some(symbols);
+    }
+
+    /// split the ROM into instructions and data bytes
+    fn layout(&mut self) {
+        let end = PROGRAM_START as usize + self.rom.len();
+        let mut address = PROGRAM_START;
+        while (address as usize) < end {
+            match self
+                .cfg
+                .decode(address)
+                .filter(|_| self.cfg.is_code(address))
+            {
+                Some(op) => {
+                    self.items.push((address, Item::Code(op)));
+                    address += 2;
+                }
+                None => {
+                    let byte = self.rom[(address - PROGRAM_START) as usize];
+                    self.items.push((address, Item::Data(byte)));
+                    address += 1;
+                }
+            }
+        }
+
+        // a label in the middle of an instruction can not be defined, its address is printed instead
+        let starts: BTreeSet<u16> = self.items.iter().map(|&(address, _)| address).collect();
+        self.labels.retain(|address, _| starts.contains(address));
+    }
+
+    /// return the name of the label at address
+    fn label(&self, address: u16) -> Option<String> {
+        // a symbol in the middle of an instruction can not be defined either
+        let symbol = self
+            .symbols
+            .and_then(|symbols| symbols.name(address))
+            .filter(|_| {
+                self.items
+                    .binary_search_by_key(&address, |&(a, _)| a)
+                    .is_ok()
+            });
+        if let Some(name) = symbol {
+            return Some(name.to_string());
+        }
+        if address == PROGRAM_START {
+            return Some(String::from("main"));
+        }
+        self.labels.get(&address).map(|kind| match kind {
+            LabelKind::Sub => format!("sub_{:03X}", address),
+            LabelKind::Branch => format!("label_{:03X}", address),
+            LabelKind::Data => format!("data_{:03X}", address),
+        })
+    }
+
+    /// return the label at address, or the address itself if there is none
+    fn target(&self, address: u16) -> String {
+        self.label(address)
+            .unwrap_or_else(|| format!("0x{:03X}", address))
+    }
+
+    /// return the assembly listing of the ROM
+    pub fn listing(&self, syntax: Syntax) -> String {
+        let comment = syntax.comment();
+        let mut listing = String::new();
+        writeln!(
+            listing,
+            "{} {} bytes, {} instructions reached from 0x{:03X}",
+            comment,
+            self.rom.len(),
+            self.cfg.instruction_count(),
+            PROGRAM_START
+        )
+        .unwrap();
+
+        for (address, item) in self.items.iter() {
+            if let Some(label) = self.label(*address) {
+                match syntax {
+                    Syntax::Cowgod => writeln!(listing, "\n{}:", label).unwrap(),
+                    Syntax::Octo => writeln!(listing, "\n: {}", label).unwrap(),
+                }
+            }
+
+            let (text, note) = match *item {
+                Item::Code(op) => {
+                    let opcode = self.cfg.opcode(*address).unwrap();
+                    let text = match syntax {
+                        Syntax::Cowgod => self.cowgod(op),
+                        Syntax::Octo => self.octo(op, opcode),
+                    };
+                    (
+                        text,
+                        format!("{}{:03X}: {:04X}", self.usage(*address, 2), address, opcode),
+                    )
+                }
+                Item::Data(byte) => {
+                    let text = match syntax {
+                        Syntax::Cowgod => format!("DB 0x{:02X}", byte),
+                        Syntax::Octo => format!("0x{:02X}", byte),
+                    };
+                    (
+                        text,
+                        format!(
+                            "{}{:03X}: {}",
+                            self.usage(*address, 1),
+                            address,
+                            bitmap(byte)
+                        ),
+                    )
+                }
+            };
+            writeln!(
+                listing,
+                "    {:width$}{} {}",
+                text,
+                comment,
+                note,
+                width = COMMENT_COLUMN - 4
+            )
+            .unwrap();
+        }
+
+        listing
+    }
+
+    /// return the coverage flags of the bytes of an item followed by a space, or nothing without coverage
+    fn usage(&self, address: u16, len: u16) -> String {
+        match self.coverage {
+            Some(coverage) => {
+                let flags = (address..address + len).fold(0, |f, a| f | coverage.flags(a));
+                format!("{} ", Coverage::describe(flags))
+            }
+            None => String::new(),
+        }
+    }
+
+    /// format an instruction with Cowgod's mnemonics, using labels for addresses
+    fn cowgod(&self, op: Op) -> String {
+        match op {
+            Op::SYS { address } => format!("SYS {}", self.target(address)),
+            Op::JP { address } => format!("JP {}", self.target(address)),
+            Op::CALL { address } => format!("CALL {}", self.target(address)),
+            Op::LDA { address } => format!("LD I, {}", self.target(address)),
+            Op::JPV { address } => format!("JP V0, {}", self.target(address)),
+            _ => op.to_string(),
+        }
+    }
+
+    /// format an instruction as an Octo statement, using labels for addresses
+    fn octo(&self, op: Op, opcode: u16) -> String {
+        match op {
+            // Octo has no statement for machine code routines, emit the raw bytes
+            Op::SYS { .. } => format!("0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF),
+            Op::CLS => String::from("clear"),
+            Op::RET => String::from("return"),
+            Op::JP { address } => format!("jump {}", self.target(address)),
+            Op::CALL { address } => match self.label(address) {
+                Some(label) => label,
+                None => format!(":call 0x{:03X}", address),
+            },
+            Op::SE { reg, byte } => format!("if v{:x} != 0x{:02X} then", reg, byte),
+            Op::SNE2 { reg, byte } => format!("if v{:x} == 0x{:02X} then", reg, byte),
+            Op::SEV { reg_x, reg_y } => format!("if v{:x} != v{:x} then", reg_x, reg_y),
+            Op::LD { reg, byte } => format!("v{:x} := 0x{:02X}", reg, byte),
+            Op::ADD { reg, byte } => format!("v{:x} += 0x{:02X}", reg, byte),
+            Op::LDR { reg_x, reg_y } => format!("v{:x} := v{:x}", reg_x, reg_y),
+            Op::OR { reg_x, reg_y } => format!("v{:x} |= v{:x}", reg_x, reg_y),
+            Op::AND { reg_x, reg_y } => format!("v{:x} &= v{:x}", reg_x, reg_y),
+            Op::XOR { reg_x, reg_y } => format!("v{:x} ^= v{:x}", reg_x, reg_y),
+            Op::ADD2 { reg_x, reg_y } => format!("v{:x} += v{:x}", reg_x, reg_y),
+            Op::SUB { reg_x, reg_y } => format!("v{:x} -= v{:x}", reg_x, reg_y),
+            Op::SHR { reg_x, reg_y } => format!("v{:x} >>= v{:x}", reg_x, reg_y),
+            Op::SUBN { reg_x, reg_y } => format!("v{:x} =- v{:x}", reg_x, reg_y),
+            Op::SHL { reg_x, reg_y } => format!("v{:x} <<= v{:x}", reg_x, reg_y),
+            Op::SNE { reg_x, reg_y } => format!("if v{:x} == v{:x} then", reg_x, reg_y),
+            Op::LDA { address } => format!("i := {}", self.target(address)),
+            Op::JPV { address } => format!("jump0 {}", self.target(address)),
+            Op::RND { reg, byte } => format!("v{:x} := random 0x{:02X}", reg, byte),
+            Op::DRW { reg_x, reg_y, n } => format!("sprite v{:x} v{:x} {}", reg_x, reg_y, n),
+            Op::SKP { reg } => format!("if v{:x} -key then", reg),
+            Op::SKNP { reg } => format!("if v{:x} key then", reg),
+            Op::LDT { reg } => format!("v{:x} := delay", reg),
+            Op::LDK { reg } => format!("v{:x} := key", reg),
+            Op::LDF { reg } => format!("delay := v{:x}", reg),
+            Op::LDS { reg } => format!("buzzer := v{:x}", reg),
+            Op::ADDI { reg } => format!("i += v{:x}", reg),
+            Op::LDX { reg } => format!("i := hex v{:x}", reg),
+            Op::LDB { reg } => format!("bcd v{:x}", reg),
+            Op::LDI { reg } => format!("save v{:x}", reg),
+            Op::LDJ { reg } => format!("load v{:x}", reg),
+        }
+    }
+}
+
+/// render a byte as a row of sprite pixels
+fn bitmap(byte: u8) -> String {
+    (0..8)
+        .map(|bit| if byte >> (7 - bit) & 1 == 1 { '#' } else { '.' })
+        .collect()
+}
@@ -1,6 +1,8 @@
 use sdl2::video;
 use std::error;
 use std::fmt::{Debug, Display, Formatter};
+use std::io;
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub enum BuildManagerError {
@@ -10,6 +12,7 @@ pub enum BuildManagerError {
     WindowError(video::WindowBuildError),
     AudioDeviceError(String),
     WindowCanvasError(sdl2::IntegerOrSdlError),
+    TextureError(sdl2::render::TextureValueError),
     EventPumpError(String),
 }
 
@@ -34,6 +37,9 @@ impl Display for BuildManagerError {
             BuildManagerError::WindowCanvasError(e) => {
                 write!(f, "Build window canvas failed, {}", e)
             }
+            BuildManagerError::TextureError(e) => {
+                write!(f, "Build texture failed, {}", e)
+            }
             BuildManagerError::EventPumpError(e) => {
                 write!(f, "Build event pump failed, {}", e)
             }
@@ -46,3 +52,77 @@ impl error::Error for BuildManagerError {
         None
     }
 }
+
+#[derive(Debug)]
+pub enum AssembleError {
+    ReadFileError(PathBuf, io::Error),
+    SyntaxError {
+        file: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssembleError::ReadFileError(path, _) => {
+                write!(f, "Read source file {} failed", path.display())
+            }
+            AssembleError::SyntaxError {
+                file,
+                line,
+                message,
+            } => {
+                write!(f, "{}:{}: {}", file.display(), line, message)
+            }
+        }
+    }
+}
+
+impl error::Error for AssembleError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            AssembleError::ReadFileError(_, e) => Some(e),
+            AssembleError::SyntaxError { .. } => None,
+        }
+    }
+}
+
+/// an error of the emulated program, it stops the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    InvalidOpcode { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::InvalidOpcode { pc, opcode } => {
+                write!(f, "Invalid opcode {:04X} at {:03X}", opcode, pc)
+            }
+            Fault::StackOverflow { pc } => {
+                write!(
+                    f,
+                    "Stack overflow, CALL at {:03X} with 16 return addresses",
+                    pc
+                )
+            }
+            Fault::StackUnderflow { pc } => {
+                write!(
+                    f,
+                    "Stack underflow, RET at {:03X} with no return address",
+                    pc
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for Fault {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
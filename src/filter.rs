@@ -0,0 +1,308 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::framebuffer::Framebuffer;
+use crate::palette::Rgb;
+
+/// the share of its brightness a pixel under a scanline or the grid keeps, in percent
+const SHADE: u32 = 50;
+
+/// darken the color of a pixel under a scanline or the grid
+pub fn shade(rgb: Rgb) -> Rgb {
+    rgb.map(|channel| (channel as u32 * SHADE / 100) as u8)
+}
+
+/// the smoothing of the edges of the pixels as the display is scaled up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Smoothing {
+    None,
+    Scale2x, // EPX, a pixel becomes 2x2 which follow the diagonal edges of its neighbors
+    Scale3x, // the same at 3x3
+}
+
+impl Smoothing {
+    /// return the pixels a display pixel is split into along each axis
+    fn factor(&self) -> usize {
+        match self {
+            Smoothing::None => 1,
+            Smoothing::Scale2x => 2,
+            Smoothing::Scale3x => 3,
+        }
+    }
+}
+
+/// the post-processing of the display as it is scaled up, done in software for the window, the screenshots and the recordings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filters {
+    pub smoothing: Smoothing,
+    pub scanlines: bool, // every other row of the image is darkened
+    pub grid: bool,      // the last row and column of each display pixel are darkened
+}
+
+impl Filters {
+    /// create the filters which leave the display as it is
+    pub fn new() -> Filters {
+        Filters {
+            smoothing: Smoothing::None,
+            scanlines: false,
+            grid: false,
+        }
+    }
+
+    /// check whether the filters leave the display as it is
+    pub fn is_empty(&self) -> bool {
+        *self == Filters::new()
+    }
+
+    /// return the scale the filters draw at when asked for a scale, which is at least the one they need to show
+    pub fn scale(&self, scale: usize) -> usize {
+        let lines = if self.scanlines || self.grid { 2 } else { 1 };
+        scale.max(self.smoothing.factor()).max(lines)
+    }
+
+    /// scale up the pixels of the display, row by row, to `self.scale(scale)`.
+    /// return each pixel of the image and whether it is darkened by a scanline or the grid
+    pub fn apply<T: Copy + PartialEq>(&self, pixels: &[T], scale: usize) -> Vec<(T, bool)> {
+        let scale = self.scale(scale);
+        let factor = self.smoothing.factor();
+        let smoothed = match self.smoothing {
+            Smoothing::None => pixels.to_vec(),
+            Smoothing::Scale2x => scale2x(pixels),
+            Smoothing::Scale3x => scale3x(pixels),
+        };
+        let smoothed_width = Framebuffer::width() * factor;
+        let (width, height) = (Framebuffer::width() * scale, Framebuffer::height() * scale);
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let pixel = smoothed[y * factor / scale * smoothed_width + x * factor / scale];
+                let shaded = (self.scanlines && y % 2 == 1)
+                    || (self.grid && (x % scale == scale - 1 || y % scale == scale - 1));
+                (pixel, shaded)
+            })
+            .collect()
+    }
+}
+
+impl Default for Filters {
+    fn default() -> Filters {
+        Filters::new()
+    }
+}
+
+/// return the pixel at the offset from a pixel of the display, or the pixel itself past the edges
+fn neighbor<T: Copy>(pixels: &[T], x: usize, y: usize, dx: isize, dy: isize) -> T {
+    let (nx, ny) = (x as isize + dx, y as isize + dy);
+    if nx < 0
+        || ny < 0
+        || nx >= Framebuffer::width() as isize
+        || ny >= Framebuffer::height() as isize
+    {
+        pixels[y * Framebuffer::width() + x]
+    } else {
+        pixels[ny as usize * Framebuffer::width() + nx as usize]
+    }
+}
+
+/// scale up the pixels of the display 2x with EPX
+fn scale2x<T: Copy + PartialEq>(pixels: &[T]) -> Vec<T> {
+    let width = Framebuffer::width() * 2;
+    let mut scaled = vec![pixels[0]; pixels.len() * 4];
+    for y in 0..Framebuffer::height() {
+        for x in 0..Framebuffer::width() {
+            let p = pixels[y * Framebuffer::width() + x];
+            let a = neighbor(pixels, x, y, 0, -1);
+            let b = neighbor(pixels, x, y, 1, 0);
+            let c = neighbor(pixels, x, y, -1, 0);
+            let d = neighbor(pixels, x, y, 0, 1);
+            let block = [
+                if c == a && c != d && a != b { a } else { p },
+                if a == b && a != c && b != d { b } else { p },
+                if d == c && d != b && c != a { c } else { p },
+                if b == d && b != a && d != c { d } else { p },
+            ];
+            for (i, pixel) in block.into_iter().enumerate() {
+                scaled[(y * 2 + i / 2) * width + x * 2 + i % 2] = pixel;
+            }
+        }
+    }
+    scaled
+}
+
+/// scale up the pixels of the display 3x with the 3x extension of EPX
+fn scale3x<T: Copy + PartialEq>(pixels: &[T]) -> Vec<T> {
+    let width = Framebuffer::width() * 3;
+    let mut scaled = vec![pixels[0]; pixels.len() * 9];
+    for y in 0..Framebuffer::height() {
+        for x in 0..Framebuffer::width() {
+            // the pixel is e, among its neighbors:
+            // a b c
+            // d e f
+            // g h i
+            let n = |dx, dy| neighbor(pixels, x, y, dx, dy);
+            let (a, b, c) = (n(-1, -1), n(0, -1), n(1, -1));
+            let (d, e, f) = (n(-1, 0), n(0, 0), n(1, 0));
+            let (g, h, i) = (n(-1, 1), n(0, 1), n(1, 1));
+            let (top_left, top_right) = (d == b && b != f && d != h, b == f && b != d && f != h);
+            let (bottom_left, bottom_right) =
+                (d == h && d != b && h != f, h == f && d != h && b != f);
+            let block = [
+                if top_left { d } else { e },
+                if (top_left && e != c) || (top_right && e != a) {
+                    b
+                } else {
+                    e
+                },
+                if top_right { f } else { e },
+                if (top_left && e != g) || (bottom_left && e != a) {
+                    d
+                } else {
+                    e
+                },
+                e,
+                if (top_right && e != i) || (bottom_right && e != c) {
+                    f
+                } else {
+                    e
+                },
+                if bottom_left { d } else { e },
+                if (bottom_left && e != i) || (bottom_right && e != g) {
+                    h
+                } else {
+                    e
+                },
+                if bottom_right { f } else { e },
+            ];
+            for (i, pixel) in block.into_iter().enumerate() {
+                scaled[(y * 3 + i / 3) * width + x * 3 + i % 3] = pixel;
+            }
+        }
+    }
+    scaled
+}
+
+impl FromStr for Filters {
+    type Err = String;
+
+    /// parse the filters separated by commas, from scale2x or scale3x, scanlines and grid, or none
+    fn from_str(s: &str) -> Result<Filters, String> {
+        let mut filters = Filters::new();
+        for filter in s.split(',') {
+            match filter.trim().to_ascii_lowercase().as_str() {
+                "none" => {}
+                "scale2x" | "epx" => filters.smoothing = Smoothing::Scale2x,
+                "scale3x" => filters.smoothing = Smoothing::Scale3x,
+                "scanlines" => filters.scanlines = true,
+                "grid" => filters.grid = true,
+                _ => return Err(format!("unknown filter: {}", filter)),
+            }
+        }
+        Ok(filters)
+    }
+}
+
+impl fmt::Display for Filters {
+    /// write the filters as `Filters::from_str` reads them
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = vec![];
+        match self.smoothing {
+            Smoothing::None => {}
+            Smoothing::Scale2x => names.push("scale2x"),
+            Smoothing::Scale3x => names.push("scale3x"),
+        }
+        if self.scanlines {
+            names.push("scanlines");
+        }
+        if self.grid {
+            names.push("grid");
+        }
+        if names.is_empty() {
+            names.push("none");
+        }
+        write!(f, "{}", names.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a display with the pixels lit
+    fn display(lit: &[(usize, usize)]) -> Vec<bool> {
+        let mut pixels = vec![false; Framebuffer::width() * Framebuffer::height()];
+        lit.iter()
+            .for_each(|&(x, y)| pixels[y * Framebuffer::width() + x] = true);
+        pixels
+    }
+
+    /// the square of a scaled image from the display pixel at (1, 1) on, away from the edges of the display,
+    /// a row of `#` for lit and `.` for dark pixels per line
+    fn square(scaled: &[bool], factor: usize, size: usize) -> Vec<String> {
+        let width = Framebuffer::width() * factor;
+        (factor..factor + size)
+            .map(|y| {
+                (factor..factor + size)
+                    .map(|x| if scaled[y * width + x] { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scale2x_keeps_a_lone_pixel_square() {
+        let scaled = scale2x(&display(&[(2, 2)]));
+        assert_eq!(
+            square(&scaled, 2, 6),
+            ["......", "......", "..##..", "..##..", "......", "......"]
+        );
+    }
+
+    #[test]
+    fn scale2x_smooths_a_diagonal() {
+        let scaled = scale2x(&display(&[(1, 1), (2, 2), (3, 3)]));
+        let expected = ["##....", "###...", ".###..", "..###.", "...###", "....##"];
+        assert_eq!(square(&scaled, 2, 6), expected);
+    }
+
+    #[test]
+    fn scale3x_keeps_a_lone_pixel_square() {
+        let scaled = scale3x(&display(&[(2, 2)]));
+        let expected = [
+            ".........",
+            ".........",
+            ".........",
+            "...###...",
+            "...###...",
+            "...###...",
+            ".........",
+            ".........",
+            ".........",
+        ];
+        assert_eq!(square(&scaled, 3, 9), expected);
+    }
+
+    #[test]
+    fn scale3x_smooths_a_diagonal() {
+        let scaled = scale3x(&display(&[(1, 1), (2, 2), (3, 3)]));
+        let expected = [
+            "###......",
+            "###......",
+            "####.....",
+            "..####...",
+            "...###...",
+            "...####..",
+            ".....####",
+            "......###",
+            "......###",
+        ];
+        assert_eq!(square(&scaled, 3, 9), expected);
+    }
+
+    #[test]
+    fn filters_draw_at_the_least_scale_they_need() {
+        assert_eq!(Filters::new().scale(1), 1);
+        assert_eq!("scanlines".parse::<Filters>().unwrap().scale(1), 2);
+        assert_eq!("scale3x,grid".parse::<Filters>().unwrap().scale(1), 3);
+        assert_eq!("scale2x".parse::<Filters>().unwrap().scale(5), 5);
+    }
+}
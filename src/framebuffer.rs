@@ -0,0 +1,461 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::chip8::display::Display;
+use crate::filter::{self, Filters};
+use crate::palette::Palette;
+
+/// the characters of a lit pixel in ASCII art, the first one is written
+const LIT: &[char] = &['#', 'X', 'x', '*', '@', 'O', '1', '█'];
+
+/// the characters of a dark pixel in ASCII art, the first one is written
+const DARK: &[char] = &['.', ' ', '0', '-', '_'];
+
+/// a picture of the display, it is read from and written to PBM, PPM, PNG and ASCII art files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    pixels: Vec<bool>, // row by row
+}
+
+/// return an invalid data error
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// return the lowercase extension of a path
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+}
+
+/// return the luminance of a color
+fn luminance(red: usize, green: usize, blue: usize) -> usize {
+    (red * 299 + green * 587 + blue * 114) / 1000
+}
+
+impl Framebuffer {
+    /// create a dark framebuffer
+    pub fn new() -> Framebuffer {
+        Framebuffer {
+            pixels: vec![false; Framebuffer::width() * Framebuffer::height()],
+        }
+    }
+
+    /// return the width in pixels
+    pub fn width() -> usize {
+        Display::display_width()
+    }
+
+    /// return the height in pixels
+    pub fn height() -> usize {
+        Display::display_height()
+    }
+
+    /// take the picture of the display
+    pub fn from_display(display: &Display) -> Framebuffer {
+        let pixels = (0..Framebuffer::height())
+            .flat_map(|y| (0..Framebuffer::width()).map(move |x| (x, y)))
+            .map(|(x, y)| display.pixel(x, y))
+            .collect();
+        Framebuffer { pixels }
+    }
+
+    /// create the framebuffer of an image, which is the display size or an integer multiple of it
+    fn from_fn(
+        width: usize,
+        height: usize,
+        lit: impl Fn(usize, usize) -> bool,
+    ) -> io::Result<Framebuffer> {
+        let scale = width / Framebuffer::width();
+        if scale == 0
+            || width != scale * Framebuffer::width()
+            || height != scale * Framebuffer::height()
+        {
+            return Err(invalid(format!(
+                "the image is {}x{}, not {}x{} or a multiple of it",
+                width,
+                height,
+                Framebuffer::width(),
+                Framebuffer::height()
+            )));
+        }
+
+        // a scaled pixel is sampled at its center
+        let pixels = (0..Framebuffer::height())
+            .flat_map(|y| (0..Framebuffer::width()).map(move |x| (x, y)))
+            .map(|(x, y)| lit(x * scale + scale / 2, y * scale + scale / 2))
+            .collect();
+        Ok(Framebuffer { pixels })
+    }
+
+    /// check whether the pixel on the (x, y) is lit
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[y * Framebuffer::width() + x]
+    }
+
+    /// return the coordinates of the pixels which differ from another framebuffer
+    pub fn differences(&self, other: &Framebuffer) -> Vec<(usize, usize)> {
+        (0..Framebuffer::height())
+            .flat_map(|y| (0..Framebuffer::width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.pixel(x, y) != other.pixel(x, y))
+            .collect()
+    }
+
+    /// draw the framebuffer as ASCII art, a line per row with `#` for lit and `.` for dark pixels
+    pub fn to_ascii(&self) -> String {
+        self.pixels
+            .chunks(Framebuffer::width())
+            .map(|row| {
+                let mut line: String = row
+                    .iter()
+                    .map(|&pixel| if pixel { LIT[0] } else { DARK[0] })
+                    .collect();
+                line.push('\n');
+                line
+            })
+            .collect()
+    }
+
+    /// draw the framebuffer as ASCII art against the expected one,
+    /// with `+` for pixels lit but expected dark and `-` for pixels dark but expected lit
+    pub fn to_ascii_diff(&self, expected: &Framebuffer) -> String {
+        let mut art = String::new();
+        for y in 0..Framebuffer::height() {
+            for x in 0..Framebuffer::width() {
+                art.push(match (self.pixel(x, y), expected.pixel(x, y)) {
+                    (true, true) => LIT[0],
+                    (false, false) => DARK[0],
+                    (true, false) => '+',
+                    (false, true) => '-',
+                });
+            }
+            art.push('\n');
+        }
+        art
+    }
+
+    /// return the pixels scaled up through the filters to `filters.scale(scale)`, row by row,
+    /// each with whether it is darkened by a scanline or the grid
+    pub fn filtered(&self, filters: &Filters, scale: usize) -> Vec<(bool, bool)> {
+        filters.apply(&self.pixels, scale)
+    }
+
+    /// return the pixels scaled up through the filters as RGB bytes row by row, in the colors of the palette
+    pub fn to_rgb(&self, palette: &Palette, filters: &Filters, scale: usize) -> Vec<u8> {
+        self.filtered(filters, scale)
+            .into_iter()
+            .flat_map(|(lit, shaded)| {
+                if shaded {
+                    filter::shade(palette.color(lit))
+                } else {
+                    palette.color(lit)
+                }
+            })
+            .collect()
+    }
+
+    /// read a framebuffer: a PBM (.pbm) where 1 is lit, a PPM (.ppm) or PNG (.png) where bright pixels are lit,
+    /// or ASCII art with a line per row where `#` is lit and `.` dark
+    pub fn load(path: &Path) -> io::Result<Framebuffer> {
+        match extension(path).as_deref() {
+            Some("pbm") | Some("ppm") => Framebuffer::parse_pnm(&fs::read(path)?),
+            Some("png") => Framebuffer::decode_png(File::open(path)?),
+            _ => Framebuffer::parse_ascii(&fs::read_to_string(path)?),
+        }
+    }
+
+    /// write the framebuffer at the native size, as a PBM (.pbm), a PPM (.ppm) or PNG (.png) in white on black,
+    /// or ASCII art
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        match extension(path).as_deref() {
+            Some("pbm") => fs::write(path, self.to_pbm()),
+            Some("ppm") | Some("png") => {
+                self.save_image(path, &Palette::monochrome(), &Filters::new(), 1)
+            }
+            _ => fs::write(path, self.to_ascii()),
+        }
+    }
+
+    /// write the framebuffer scaled up through the filters in the colors of the palette, as a PNG (.png) or a PPM (.ppm)
+    pub fn save_image(
+        &self,
+        path: &Path,
+        palette: &Palette,
+        filters: &Filters,
+        scale: usize,
+    ) -> io::Result<()> {
+        let scale = filters.scale(scale.max(1));
+        let (width, height) = (Framebuffer::width() * scale, Framebuffer::height() * scale);
+        let rgb = self.to_rgb(palette, filters, scale);
+        match extension(path).as_deref() {
+            Some("png") => {
+                let mut encoder = png::Encoder::new(
+                    BufWriter::new(File::create(path)?),
+                    width as u32,
+                    height as u32,
+                );
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                let mut writer = encoder.write_header().map_err(io::Error::other)?;
+                writer.write_image_data(&rgb).map_err(io::Error::other)?;
+                writer.finish().map_err(io::Error::other)
+            }
+            Some("ppm") => {
+                let mut file = BufWriter::new(File::create(path)?);
+                write!(file, "P6\n{} {}\n255\n", width, height)?;
+                file.write_all(&rgb)?;
+                file.flush()
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unknown image format of {}, expected .png or .ppm",
+                    path.display()
+                ),
+            )),
+        }
+    }
+
+    /// encode the framebuffer as a plain PBM
+    fn to_pbm(&self) -> String {
+        let mut pbm = format!("P1\n{} {}\n", Framebuffer::width(), Framebuffer::height());
+        for row in self.pixels.chunks(Framebuffer::width()) {
+            let bits: Vec<&str> = row
+                .iter()
+                .map(|&pixel| if pixel { "1" } else { "0" })
+                .collect();
+            pbm.push_str(&bits.join(" "));
+            pbm.push('\n');
+        }
+        pbm
+    }
+
+    /// parse ASCII art, short lines are dark at the end
+    fn parse_ascii(text: &str) -> io::Result<Framebuffer> {
+        let lines: Vec<&str> = text.trim_end_matches(['\n', '\r']).lines().collect();
+        if lines.len() != Framebuffer::height() {
+            return Err(invalid(format!(
+                "the ASCII art has {} lines, not {}",
+                lines.len(),
+                Framebuffer::height()
+            )));
+        }
+
+        let mut framebuffer = Framebuffer::new();
+        for (y, line) in lines.iter().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.chars().count() > Framebuffer::width() {
+                return Err(invalid(format!(
+                    "line {} of the ASCII art is longer than {} pixels",
+                    y + 1,
+                    Framebuffer::width()
+                )));
+            }
+            for (x, c) in line.chars().enumerate() {
+                framebuffer.pixels[y * Framebuffer::width() + x] = if LIT.contains(&c) {
+                    true
+                } else if DARK.contains(&c) {
+                    false
+                } else {
+                    return Err(invalid(format!(
+                        "unknown pixel `{}` in line {} of the ASCII art",
+                        c,
+                        y + 1
+                    )));
+                };
+            }
+        }
+        Ok(framebuffer)
+    }
+
+    /// parse a plain (P1) or raw (P4) PBM, or a plain (P3) or raw (P6) PPM
+    fn parse_pnm(bytes: &[u8]) -> io::Result<Framebuffer> {
+        // the header is the magic number, the width, the height and the maximum value of a PPM,
+        // separated by whitespace and comments
+        let mut fields: Vec<String> = vec![];
+        let mut position = 0;
+        let header_len = |fields: &[String]| match fields.first().map(String::as_str) {
+            Some("P3") | Some("P6") => 4,
+            _ => 3,
+        };
+        while fields.len() < header_len(&fields) && position < bytes.len() {
+            match bytes[position] {
+                b'#' => {
+                    while position < bytes.len() && bytes[position] != b'\n' {
+                        position += 1;
+                    }
+                }
+                byte if byte.is_ascii_whitespace() => position += 1,
+                _ => {
+                    let start = position;
+                    while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+                        position += 1;
+                    }
+                    fields.push(String::from_utf8_lossy(&bytes[start..position]).into_owned());
+                }
+            }
+        }
+        let number = |field: Option<&String>| {
+            field
+                .and_then(|f| f.parse::<usize>().ok())
+                .filter(|&n| n > 0)
+                .ok_or(invalid(String::from("invalid PBM or PPM header")))
+        };
+        let (width, height) = (number(fields.get(1))?, number(fields.get(2))?);
+        // a single whitespace character ends the header
+        let data = bytes.get(position + 1..).unwrap_or_default();
+        let too_few = || invalid(String::from("the image has too few pixels"));
+
+        match fields[0].as_str() {
+            "P1" => {
+                let bits: Vec<bool> = data
+                    .iter()
+                    .filter(|b| matches!(b, b'0' | b'1'))
+                    .map(|&b| b == b'1')
+                    .collect();
+                if bits.len() < width * height {
+                    return Err(too_few());
+                }
+                Framebuffer::from_fn(width, height, |x, y| bits[y * width + x])
+            }
+            "P4" => {
+                let row = width.div_ceil(8);
+                if data.len() < row * height {
+                    return Err(too_few());
+                }
+                Framebuffer::from_fn(width, height, |x, y| {
+                    data[y * row + x / 8] >> (7 - x % 8) & 1 == 1
+                })
+            }
+            "P3" | "P6" => {
+                let max = number(fields.get(3))?;
+                let samples: Vec<usize> = if fields[0] == "P6" && max < 256 {
+                    data.iter().map(|&b| b as usize).collect()
+                } else if fields[0] == "P6" {
+                    data.chunks_exact(2)
+                        .map(|b| (b[0] as usize) << 8 | b[1] as usize)
+                        .collect()
+                } else {
+                    String::from_utf8_lossy(data)
+                        .split_ascii_whitespace()
+                        .map(|sample| {
+                            sample
+                                .parse()
+                                .map_err(|_| invalid(String::from("invalid PPM sample")))
+                        })
+                        .collect::<io::Result<_>>()?
+                };
+                if samples.len() < width * height * 3 {
+                    return Err(too_few());
+                }
+                Framebuffer::from_fn(width, height, |x, y| {
+                    let pixel = &samples[(y * width + x) * 3..][..3];
+                    luminance(pixel[0], pixel[1], pixel[2]) * 2 > max
+                })
+            }
+            magic => Err(invalid(format!(
+                "unknown PBM or PPM magic number {}",
+                magic
+            ))),
+        }
+    }
+
+    /// decode a PNG, pixels brighter than half are lit
+    fn decode_png(file: File) -> io::Result<Framebuffer> {
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(|e| invalid(e.to_string()))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buffer)
+            .map_err(|e| invalid(e.to_string()))?;
+
+        let channels = info.color_type.samples();
+        let (width, height) = (info.width as usize, info.height as usize);
+        Framebuffer::from_fn(width, height, |x, y| {
+            let pixel = &buffer[y * info.line_size + x * channels..][..channels];
+            let luminance = match channels {
+                1 | 2 => pixel[0] as usize,
+                _ => luminance(pixel[0] as usize, pixel[1] as usize, pixel[2] as usize),
+            };
+            luminance > 127
+        })
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Framebuffer {
+        Framebuffer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// return a framebuffer with a checkerboard in the top left corner and a lit bottom right corner
+    fn picture() -> Framebuffer {
+        Framebuffer::from_fn(Framebuffer::width(), Framebuffer::height(), |x, y| {
+            (x < 16 && y < 8 && (x + y) % 2 == 0) || (x == 63 && y == 31)
+        })
+        .unwrap()
+    }
+
+    /// return a path in the temporary directory which is unique to the test
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chip8-framebuffer-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn saved_framebuffers_load_back() {
+        for name in ["picture.pbm", "picture.ppm", "picture.png", "picture.txt"] {
+            let path = temp_path(name);
+            picture().save(&path).unwrap();
+            let loaded = Framebuffer::load(&path);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.unwrap(), picture(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn scaled_images_load_back() {
+        for name in ["scaled.ppm", "scaled.png"] {
+            let path = temp_path(name);
+            picture()
+                .save_image(&path, &Palette::monochrome(), &Filters::new(), 3)
+                .unwrap();
+            let loaded = Framebuffer::load(&path);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.unwrap(), picture(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn images_which_are_not_a_multiple_of_the_display_are_invalid() {
+        assert!(Framebuffer::from_fn(64, 32, |_, _| false).is_ok());
+        assert!(Framebuffer::from_fn(128, 64, |_, _| false).is_ok());
+        assert!(Framebuffer::from_fn(32, 16, |_, _| false).is_err());
+        assert!(Framebuffer::from_fn(128, 32, |_, _| false).is_err());
+        assert!(Framebuffer::from_fn(65, 32, |_, _| false).is_err());
+    }
+
+    #[test]
+    fn images_with_too_few_pixels_are_invalid() {
+        let pbm = picture().to_pbm();
+        assert_eq!(Framebuffer::parse_pnm(pbm.as_bytes()).unwrap(), picture());
+        let truncated = &pbm[..pbm.len() - 4];
+        assert!(Framebuffer::parse_pnm(truncated.as_bytes()).is_err());
+        assert!(Framebuffer::parse_pnm(b"P4\n64 32\n\0\0\0").is_err());
+        assert!(Framebuffer::parse_pnm(b"P6\n64 32\n255\n\xFF\xFF\xFF").is_err());
+        assert!(Framebuffer::parse_pnm(b"P3\n64 32\n255\n255 255 255").is_err());
+
+        let ascii = picture().to_ascii();
+        assert_eq!(Framebuffer::parse_ascii(&ascii).unwrap(), picture());
+        let missing_line = ascii.lines().skip(1).collect::<Vec<_>>().join("\n");
+        assert!(Framebuffer::parse_ascii(&missing_line).is_err());
+        let long_line = format!("{}#\n{}", "#".repeat(64), missing_line);
+        assert!(Framebuffer::parse_ascii(&long_line).is_err());
+    }
+}
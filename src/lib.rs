@@ -1,15 +1,213 @@
+use std::fs;
+
+use crate::cfg::{Cfg, GraphFormat};
+use crate::chip8::memory::Memory;
+use crate::chip8::settings::Settings;
+use crate::chip8::trace::{TraceReader, Tracer};
 use crate::chip8::CHIP8;
-use crate::cli::Cli;
+use crate::cli::{AsmCli, Cli, DisasmCli, RomTestCli, TraceDiffCli};
+use crate::disasm::Disassembler;
+use crate::framebuffer::Framebuffer;
+use crate::lint::Lint;
+use crate::symbols::Symbols;
 
+pub mod asm;
+pub mod audio;
+pub mod cfg;
 pub mod chip8;
 pub mod cli;
+pub mod disasm;
 pub mod error;
+pub mod filter;
+pub mod framebuffer;
+pub mod lint;
 pub mod manager;
+pub mod octo;
+pub mod palette;
+pub mod phosphor;
+pub mod quirks;
+pub mod recorder;
+pub mod romtest;
+pub mod symbols;
+pub mod terminal;
+pub mod tracediff;
 
 /// run the CHIP-8 emulator
 pub fn run(args: Cli) -> Result<(), Box<dyn std::error::Error>> {
     let mut chip8 = CHIP8::new(args.game_name());
-    chip8.run(args.game_path(), args.if_print_instruction())?;
+    if let Some(path) = args.symbols_path() {
+        chip8.load_symbols(&path)?;
+    }
+    if let Some(path) = args.trace_path() {
+        let tracer = Tracer::create(path, args.trace_format(), args.trace_filter())?;
+        chip8.set_tracer(tracer);
+    } else if args.if_print_instruction() {
+        chip8.set_tracer(Tracer::stdout(args.trace_filter()));
+    }
+    if args.if_profile() {
+        chip8.enable_profiler();
+    }
+    if args.if_coverage() {
+        chip8.enable_coverage();
+    }
+    chip8.load_game(args.game_path())?;
+    let mut settings = settings(&args, chip8.rom())?;
+    if let Some(palette) = args.palette() {
+        settings.palette = palette;
+    }
+    if let Some(filters) = args.filters() {
+        settings.filters = filters;
+    }
+    chip8.set_settings(settings);
+    chip8.set_record_scale(args.record_scale());
+    chip8.set_window_scale(args.scale(), args.if_integer_scale());
+    if let Some(path) = args.record_path() {
+        let palette = chip8.settings().palette;
+        chip8.start_recording(path, &palette, args.record_scale())?;
+    }
+    if let Some(path) = args.record_audio_path() {
+        chip8.start_audio_capture(path)?;
+    }
+    if args.if_tui() {
+        chip8.run_terminal(args.game_path())?;
+    } else {
+        chip8.run(args.game_path(), args.if_debug())?;
+    }
 
     Ok(())
 }
+
+/// return the settings of the game: those of its config file, those known for it, or the quirks detected by running it,
+/// which are saved to the config file for the next runs. `--detect-quirks` detects them even if they are known
+fn settings(args: &Cli, rom: &[u8]) -> Result<Settings, Box<dyn std::error::Error>> {
+    let game_name = args.game_name();
+    let config_path = args.config_path();
+    if !args.if_detect_quirks() {
+        if config_path.exists() {
+            let settings = Settings::load(game_name, &config_path)?;
+            println!("INFO: Settings were loaded from {}.", config_path.display());
+            return Ok(settings);
+        }
+        if Settings::is_known(game_name) {
+            return Ok(Settings::new(game_name));
+        }
+    }
+
+    println!(
+        "INFO: Detecting the quirks of the game, they will be saved to {}.",
+        config_path.display()
+    );
+    let detection = match quirks::detect(rom, game_name) {
+        Ok(detection) => detection,
+        Err(err) => {
+            println!("ERROR: Detect quirks failed, {}.", err);
+            return Ok(Settings::new(game_name));
+        }
+    };
+    print!("{}", detection.report());
+    if !detection.is_sane() {
+        println!("INFO: No quirk profile behaves sanely, the least faulty one is used.");
+    }
+    let settings = detection.settings().clone();
+    let comment = format!(
+        "settings of {}, the quirks were detected by mychip8",
+        game_name
+    );
+    match settings.save(&config_path, &comment) {
+        Ok(()) => println!("INFO: Settings were saved to {}.", config_path.display()),
+        Err(err) => println!("ERROR: Save settings failed, {}.", err),
+    }
+    Ok(settings)
+}
+
+/// disassemble a CHIP-8 ROM
+pub fn disasm(args: DisasmCli) -> Result<(), Box<dyn std::error::Error>> {
+    let rom = fs::read(args.rom_path())?;
+    Memory::check_program(&rom)?;
+    let symbols = match args.symbols_path() {
+        Some(path) => Symbols::load(&path)?,
+        None => Symbols::new(),
+    };
+    let listing = match args.graph() {
+        _ if args.if_lint() => Lint::new(&Cfg::new(&rom)).report(&symbols),
+        Some(GraphFormat::Dot) => Cfg::new(&rom).dot(&symbols),
+        Some(GraphFormat::Json) => Cfg::new(&rom).json(&symbols),
+        None => {
+            let mut disassembler = Disassembler::new(&rom);
+            disassembler.set_symbols(&symbols);
+            disassembler.listing(args.syntax())
+        }
+    };
+
+    match args.output() {
+        Some(path) => fs::write(path, listing)?,
+        None => print!("{}", listing),
+    }
+
+    Ok(())
+}
+
+/// assemble a source file into a CHIP-8 ROM, Octo sources (.8o) also write a symbol map (.sym)
+pub fn asm(args: AsmCli) -> Result<(), Box<dyn std::error::Error>> {
+    let source_path = args.source_path();
+    let rom = if source_path.extension().is_some_and(|ext| ext == "8o") {
+        let program = octo::compile(source_path)?;
+        let symbol_path = args.output().with_extension("sym");
+        fs::write(&symbol_path, program.symbol_map())?;
+        println!("INFO: Symbol map written to {}.", symbol_path.display());
+        program.rom
+    } else {
+        asm::assemble(source_path)?
+    };
+    fs::write(args.output(), &rom)?;
+    println!(
+        "INFO: {} bytes written to {}.",
+        rom.len(),
+        args.output().display()
+    );
+
+    Ok(())
+}
+
+/// compare two execution traces and print the first divergence
+/// return false if the traces differ
+pub fn tracediff(args: TraceDiffCli) -> Result<bool, Box<dyn std::error::Error>> {
+    let left = TraceReader::open(args.left())?;
+    let right = TraceReader::open(args.right())?;
+    let outcome = tracediff::diff(left, right, args.options())?;
+    print!("{}", tracediff::report(&outcome));
+
+    Ok(matches!(outcome, tracediff::Outcome::Identical(_)))
+}
+
+/// run a ROM without a window and compare the display to the expected image
+/// return false if the test failed
+pub fn romtest(args: RomTestCli) -> Result<bool, Box<dyn std::error::Error>> {
+    let game_name = args.game_name();
+    let mut chip8 = CHIP8::new(&game_name);
+    chip8.load_game(args.rom_path())?;
+    let mut settings = match args.config_path() {
+        Some(path) => Settings::load(&game_name, &path)?,
+        None => Settings::new(&game_name),
+    };
+    for line in args.settings() {
+        settings.set(line)?;
+    }
+    chip8.set_settings(settings);
+    chip8.seed(args.seed());
+    let expected = args.expect().map(Framebuffer::load).transpose()?;
+
+    if let Some(path) = args.audio() {
+        chip8.start_audio_capture(path)?;
+    }
+
+    let outcome = romtest::run(&mut chip8, args.length(), args.keys());
+    chip8.stop_audio_capture()?;
+    if let Some(path) = args.actual() {
+        outcome.framebuffer.save(path)?;
+    }
+    let (passed, report) = romtest::check(&outcome, expected.as_ref());
+    print!("{}", report);
+
+    Ok(passed)
+}
@@ -0,0 +1,576 @@
+use std::fmt::Write;
+
+use crate::cfg::{Block, Cfg, EdgeKind};
+use crate::chip8::operation::Op;
+use crate::symbols::Symbols;
+
+/// the number of instructions followed after a site to see how its result is used
+const LOOKAHEAD: usize = 12;
+
+/// the display size sprites are clipped or wrapped at
+const DISPLAY_WIDTH: u8 = 64;
+const DISPLAY_HEIGHT: u8 = 32;
+
+/// a behaviour which differs between CHIP-8 interpreters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quirk {
+    Shift,         // 8xy6/8xyE shift Vy into Vx, or shift Vx in place
+    LoadStore,     // Fx55/Fx65 leave I unchanged, or advance it past the registers
+    IndexOverflow, // Fx1E sets VF when I overflows, or leaves VF alone
+    JumpV0,        // Bnnn jumps to nnn + V0, or to xnn + Vx on SUPER-CHIP
+    Clip,          // sprites crossing the bottom edge are clipped, or wrap around
+    Wrap,          // sprites crossing the right edge wrap around
+}
+
+impl Quirk {
+    /// return the name of the quirk
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quirk::Shift => "shift",
+            Quirk::LoadStore => "load/store",
+            Quirk::IndexOverflow => "index overflow",
+            Quirk::JumpV0 => "jump with offset",
+            Quirk::Clip => "vertical clipping",
+            Quirk::Wrap => "horizontal wrapping",
+        }
+    }
+
+    /// return the `Settings` flag of the quirk, if mychip8 has one
+    pub fn setting(&self) -> Option<&'static str> {
+        match self {
+            Quirk::Shift => Some("shift_vx_ignore_vy"),
+            Quirk::LoadStore => Some("increment_i_register"),
+            Quirk::IndexOverflow => Some("set_vf_when_overflow"),
+            Quirk::Clip => Some("vertical_wrap"),
+            Quirk::JumpV0 | Quirk::Wrap => None,
+        }
+    }
+}
+
+/// an instruction whose behaviour depends on a quirk
+#[derive(Debug, Clone)]
+pub struct Site {
+    pub address: u16,
+    pub op: Op,
+    pub quirk: Quirk,
+    pub reason: String,
+    pub hint: Option<bool>, // the value of the quirk's setting the site points to, if any
+}
+
+/// the likely `Settings` values of a ROM, None where the ROM gives no evidence
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Suggestion {
+    pub shift_vx_ignore_vy: Option<bool>,
+    pub increment_i_register: Option<bool>,
+    pub set_vf_when_overflow: Option<bool>,
+    pub vertical_wrap: Option<bool>,
+}
+
+impl Suggestion {
+    /// return the values in the order shift, load/store, index overflow and vertical wrap
+    pub fn values(&self) -> [Option<bool>; 4] {
+        [
+            self.shift_vx_ignore_vy,
+            self.increment_i_register,
+            self.set_vf_when_overflow,
+            self.vertical_wrap,
+        ]
+    }
+}
+
+/// the quirk-sensitive sites of a ROM
+pub struct Lint {
+    sites: Vec<Site>,
+}
+
+/// return the registers the instruction reads as a bit mask
+fn reads(op: Op) -> u16 {
+    let r = |reg: u8| 1u16 << (reg & 0xF);
+    match op {
+        Op::SE { reg, .. } | Op::SNE2 { reg, .. } | Op::ADD { reg, .. } => r(reg),
+        Op::SKP { reg } | Op::SKNP { reg } | Op::LDF { reg } | Op::LDS { reg } => r(reg),
+        Op::ADDI { reg } | Op::LDX { reg } | Op::LDB { reg } => r(reg),
+        Op::LDR { reg_y, .. } => r(reg_y),
+        Op::SEV { reg_x, reg_y }
+        | Op::SNE { reg_x, reg_y }
+        | Op::OR { reg_x, reg_y }
+        | Op::AND { reg_x, reg_y }
+        | Op::XOR { reg_x, reg_y }
+        | Op::ADD2 { reg_x, reg_y }
+        | Op::SUB { reg_x, reg_y }
+        | Op::SHR { reg_x, reg_y }
+        | Op::SUBN { reg_x, reg_y }
+        | Op::SHL { reg_x, reg_y }
+        | Op::DRW { reg_x, reg_y, .. } => r(reg_x) | r(reg_y),
+        Op::JPV { .. } => r(0),
+        Op::LDI { reg } => (1u32 << (reg as u32 + 1)).wrapping_sub(1) as u16,
+        _ => 0,
+    }
+}
+
+/// return the registers the instruction writes as a bit mask
+fn writes(op: Op) -> u16 {
+    let r = |reg: u8| 1u16 << (reg & 0xF);
+    match op {
+        Op::LD { reg, .. } | Op::ADD { reg, .. } | Op::RND { reg, .. } => r(reg),
+        Op::LDT { reg } | Op::LDK { reg } => r(reg),
+        Op::LDR { reg_x, .. } | Op::OR { reg_x, .. } | Op::AND { reg_x, .. } => r(reg_x),
+        Op::XOR { reg_x, .. } => r(reg_x),
+        Op::ADD2 { reg_x, .. }
+        | Op::SUB { reg_x, .. }
+        | Op::SHR { reg_x, .. }
+        | Op::SUBN { reg_x, .. }
+        | Op::SHL { reg_x, .. } => r(reg_x) | r(0xF),
+        Op::DRW { .. } => r(0xF),
+        Op::LDJ { reg } => (1u32 << (reg as u32 + 1)).wrapping_sub(1) as u16,
+        _ => 0,
+    }
+}
+
+/// check whether the instruction reads I
+fn reads_i(op: Op) -> bool {
+    matches!(
+        op,
+        Op::DRW { .. } | Op::LDB { .. } | Op::LDI { .. } | Op::LDJ { .. } | Op::ADDI { .. }
+    )
+}
+
+/// return the instructions which follow the one at address on its only path, stopping after a branch
+fn following(cfg: &Cfg, address: u16) -> Vec<(u16, Op)> {
+    let mut instructions = vec![];
+    let mut block: Option<&Block> = cfg.block_at(address);
+    let mut after = address;
+    while let Some(current) = block {
+        for &(a, op) in current.instructions.iter().filter(|&&(a, _)| a > after) {
+            instructions.push((a, op));
+            if instructions.len() == LOOKAHEAD {
+                return instructions;
+            }
+        }
+        // only a path without choices is followed, and not into subroutines
+        block = match current.successors.as_slice() {
+            [edge] if matches!(edge.kind, EdgeKind::Next | EdgeKind::Jump) => {
+                cfg.block_at(edge.target).filter(|b| b.start == edge.target)
+            }
+            _ => None,
+        };
+        after = 0;
+    }
+    instructions
+}
+
+/// return the instructions which run before the one at address, in order, following the blocks
+/// which fall through into each other
+fn preceding(cfg: &Cfg, address: u16) -> Vec<(u16, Op)> {
+    let mut instructions = vec![];
+    let mut block: Option<&Block> = cfg.block_at(address);
+    let mut before = address;
+    while let Some(current) = block {
+        for &(a, op) in current
+            .instructions
+            .iter()
+            .rev()
+            .filter(|&&(a, _)| a < before)
+        {
+            instructions.insert(0, (a, op));
+            if instructions.len() == LOOKAHEAD {
+                return instructions;
+            }
+        }
+        // the other paths into the block are not followed
+        block = cfg
+            .block_at(current.start.wrapping_sub(1))
+            .filter(|b| b.end == current.start)
+            .filter(|b| {
+                b.successors
+                    .iter()
+                    .any(|e| e.target == current.start && e.kind == EdgeKind::Next)
+            });
+        before = u16::MAX;
+    }
+    instructions
+}
+
+impl Lint {
+    /// find the quirk-sensitive sites of the reached instructions
+    pub fn new(cfg: &Cfg) -> Lint {
+        let mut sites = vec![];
+        for block in cfg.blocks() {
+            // the constant values of the registers in the block, for the sprite coordinates
+            let mut constants: [Option<u8>; 16] = [None; 16];
+            for &(address, op) in block.instructions.iter() {
+                let site = |quirk: Quirk, reason: String, hint: Option<bool>| Site {
+                    address,
+                    op,
+                    quirk,
+                    reason,
+                    hint,
+                };
+                match op {
+                    Op::SHR { reg_x, reg_y } | Op::SHL { reg_x, reg_y } if reg_x != reg_y => {
+                        // shifting Vy into Vx discards Vx, a value just written to it would be lost,
+                        // while a program written for shifting Vy prepares Vy first
+                        let earlier = preceding(cfg, address);
+                        let last_write = |reg: u8| {
+                            earlier
+                                .iter()
+                                .rposition(|&(_, op)| writes(op) & (1 << reg) != 0)
+                        };
+                        let (reason, hint) = match (last_write(reg_x), last_write(reg_y)) {
+                            (Some(w), _)
+                                if earlier[w + 1..]
+                                    .iter()
+                                    .all(|&(_, op)| reads(op) & (1 << reg_x) == 0) =>
+                            {
+                                let reason = format!(
+                                    "V{:X} is set before, it is likely shifted in place",
+                                    reg_x
+                                );
+                                (reason, Some(true))
+                            }
+                            (_, Some(_)) => {
+                                let reason = format!(
+                                    "V{:X} is set before, it is likely the value shifted",
+                                    reg_y
+                                );
+                                (reason, Some(false))
+                            }
+                            _ => (String::from("neither register is set before"), None),
+                        };
+                        sites.push(site(Quirk::Shift, reason, hint));
+                    }
+                    Op::LDI { .. } | Op::LDJ { .. } => {
+                        let next = following(cfg, address).into_iter().find(|&(_, next)| {
+                            reads_i(next) || matches!(next, Op::LDA { .. } | Op::LDX { .. })
+                        });
+                        // storing or loading on in the same direction expects I to have moved past the registers,
+                        // writing back what was loaded, drawing or adding to I expects it unchanged
+                        let expectation = match (op, next) {
+                            (Op::LDI { .. }, Some((_, Op::LDI { .. })))
+                            | (Op::LDJ { .. }, Some((_, Op::LDJ { .. }))) => {
+                                Some(("to have advanced", true))
+                            }
+                            (_, Some((_, Op::LDI { .. } | Op::LDJ { .. } | Op::DRW { .. })))
+                            | (_, Some((_, Op::ADDI { .. }))) => Some(("unchanged", false)),
+                            _ => None,
+                        };
+                        if let (Some((at, next)), Some((state, hint))) = (next, expectation) {
+                            sites.push(site(
+                                Quirk::LoadStore,
+                                format!(
+                                    "{:03X}: {} uses I again, likely expecting it {}",
+                                    at, next, state
+                                ),
+                                Some(hint),
+                            ));
+                        }
+                    }
+                    Op::ADDI { .. } => {
+                        // VF is a flag of the overflow if it is read before it is written
+                        let next = following(cfg, address)
+                            .into_iter()
+                            .find(|&(_, next)| (reads(next) | writes(next)) & 0x8000 != 0);
+                        if let Some((at, next)) =
+                            next.filter(|&(_, next)| reads(next) & 0x8000 != 0)
+                        {
+                            sites.push(site(
+                                Quirk::IndexOverflow,
+                                format!("{:03X}: {} reads VF after it", at, next),
+                                Some(true),
+                            ));
+                        }
+                    }
+                    Op::JPV { address: base } => sites.push(site(
+                        Quirk::JumpV0,
+                        format!(
+                            "jumps to 0x{:03X} + V0, SUPER-CHIP jumps to 0x{:03X} + V{:X}",
+                            base,
+                            base,
+                            (base >> 8) & 0xF
+                        ),
+                        None,
+                    )),
+                    Op::DRW { reg_x, reg_y, n } => {
+                        if let Some(y) = constants[reg_y as usize] {
+                            let y = y % DISPLAY_HEIGHT;
+                            if y as usize + n as usize > DISPLAY_HEIGHT as usize {
+                                sites.push(site(
+                                    Quirk::Clip,
+                                    format!(
+                                        "a sprite of {} rows at y = {} crosses the bottom edge",
+                                        n, y
+                                    ),
+                                    None,
+                                ));
+                            }
+                        }
+                        if let Some(x) = constants[reg_x as usize] {
+                            let x = x % DISPLAY_WIDTH;
+                            if x > DISPLAY_WIDTH - 8 {
+                                sites.push(site(
+                                    Quirk::Wrap,
+                                    format!("a sprite at x = {} crosses the right edge", x),
+                                    None,
+                                ));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+
+                // follow the constant values of the registers
+                match op {
+                    Op::LD { reg, byte } => constants[reg as usize] = Some(byte),
+                    Op::ADD { reg, byte } => {
+                        constants[reg as usize] =
+                            constants[reg as usize].map(|v| v.wrapping_add(byte))
+                    }
+                    Op::LDR { reg_x, reg_y } => {
+                        constants[reg_x as usize] = constants[reg_y as usize]
+                    }
+                    _ => (0..16)
+                        .filter(|reg| writes(op) & (1 << reg) != 0)
+                        .for_each(|reg| constants[reg] = None),
+                }
+            }
+        }
+        sites.sort_by_key(|site| site.address);
+        Lint { sites }
+    }
+
+    /// return the quirk-sensitive sites in address order
+    pub fn sites(&self) -> &[Site] {
+        &self.sites
+    }
+
+    /// return the setting most sites of the quirk point to, None if they do not point anywhere or are tied
+    fn vote(&self, quirk: Quirk) -> Option<bool> {
+        let hints = self
+            .sites
+            .iter()
+            .filter(|s| s.quirk == quirk)
+            .filter_map(|s| s.hint);
+        let (yes, no) = hints.fold(
+            (0, 0),
+            |(yes, no), hint| if hint { (yes + 1, no) } else { (yes, no + 1) },
+        );
+        match yes.cmp(&no) {
+            std::cmp::Ordering::Greater => Some(true),
+            std::cmp::Ordering::Less => Some(false),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+
+    /// return the likely `Settings` values
+    pub fn suggestion(&self) -> Suggestion {
+        Suggestion {
+            shift_vx_ignore_vy: self.vote(Quirk::Shift),
+            increment_i_register: self.vote(Quirk::LoadStore),
+            set_vf_when_overflow: self.vote(Quirk::IndexOverflow),
+            vertical_wrap: self.vote(Quirk::Clip),
+        }
+    }
+
+    /// return the report of the sites and the suggested settings
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let mut report = String::new();
+        writeln!(report, "{} quirk-sensitive sites", self.sites.len()).unwrap();
+        for site in self.sites.iter() {
+            writeln!(
+                report,
+                "{}: {:<20} [{}] {}",
+                symbols.describe(site.address),
+                symbols.instruction(site.op),
+                site.quirk.name(),
+                site.reason
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nSuggested settings:").unwrap();
+        let suggestion = self.suggestion();
+        for (quirk, value) in [
+            (Quirk::Shift, suggestion.shift_vx_ignore_vy),
+            (Quirk::LoadStore, suggestion.increment_i_register),
+            (Quirk::IndexOverflow, suggestion.set_vf_when_overflow),
+            (Quirk::Clip, suggestion.vertical_wrap),
+        ] {
+            let setting = quirk.setting().unwrap();
+            let count = self.sites.iter().filter(|s| s.quirk == quirk).count();
+            match value {
+                Some(value) => writeln!(report, "    {} = {} ({} sites)", setting, value, count),
+                None if count > 0 => writeln!(
+                    report,
+                    "    {} = ? ({} sites, no clear evidence)",
+                    setting, count
+                ),
+                None => writeln!(report, "    {} = any (no sites)", setting),
+            }
+            .unwrap();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// return the sites of a ROM as their address, quirk and hint, with the suggested settings
+    fn lint(rom: &[u8]) -> (Vec<(u16, Quirk, Option<bool>)>, Suggestion) {
+        let lint = Lint::new(&Cfg::new(rom));
+        let sites = lint
+            .sites()
+            .iter()
+            .map(|site| (site.address, site.quirk, site.hint))
+            .collect();
+        (sites, lint.suggestion())
+    }
+
+    #[test]
+    fn shifts_of_two_registers_point_to_the_register_set_before() {
+        let in_place = [
+            0x61, 0x05, // 0x200: LD V1, 0x05
+            0x81, 0x26, // 0x202: SHR V1, V2
+            0x12, 0x04, // 0x204: JP 0x204
+        ];
+        let (sites, suggestion) = lint(&in_place);
+        assert_eq!(sites, [(0x202, Quirk::Shift, Some(true))]);
+        assert_eq!(suggestion.shift_vx_ignore_vy, Some(true));
+
+        // V1 is read after it is set, so it is not discarded by the shift
+        let shifted = [
+            0x61, 0x05, // 0x200: LD V1, 0x05
+            0x82, 0x10, // 0x202: LD V2, V1
+            0x81, 0x2E, // 0x204: SHL V1, V2
+            0x12, 0x06, // 0x206: JP 0x206
+        ];
+        let (sites, suggestion) = lint(&shifted);
+        assert_eq!(sites, [(0x204, Quirk::Shift, Some(false))]);
+        assert_eq!(suggestion.shift_vx_ignore_vy, Some(false));
+
+        let unknown = [
+            0x81, 0x26, // 0x200: SHR V1, V2
+            0x12, 0x02, // 0x202: JP 0x202
+        ];
+        let (sites, suggestion) = lint(&unknown);
+        assert_eq!(sites, [(0x200, Quirk::Shift, None)]);
+        assert_eq!(suggestion, Suggestion::default());
+
+        // shifting a register into itself is the same with either setting
+        let same = [
+            0x61, 0x05, // 0x200: LD V1, 0x05
+            0x81, 0x16, // 0x202: SHR V1, V1
+            0x12, 0x04, // 0x204: JP 0x204
+        ];
+        assert!(lint(&same).0.is_empty());
+    }
+
+    #[test]
+    fn loads_and_stores_in_the_same_direction_expect_i_to_advance() {
+        let stores = [
+            0xA3, 0x00, // 0x200: LD I, 0x300
+            0xF1, 0x55, // 0x202: LD [I], V1
+            0xF1, 0x55, // 0x204: LD [I], V1
+            0x12, 0x06, // 0x206: JP 0x206
+        ];
+        let (sites, suggestion) = lint(&stores);
+        assert_eq!(sites, [(0x202, Quirk::LoadStore, Some(true))]);
+        assert_eq!(suggestion.increment_i_register, Some(true));
+
+        // writing back what was loaded expects I unchanged
+        let load_store = [
+            0xA3, 0x00, // 0x200: LD I, 0x300
+            0xF1, 0x65, // 0x202: LD V1, [I]
+            0x71, 0x01, // 0x204: ADD V1, 0x01
+            0xF1, 0x55, // 0x206: LD [I], V1
+            0x12, 0x08, // 0x208: JP 0x208
+        ];
+        let (sites, suggestion) = lint(&load_store);
+        assert_eq!(sites, [(0x202, Quirk::LoadStore, Some(false))]);
+        assert_eq!(suggestion.increment_i_register, Some(false));
+
+        let load_draw = [
+            0xA3, 0x00, // 0x200: LD I, 0x300
+            0xF1, 0x65, // 0x202: LD V1, [I]
+            0xD0, 0x15, // 0x204: DRW V0, V1, 5
+            0x12, 0x06, // 0x206: JP 0x206
+        ];
+        assert_eq!(lint(&load_draw).0, [(0x202, Quirk::LoadStore, Some(false))]);
+
+        // I is set again before it is used, so nothing depends on the quirk
+        let reload = [
+            0xF1, 0x55, // 0x200: LD [I], V1
+            0xA3, 0x00, // 0x202: LD I, 0x300
+            0xF1, 0x55, // 0x204: LD [I], V1
+            0x12, 0x06, // 0x206: JP 0x206
+        ];
+        assert!(lint(&reload).0.is_empty());
+    }
+
+    #[test]
+    fn index_overflow_is_expected_when_vf_is_read_after_add_i() {
+        let read = [
+            0xF1, 0x1E, // 0x200: ADD I, V1
+            0x3F, 0x01, // 0x202: SE VF, 0x01
+            0x12, 0x04, // 0x204: JP 0x204
+        ];
+        let (sites, suggestion) = lint(&read);
+        assert_eq!(sites, [(0x200, Quirk::IndexOverflow, Some(true))]);
+        assert_eq!(suggestion.set_vf_when_overflow, Some(true));
+
+        let written = [
+            0xF1, 0x1E, // 0x200: ADD I, V1
+            0x6F, 0x00, // 0x202: LD VF, 0x00
+            0x3F, 0x01, // 0x204: SE VF, 0x01
+            0x12, 0x06, // 0x206: JP 0x206
+        ];
+        assert!(lint(&written).0.is_empty());
+    }
+
+    #[test]
+    fn jumps_with_offset_are_reported_without_a_setting() {
+        let rom = [
+            0xB2, 0x04, // 0x200: JP V0, 0x204
+            0x12, 0x02, // 0x202: JP 0x202
+        ];
+        let (sites, suggestion) = lint(&rom);
+        assert_eq!(sites, [(0x200, Quirk::JumpV0, None)]);
+        assert_eq!(suggestion, Suggestion::default());
+    }
+
+    #[test]
+    fn sprites_at_constant_coordinates_near_the_edges_are_reported() {
+        let edges = [
+            0x60, 0x32, // 0x200: LD V0, 50
+            0x70, 0x0A, // 0x202: ADD V0, 10
+            0x61, 0x1E, // 0x204: LD V1, 30
+            0xD0, 0x15, // 0x206: DRW V0, V1, 5
+            0x12, 0x08, // 0x208: JP 0x208
+        ];
+        let (sites, suggestion) = lint(&edges);
+        assert_eq!(
+            sites,
+            [(0x206, Quirk::Clip, None), (0x206, Quirk::Wrap, None)]
+        );
+        assert_eq!(suggestion.vertical_wrap, None);
+
+        // a sprite touching the edges stays inside the display
+        let inside = [
+            0x60, 0x38, // 0x200: LD V0, 56
+            0x61, 0x1B, // 0x202: LD V1, 27
+            0xD0, 0x15, // 0x204: DRW V0, V1, 5
+            0x12, 0x06, // 0x206: JP 0x206
+        ];
+        assert!(lint(&inside).0.is_empty());
+
+        // the coordinates are not known after a random number
+        let random = [
+            0xC0, 0xFF, // 0x200: RND V0, 0xFF
+            0x61, 0x1E, // 0x202: LD V1, 30
+            0xD0, 0x15, // 0x204: DRW V0, V1, 5
+            0x12, 0x06, // 0x206: JP 0x206
+        ];
+        assert_eq!(lint(&random).0, [(0x204, Quirk::Clip, None)]);
+    }
+}
@@ -1,22 +1,33 @@
+use crate::audio::SquareWave;
 use crate::chip8::keymap::KeyMap;
 use crate::error::BuildManagerError;
+use crate::filter::{self, Filters};
+use crate::palette::{Palette, Rgb};
+use crate::phosphor::Phosphor;
 
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::WindowCanvas;
-use sdl2::EventPump;
+use sdl2::render::{Texture, WindowCanvas};
+use sdl2::video::{FullscreenType, Window};
+use sdl2::{EventPump, VideoSubsystem};
 
 const DISPLAY_W: u32 = 64;
 const DISPLAY_H: u32 = 32;
-const DISPLAY_SCALE: u32 = 20;
+
+/// the window pixels per display pixel of a new window, unless another scale is asked for
+pub const DEFAULT_SCALE: u32 = 20;
 
 /// possible keyboard event
 pub enum ManagerEvent {
     KeyDown(u8), // represent the event of pressing a key
     KeyUp(u8),   // represent the event of releasing a key
+    Screenshot,  // save a screenshot of the display
+    Record,      // start or stop recording the display
+    Palette,     // switch to the next palette
+    Redraw,      // the window needs to be drawn again, e.g. after it was resized
     Quit,        // quit event
     None,        // nothing happened
 }
@@ -24,15 +35,30 @@ pub enum ManagerEvent {
 /// manage the video, audio and keyboard events
 pub struct Manager {
     canvas: WindowCanvas,            // used to draw on the screen
+    texture: Texture, // the pixels of the display through the filters, scaled to the window when copied
     device: AudioDevice<SquareWave>, // used to handle the audio device
-    event_pump: EventPump,           // used to listen for event
+    event_pump: EventPump, // used to listen for event
+    palette: Palette, // the colors of the pixels
+    filters: Filters, // the filters of the display, drawn at the least scale they need
+    integer_scale: bool, // whether a display pixel is a whole number of window pixels
+}
+
+/// convert a palette color to an SDL color
+fn color([r, g, b]: Rgb) -> Color {
+    Color::RGBA(r, g, b, 0xFF)
 }
 
 impl Manager {
-    /// create a platform instance
-    pub fn new() -> Result<Manager, BuildManagerError> {
-        let w = DISPLAY_W * DISPLAY_SCALE;
-        let h = DISPLAY_H * DISPLAY_SCALE;
+    /// create a platform instance drawing in the colors of the palette through the filters,
+    /// its resizable window starts with the display scaled up by the scale
+    pub fn new(
+        palette: Palette,
+        filters: Filters,
+        scale: u32,
+        integer_scale: bool,
+    ) -> Result<Manager, BuildManagerError> {
+        let w = DISPLAY_W * scale.max(1);
+        let h = DISPLAY_H * scale.max(1);
 
         let sdl_context = sdl2::init().map_err(BuildManagerError::SdlContextError)?;
         let video_subsystem = sdl_context
@@ -42,12 +68,6 @@ impl Manager {
             .audio()
             .map_err(BuildManagerError::AudioSubsystemError)?;
 
-        let window = video_subsystem
-            .window("CHIP-8", w, h)
-            .position_centered()
-            .build()
-            .map_err(BuildManagerError::WindowError)?;
-
         let desired_spec = AudioSpecDesired {
             freq: Some(523),
             channels: Some(1),
@@ -57,18 +77,23 @@ impl Manager {
         let device = audio_subsystem
             .open_playback(None, &desired_spec, |spec| {
                 // initialize the audio callback
-                SquareWave {
-                    phase_inc: 440.0 / spec.freq as f32,
-                    phase: 0.0,
-                    volume: 0.25,
-                }
+                SquareWave::new(spec.freq as u32)
             })
             .map_err(BuildManagerError::AudioDeviceError)?;
 
-        let canvas = window
-            .into_canvas()
-            .build()
-            .map_err(BuildManagerError::WindowCanvasError)?;
+        // the pixels stay sharp when the texture is scaled
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+        let canvas = build_canvas(&video_subsystem, w, h)?;
+        // the texture lives as long as the renderer of the canvas, which destroys it
+        let texture_scale = filters.scale(1) as u32;
+        let texture = canvas
+            .texture_creator()
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                DISPLAY_W * texture_scale,
+                DISPLAY_H * texture_scale,
+            )
+            .map_err(BuildManagerError::TextureError)?;
 
         let event_pump = sdl_context
             .event_pump()
@@ -76,11 +101,63 @@ impl Manager {
 
         Ok(Manager {
             canvas,
+            texture,
             device,
             event_pump,
+            palette,
+            filters,
+            integer_scale,
         })
     }
 
+    /// return the colors of the pixels
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// change the colors of the pixels, they show at the next draw
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// return the size of a pixel of the display in the window, rounded down to a whole number of window pixels
+    pub fn scale(&self) -> usize {
+        let (w, h) = self.canvas.output_size().unwrap_or((DISPLAY_W, DISPLAY_H));
+        (w / DISPLAY_W).min(h / DISPLAY_H).max(1) as usize
+    }
+
+    /// return where the display is drawn in the window: as large as fits at its aspect ratio,
+    /// or at a whole number of window pixels per display pixel, centered with the rest of the window empty
+    fn viewport(&self) -> Result<Rect, String> {
+        let (w, h) = self.canvas.output_size()?;
+        let (display_w, display_h) = if self.integer_scale {
+            let scale = self.scale() as u32;
+            (DISPLAY_W * scale, DISPLAY_H * scale)
+        } else if w * DISPLAY_H > h * DISPLAY_W {
+            (h * DISPLAY_W / DISPLAY_H, h)
+        } else {
+            (w, w * DISPLAY_H / DISPLAY_W)
+        };
+        Ok(Rect::new(
+            (w as i32 - display_w as i32) / 2,
+            (h as i32 - display_h as i32) / 2,
+            display_w.max(1),
+            display_h.max(1),
+        ))
+    }
+
+    /// switch the window between fullscreen and its size before
+    fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        if let Err(err) = window.set_fullscreen(fullscreen) {
+            println!("ERROR: Toggle fullscreen failed, {}.", err);
+        }
+    }
+
     /// resume or pause the audio device
     pub fn beep(&mut self, beep: bool) {
         if beep {
@@ -90,34 +167,32 @@ impl Manager {
         }
     }
 
-    /// clear all pixels on the screen
-    pub fn clear(&mut self) {
-        self.canvas
-            .set_draw_color(Color::RGBA(0x19, 0x14, 0x28, 0xFF));
-        self.canvas.clear();
-    }
+    /// upload the pixels of the screen to the texture, and present it scaled to the window, the pixel sizes follow the window size.
+    /// with filters the display is scaled up through them in software to the least scale they need, the renderer does the rest
+    pub fn draw(&mut self, phosphor: &Phosphor) -> Result<(), String> {
+        let palette = self.palette;
+        let scale = self.filters.scale(1);
+        let pixels = self.filters.apply(phosphor.levels(), scale);
+        let width = DISPLAY_W as usize * scale;
+        self.texture
+            .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                for (index, &(level, shaded)) in pixels.iter().enumerate() {
+                    let offset = index / width * pitch + index % width * 3;
+                    let rgb = palette.mix(level);
+                    let rgb = if shaded { filter::shade(rgb) } else { rgb };
+                    buffer[offset..offset + 3].copy_from_slice(&rgb);
+                }
+            })?;
 
-    /// clear the current rendering target with the drawing color
-    pub fn present(&mut self) {
+        self.canvas.set_draw_color(color(palette.background));
+        self.canvas.clear();
+        let viewport = self.viewport()?;
+        self.canvas.copy(&self.texture, None, viewport)?;
         self.canvas.present();
-    }
-
-    /// update the screen with any rendering performed since the previous call
-    pub fn draw_pixel(&mut self, x: u8, y: u8) -> Result<(), String> {
-        self.canvas
-            .set_draw_color(Color::RGBA(0xC8, 0xC8, 0xFF, 0xFF));
-
-        let rect = Rect::new(
-            DISPLAY_SCALE as i32 * x as i32,
-            DISPLAY_SCALE as i32 * y as i32,
-            DISPLAY_SCALE,
-            DISPLAY_SCALE,
-        );
-        self.canvas.fill_rect(rect)?;
         Ok(())
     }
 
-    /// listen for events(KeyDown, KeyUp, Quit, None)
+    /// listen for events(KeyDown, KeyUp, Screenshot, Record, Palette, Redraw, Quit, None), F11 toggles fullscreen
     pub fn poll_event(&mut self, keymap: &KeyMap) -> ManagerEvent {
         if let Some(event) = self.event_pump.poll_event() {
             match event {
@@ -126,6 +201,29 @@ impl Manager {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => ManagerEvent::Quit,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => ManagerEvent::Screenshot,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    ..
+                } => ManagerEvent::Record,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => ManagerEvent::Palette,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => {
+                    self.toggle_fullscreen();
+                    ManagerEvent::None
+                }
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                    ..
+                } => ManagerEvent::Redraw,
 
                 Event::KeyDown {
                     keycode: Some(keycode),
@@ -157,10 +255,30 @@ impl Manager {
     }
 }
 
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
+/// build the window and its canvas, with a hardware renderer if there is one, else with the software renderer
+fn build_canvas(video: &VideoSubsystem, w: u32, h: u32) -> Result<WindowCanvas, BuildManagerError> {
+    let window = || -> Result<Window, BuildManagerError> {
+        video
+            .window("CHIP-8", w, h)
+            .position_centered()
+            .resizable()
+            .build()
+            .map_err(BuildManagerError::WindowError)
+    };
+    match window()?.into_canvas().accelerated().build() {
+        Ok(canvas) => Ok(canvas),
+        Err(err) => {
+            println!(
+                "INFO: No hardware renderer ({}), the software renderer is used.",
+                err
+            );
+            window()?
+                .into_canvas()
+                .software()
+                .build()
+                .map_err(BuildManagerError::WindowCanvasError)
+        }
+    }
 }
 
 impl AudioCallback for SquareWave {
@@ -169,12 +287,7 @@ impl AudioCallback for SquareWave {
     fn callback(&mut self, out: &mut [f32]) {
         // Generate a square wave
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            *x = self.sample();
         }
     }
 }
@@ -0,0 +1,940 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::memory::PROGRAM_START;
+use crate::chip8::operation::Op;
+use crate::error::AssembleError;
+
+/// XO-CHIP programs can address 64KB
+const MEMORY_END: u32 = 0x10000;
+
+/// the maximum number of macro expansions, more is most likely a recursive macro
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// the result of a compilation
+pub struct Program {
+    pub rom: Vec<u8>,                // the bytes from 0x200
+    pub symbols: Vec<(String, u16)>, // the labels sorted by address
+}
+
+impl Program {
+    /// return the symbol map, one `label = address` per line
+    pub fn symbol_map(&self) -> String {
+        self.symbols
+            .iter()
+            .map(|(name, address)| format!("{} = 0x{:04X}\n", name, address))
+            .collect()
+    }
+}
+
+/// compile an Octo source file
+pub fn compile(path: &Path) -> Result<Program, AssembleError> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| AssembleError::ReadFileError(path.to_path_buf(), e))?;
+    compile_source(&source, path)
+}
+
+/// compile Octo source text
+pub fn compile_source(source: &str, file: &Path) -> Result<Program, AssembleError> {
+    let mut compiler = Compiler::new(source, file);
+    compiler.compile()?;
+    compiler.program()
+}
+
+/// source token with its line number
+#[derive(Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+/// how a forward reference to a label is patched once the label is defined
+#[derive(Clone, Copy)]
+enum Fixup {
+    Address,        // the low 12 bits of the opcode at the address
+    Long,           // the 16-bit word at the address
+    UnpackHigh(u8), // the byte at the address becomes the nibble and the high 4 bits of the label
+    UnpackLow,      // the byte at the address becomes the low byte of the label
+}
+
+/// a condition of `if` or `while`
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+    Less(u8, Operand),
+    Greater(u8, Operand),
+    LessEqual(u8, Operand),
+    GreaterEqual(u8, Operand),
+}
+
+/// the right hand side of a comparison or an assignment
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Value(u8),
+}
+
+/// an open `if ... begin` block
+struct Block {
+    jump: u32, // the jump over the block, patched at `else` or `end`
+    has_else: bool,
+}
+
+/// an open `loop` block
+struct Loop {
+    start: u32,
+    exits: Vec<u32>, // the jumps of `while`, patched at `again`
+}
+
+struct Compiler {
+    file: PathBuf,
+    tokens: VecDeque<Token>,
+    line: usize, // line of the last token taken
+    image: Vec<u8>,
+    here: u32,
+    end: u32,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, (Vec<String>, Vec<Token>)>,
+    fixups: Vec<(u32, Fixup, String, usize)>, // address, kind, label and line of the reference
+    blocks: Vec<Block>,
+    loops: Vec<Loop>,
+    expansions: usize,
+}
+
+impl Compiler {
+    fn new(source: &str, file: &Path) -> Compiler {
+        let tokens = source
+            .lines()
+            .enumerate()
+            .flat_map(|(i, line)| {
+                line.split('#')
+                    .next()
+                    .unwrap_or("")
+                    .split_whitespace()
+                    .map(move |text| Token {
+                        text: text.to_string(),
+                        line: i + 1,
+                    })
+            })
+            .collect();
+
+        Compiler {
+            file: file.to_path_buf(),
+            tokens,
+            line: 0,
+            image: vec![0; MEMORY_END as usize],
+            here: PROGRAM_START as u32,
+            end: PROGRAM_START as u32,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: vec![],
+            blocks: vec![],
+            loops: vec![],
+            expansions: 0,
+        }
+    }
+
+    /// create a syntax error at the current line
+    fn error(&self, message: String) -> AssembleError {
+        self.error_at(self.line, message)
+    }
+
+    fn error_at(&self, line: usize, message: String) -> AssembleError {
+        AssembleError::SyntaxError {
+            file: self.file.clone(),
+            line,
+            message,
+        }
+    }
+
+    /// take the next token
+    fn next(&mut self) -> Result<String, AssembleError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => Err(self.error(String::from("unexpected end of file"))),
+        }
+    }
+
+    /// take the next token if it is the expected one
+    fn accept(&mut self, expected: &str) -> bool {
+        if self.tokens.front().is_some_and(|t| t.text == expected) {
+            self.tokens.pop_front();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        if token == expected {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{}`, found `{}`", expected, token)))
+        }
+    }
+
+    /// emit a byte at the current address
+    fn byte(&mut self, byte: u8) -> Result<(), AssembleError> {
+        if self.here >= MEMORY_END {
+            return Err(self.error(String::from("program does not fit in memory")));
+        }
+        self.image[self.here as usize] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    /// emit a 16-bit word at the current address
+    fn word(&mut self, word: u16) -> Result<(), AssembleError> {
+        self.byte((word >> 8) as u8)?;
+        self.byte(word as u8)
+    }
+
+    fn op(&mut self, op: Op) -> Result<(), AssembleError> {
+        self.word(op.encode().get_opcode())
+    }
+
+    /// patch the low 12 bits of the opcode at the address, the target of `name` at the line
+    fn patch(
+        &mut self,
+        address: u32,
+        target: u32,
+        name: &str,
+        line: usize,
+    ) -> Result<(), AssembleError> {
+        if target > 0xFFF {
+            return Err(self.error_at(line, format!("`{}` is beyond 0xFFF", name)));
+        }
+        let address = address as usize;
+        self.image[address] = (self.image[address] & 0xF0) | ((target >> 8) & 0x0F) as u8;
+        self.image[address + 1] = target as u8;
+        Ok(())
+    }
+
+    fn compile(&mut self) -> Result<(), AssembleError> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if !self.blocks.is_empty() {
+            return Err(self.error(String::from("`begin` without `end`")));
+        }
+        if !self.loops.is_empty() {
+            return Err(self.error(String::from("`loop` without `again`")));
+        }
+        Ok(())
+    }
+
+    /// resolve the forward references and return the program
+    fn program(mut self) -> Result<Program, AssembleError> {
+        for (address, fixup, name, line) in std::mem::take(&mut self.fixups) {
+            let target = match self.labels.get(&name) {
+                Some(&target) => target as u32,
+                None => return Err(self.error_at(line, format!("undefined label `{}`", name))),
+            };
+            match fixup {
+                Fixup::Address => self.patch(address, target, &name, line)?,
+                Fixup::Long => {
+                    self.image[address as usize] = (target >> 8) as u8;
+                    self.image[address as usize + 1] = target as u8;
+                }
+                Fixup::UnpackHigh(nibble) => {
+                    self.image[address as usize] = nibble << 4 | ((target >> 8) & 0x0F) as u8
+                }
+                Fixup::UnpackLow => self.image[address as usize] = target as u8,
+            }
+        }
+
+        let mut symbols: Vec<(String, u16)> = self.labels.into_iter().collect();
+        symbols.sort_by_key(|&(_, address)| address);
+        Ok(Program {
+            rom: self.image[PROGRAM_START as usize..self.end as usize].to_vec(),
+            symbols,
+        })
+    }
+
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        let token = self.next()?;
+
+        if let Some((params, body)) = self.macros.get(&token).cloned() {
+            return self.expand(&params, body);
+        }
+
+        match token.as_str() {
+            ":" => {
+                let name = self.name()?;
+                if self.labels.insert(name.clone(), self.here as u16).is_some() {
+                    return Err(self.error(format!("label `{}` is defined more than once", name)));
+                }
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let reg = self.register()?;
+                self.aliases.insert(name, reg);
+            }
+            ":macro" => self.define_macro()?,
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+            }
+            ":byte" => {
+                let value = if self.accept("{") {
+                    self.calc()?
+                } else {
+                    self.value()?
+                };
+                self.byte(value as u8)?;
+            }
+            ":org" => {
+                let address = self.value()?;
+                // the bytes below the program start are not part of the ROM
+                if !(PROGRAM_START as i64..MEMORY_END as i64).contains(&address) {
+                    return Err(self.error(format!("address {:#X} is out of range", address)));
+                }
+                self.here = address as u32;
+            }
+            ":call" => self.address_op(|address| Op::CALL { address })?,
+            ":unpack" => {
+                let nibble = self.value()? as u8 & 0x0F;
+                let name = self.next()?;
+                match self.labels.get(&name).copied() {
+                    Some(target) => {
+                        self.op(Op::LD {
+                            reg: 0,
+                            byte: nibble << 4 | (target >> 8) as u8 & 0x0F,
+                        })?;
+                        self.op(Op::LD {
+                            reg: 1,
+                            byte: target as u8,
+                        })?;
+                    }
+                    None => {
+                        self.fixups.push((
+                            self.here + 1,
+                            Fixup::UnpackHigh(nibble),
+                            name.clone(),
+                            self.line,
+                        ));
+                        self.op(Op::LD { reg: 0, byte: 0 })?;
+                        self.fixups
+                            .push((self.here + 1, Fixup::UnpackLow, name, self.line));
+                        self.op(Op::LD { reg: 1, byte: 0 })?;
+                    }
+                }
+            }
+            // debugging directives of the Octo IDE, they emit nothing
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "clear" => self.op(Op::CLS)?,
+            "return" | ";" => self.op(Op::RET)?,
+            "jump" => self.address_op(|address| Op::JP { address })?,
+            "jump0" => self.address_op(|address| Op::JPV { address })?,
+            "bcd" => {
+                let reg = self.register()?;
+                self.op(Op::LDB { reg })?;
+            }
+            "save" | "load" => {
+                let reg_x = self.register()?;
+                if self.accept("-") {
+                    // XO-CHIP register range
+                    let reg_y = self.register()? as u16;
+                    let low = if token == "save" { 2 } else { 3 };
+                    self.word(0x5000 | (reg_x as u16) << 8 | reg_y << 4 | low)?;
+                } else if token == "save" {
+                    self.op(Op::LDI { reg: reg_x })?;
+                } else {
+                    self.op(Op::LDJ { reg: reg_x })?;
+                }
+            }
+            "sprite" => {
+                let reg_x = self.register()?;
+                let reg_y = self.register()?;
+                let n = self.value()?;
+                if !(0..=15).contains(&n) {
+                    return Err(self.error(format!("sprite height {} is out of range", n)));
+                }
+                self.op(Op::DRW {
+                    reg_x,
+                    reg_y,
+                    n: n as u8,
+                })?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let reg = self.register()?;
+                match token.as_str() {
+                    "delay" => self.op(Op::LDF { reg })?,
+                    "buzzer" => self.op(Op::LDS { reg })?,
+                    _ => self.word(0xF03A | (reg as u16) << 8)?,
+                }
+            }
+            "i" => self.index()?,
+            "if" => self.conditional()?,
+            "else" => {
+                let block = match self.blocks.last_mut() {
+                    Some(block) if !block.has_else => block,
+                    _ => return Err(self.error(String::from("`else` without `begin`"))),
+                };
+                block.has_else = true;
+                let jump = block.jump;
+                let skip = self.here;
+                self.op(Op::JP { address: 0 })?;
+                self.patch(jump, self.here, "else", self.line)?;
+                self.blocks.last_mut().unwrap().jump = skip;
+            }
+            "end" => match self.blocks.pop() {
+                Some(block) => self.patch(block.jump, self.here, "end", self.line)?,
+                None => return Err(self.error(String::from("`end` without `begin`"))),
+            },
+            "loop" => self.loops.push(Loop {
+                start: self.here,
+                exits: vec![],
+            }),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(self.error(String::from("`while` outside of a loop")));
+                }
+                let condition = self.condition()?;
+                // skip the exit jump while the condition holds
+                self.skip(&condition, true)?;
+                let exit = self.here;
+                self.op(Op::JP { address: 0 })?;
+                self.loops.last_mut().unwrap().exits.push(exit);
+            }
+            "again" => match self.loops.pop() {
+                Some(l) => {
+                    if l.start > 0xFFF {
+                        return Err(self.error(String::from("`loop` is beyond 0xFFF")));
+                    }
+                    self.op(Op::JP {
+                        address: l.start as u16,
+                    })?;
+                    for exit in l.exits {
+                        self.patch(exit, self.here, "again", self.line)?;
+                    }
+                }
+                None => return Err(self.error(String::from("`again` without `loop`"))),
+            },
+            // SUPER-CHIP
+            "hires" => self.word(0x00FF)?,
+            "lores" => self.word(0x00FE)?,
+            "exit" => self.word(0x00FD)?,
+            "scroll-left" => self.word(0x00FC)?,
+            "scroll-right" => self.word(0x00FB)?,
+            "scroll-down" => {
+                let n = self.value()? as u16 & 0x0F;
+                self.word(0x00C0 | n)?;
+            }
+            "saveflags" => {
+                let reg = self.register()? as u16;
+                self.word(0xF075 | reg << 8)?;
+            }
+            "loadflags" => {
+                let reg = self.register()? as u16;
+                self.word(0xF085 | reg << 8)?;
+            }
+            // XO-CHIP
+            "scroll-up" => {
+                let n = self.value()? as u16 & 0x0F;
+                self.word(0x00D0 | n)?;
+            }
+            "plane" => {
+                let n = self.value()? as u16 & 0x0F;
+                self.word(0xF001 | n << 8)?;
+            }
+            "audio" => self.word(0xF002)?,
+            _ => {
+                if let Some(reg) = self.try_register(&token) {
+                    self.assignment(reg)?;
+                } else if let Some(value) = self.try_number(&token) {
+                    self.byte(value as u8)?;
+                } else if token.starts_with(':') {
+                    return Err(self.error(format!("unknown directive `{}`", token)));
+                } else {
+                    // a bare name calls the subroutine
+                    self.tokens.push_front(Token {
+                        text: token,
+                        line: self.line,
+                    });
+                    self.address_op(|address| Op::CALL { address })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// take a label, constant or macro name
+    fn name(&mut self) -> Result<String, AssembleError> {
+        let name = self.next()?;
+        let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+        if valid && self.try_register(&name).is_none() {
+            Ok(name)
+        } else {
+            Err(self.error(format!("invalid name `{}`", name)))
+        }
+    }
+
+    /// return the register named by the token, v0 to vf or an alias
+    fn try_register(&self, token: &str) -> Option<u8> {
+        if let Some(&reg) = self.aliases.get(token) {
+            return Some(reg);
+        }
+        let digit = token.strip_prefix(['v', 'V'])?;
+        if digit.len() == 1 {
+            u8::from_str_radix(digit, 16).ok()
+        } else {
+            None
+        }
+    }
+
+    fn register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        self.try_register(&token)
+            .ok_or_else(|| self.error(format!("expected a register, found `{}`", token)))
+    }
+
+    /// return the value of a number or constant
+    fn try_number(&self, token: &str) -> Option<i64> {
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token),
+        };
+        let number = if let Some(hex) = digits.strip_prefix("0x") {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(bin) = digits.strip_prefix("0b") {
+            i64::from_str_radix(bin, 2).ok()
+        } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+            digits.parse::<i64>().ok()
+        } else {
+            None
+        };
+        match number {
+            Some(n) if negative => Some(-n),
+            Some(n) => Some(n),
+            None => self.constants.get(token).copied(),
+        }
+    }
+
+    /// return the value of a number, constant or defined label
+    fn try_value(&self, token: &str) -> Option<i64> {
+        self.try_number(token)
+            .or_else(|| self.labels.get(token).map(|&a| a as i64))
+    }
+
+    fn value(&mut self) -> Result<i64, AssembleError> {
+        let token = self.next()?;
+        self.try_value(&token)
+            .ok_or_else(|| self.error(format!("expected a value, found `{}`", token)))
+    }
+
+    /// take a byte value, negative values wrap around
+    fn byte_value(&mut self) -> Result<u8, AssembleError> {
+        let value = self.value()?;
+        if (-0x80..=0xFF).contains(&value) {
+            Ok(value as u8)
+        } else {
+            Err(self.error(format!("{} does not fit in a byte", value)))
+        }
+    }
+
+    /// emit an instruction whose operand is an address, which may be a forward reference
+    fn address_op(&mut self, op: impl Fn(u16) -> Op) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        let address = match self.try_value(&token) {
+            Some(address) if (0..=0xFFF).contains(&address) => address as u16,
+            Some(address) => {
+                return Err(self.error(format!("address {:#X} is out of range", address)))
+            }
+            None => {
+                self.fixups
+                    .push((self.here, Fixup::Address, token, self.line));
+                0
+            }
+        };
+        self.op(op(address))
+    }
+
+    /// `i := ...` and `i += vx`
+    fn index(&mut self) -> Result<(), AssembleError> {
+        let operator = self.next()?;
+        match operator.as_str() {
+            "+=" => {
+                let reg = self.register()?;
+                self.op(Op::ADDI { reg })
+            }
+            ":=" => {
+                if self.accept("hex") {
+                    let reg = self.register()?;
+                    self.op(Op::LDX { reg })
+                } else if self.accept("bighex") {
+                    let reg = self.register()? as u16;
+                    self.word(0xF030 | reg << 8)
+                } else if self.accept("long") {
+                    self.word(0xF000)?;
+                    let token = self.next()?;
+                    match self.try_value(&token) {
+                        Some(address) => self.word(address as u16),
+                        None => {
+                            self.fixups.push((self.here, Fixup::Long, token, self.line));
+                            self.word(0)
+                        }
+                    }
+                } else {
+                    self.address_op(|address| Op::LDA { address })
+                }
+            }
+            _ => Err(self.error(format!("unknown operator `i {}`", operator))),
+        }
+    }
+
+    /// `vx op ...`
+    fn assignment(&mut self, reg: u8) -> Result<(), AssembleError> {
+        let operator = self.next()?;
+        if operator == ":=" {
+            if self.accept("delay") {
+                return self.op(Op::LDT { reg });
+            }
+            if self.accept("key") {
+                return self.op(Op::LDK { reg });
+            }
+            if self.accept("random") {
+                let byte = self.byte_value()?;
+                return self.op(Op::RND { reg, byte });
+            }
+        }
+
+        let token = self.next()?;
+        let op = match self.try_register(&token) {
+            Some(reg_y) => match operator.as_str() {
+                ":=" => Op::LDR { reg_x: reg, reg_y },
+                "+=" => Op::ADD2 { reg_x: reg, reg_y },
+                "-=" => Op::SUB { reg_x: reg, reg_y },
+                "=-" => Op::SUBN { reg_x: reg, reg_y },
+                "|=" => Op::OR { reg_x: reg, reg_y },
+                "&=" => Op::AND { reg_x: reg, reg_y },
+                "^=" => Op::XOR { reg_x: reg, reg_y },
+                ">>=" => Op::SHR { reg_x: reg, reg_y },
+                "<<=" => Op::SHL { reg_x: reg, reg_y },
+                _ => return Err(self.error(format!("unknown operator `{}`", operator))),
+            },
+            None => {
+                self.tokens.push_front(Token {
+                    text: token,
+                    line: self.line,
+                });
+                let byte = self.byte_value()?;
+                match operator.as_str() {
+                    ":=" => Op::LD { reg, byte },
+                    "+=" => Op::ADD { reg, byte },
+                    "-=" => Op::ADD {
+                        reg,
+                        byte: byte.wrapping_neg(),
+                    },
+                    _ => {
+                        return Err(self.error(format!("operator `{}` needs a register", operator)))
+                    }
+                }
+            }
+        };
+        self.op(op)
+    }
+
+    fn operand(&mut self) -> Result<Operand, AssembleError> {
+        let token = self.next()?;
+        if let Some(reg) = self.try_register(&token) {
+            return Ok(Operand::Register(reg));
+        }
+        self.tokens.push_front(Token {
+            text: token,
+            line: self.line,
+        });
+        Ok(Operand::Value(self.byte_value()?))
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssembleError> {
+        let reg = self.register()?;
+        let operator = self.next()?;
+        let condition = match operator.as_str() {
+            "key" => Condition::Key(reg),
+            "-key" => Condition::NotKey(reg),
+            "==" => Condition::Equal(reg, self.operand()?),
+            "!=" => Condition::NotEqual(reg, self.operand()?),
+            "<" => Condition::Less(reg, self.operand()?),
+            ">" => Condition::Greater(reg, self.operand()?),
+            "<=" => Condition::LessEqual(reg, self.operand()?),
+            ">=" => Condition::GreaterEqual(reg, self.operand()?),
+            _ => return Err(self.error(format!("unknown comparison `{}`", operator))),
+        };
+        Ok(condition)
+    }
+
+    /// emit the instructions which skip the next one if the condition is `when`
+    fn skip(&mut self, condition: &Condition, when: bool) -> Result<(), AssembleError> {
+        let equal = |reg, operand, skip_if_equal: bool| match (operand, skip_if_equal) {
+            (Operand::Value(byte), true) => Op::SE { reg, byte },
+            (Operand::Value(byte), false) => Op::SNE2 { reg, byte },
+            (Operand::Register(reg_y), true) => Op::SEV { reg_x: reg, reg_y },
+            (Operand::Register(reg_y), false) => Op::SNE { reg_x: reg, reg_y },
+        };
+
+        match *condition {
+            Condition::Equal(reg, operand) => self.op(equal(reg, operand, when)),
+            Condition::NotEqual(reg, operand) => self.op(equal(reg, operand, !when)),
+            Condition::Key(reg) if when => self.op(Op::SKP { reg }),
+            Condition::Key(reg) => self.op(Op::SKNP { reg }),
+            Condition::NotKey(reg) if when => self.op(Op::SKNP { reg }),
+            Condition::NotKey(reg) => self.op(Op::SKP { reg }),
+            // comparisons go through VF: after the subtraction VF is 1 when there was no borrow
+            Condition::Less(reg, operand)
+            | Condition::Greater(reg, operand)
+            | Condition::LessEqual(reg, operand)
+            | Condition::GreaterEqual(reg, operand) => {
+                // `a - b` has no borrow when a >= b
+                let (vx_minus_operand, holds_without_borrow) = match condition {
+                    Condition::Less(..) => (true, false),
+                    Condition::GreaterEqual(..) => (true, true),
+                    Condition::Greater(..) => (false, false),
+                    _ => (false, true),
+                };
+                match operand {
+                    Operand::Register(reg_y) => {
+                        let (a, b) = if vx_minus_operand {
+                            (reg, reg_y)
+                        } else {
+                            (reg_y, reg)
+                        };
+                        self.op(Op::LDR {
+                            reg_x: 0xF,
+                            reg_y: a,
+                        })?;
+                        self.op(Op::SUB {
+                            reg_x: 0xF,
+                            reg_y: b,
+                        })?;
+                    }
+                    Operand::Value(byte) => {
+                        self.op(Op::LD { reg: 0xF, byte })?;
+                        if vx_minus_operand {
+                            self.op(Op::SUBN {
+                                reg_x: 0xF,
+                                reg_y: reg,
+                            })?;
+                        } else {
+                            self.op(Op::SUB {
+                                reg_x: 0xF,
+                                reg_y: reg,
+                            })?;
+                        }
+                    }
+                }
+                let byte = holds_without_borrow as u8;
+                self.op(equal(0xF, Operand::Value(byte), when))
+            }
+        }
+    }
+
+    /// `if condition then statement` or `if condition begin ... else ... end`
+    fn conditional(&mut self) -> Result<(), AssembleError> {
+        let condition = self.condition()?;
+        let keyword = self.next()?;
+        match keyword.as_str() {
+            "then" => self.skip(&condition, false),
+            "begin" => {
+                // skip the jump over the block when the condition holds
+                self.skip(&condition, true)?;
+                self.blocks.push(Block {
+                    jump: self.here,
+                    has_else: false,
+                });
+                self.op(Op::JP { address: 0 })
+            }
+            _ => Err(self.error(format!("expected `then` or `begin`, found `{}`", keyword))),
+        }
+    }
+
+    /// `:macro name params { body }`
+    fn define_macro(&mut self) -> Result<(), AssembleError> {
+        let name = self.name()?;
+        let mut params = vec![];
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            params.push(token);
+        }
+
+        let mut body = vec![];
+        let mut depth = 1;
+        loop {
+            let token = match self.tokens.pop_front() {
+                Some(token) => token,
+                None => return Err(self.error(format!("macro `{}` is not closed", name))),
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, (params, body));
+        Ok(())
+    }
+
+    /// replace a macro invocation with its body
+    fn expand(&mut self, params: &[String], body: Vec<Token>) -> Result<(), AssembleError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error(String::from("too many macro expansions")));
+        }
+
+        let args = params
+            .iter()
+            .map(|_| self.next())
+            .collect::<Result<Vec<_>, _>>()?;
+        body.into_iter().rev().for_each(|mut token| {
+            if let Some(i) = params.iter().position(|p| *p == token.text) {
+                token.text = args[i].clone();
+            }
+            self.tokens.push_front(token);
+        });
+        Ok(())
+    }
+
+    /// evaluate a `:calc` expression up to the closing brace.
+    /// like Octo, operators have no precedence and are evaluated from right to left
+    fn calc(&mut self) -> Result<i64, AssembleError> {
+        let mut terms = vec![];
+        let mut operators: Vec<String> = vec![];
+        loop {
+            terms.push(self.calc_term()?);
+            let token = self.next()?;
+            match token.as_str() {
+                "}" | ")" => {
+                    let mut value = terms.pop().unwrap();
+                    while let Some(operator) = operators.pop() {
+                        let left = terms.pop().unwrap();
+                        value = self.apply(&operator, left, value)?;
+                    }
+                    return Ok(value);
+                }
+                _ => operators.push(token),
+            }
+        }
+    }
+
+    fn calc_term(&mut self) -> Result<i64, AssembleError> {
+        let token = self.next()?;
+        match token.as_str() {
+            "(" => self.calc(),
+            "-" => Ok(-self.calc_term()?),
+            "~" => Ok(!self.calc_term()?),
+            "HERE" => Ok(self.here as i64),
+            _ => self
+                .try_value(&token)
+                .ok_or_else(|| self.error(format!("unknown value `{}`", token))),
+        }
+    }
+
+    fn apply(&self, operator: &str, left: i64, right: i64) -> Result<i64, AssembleError> {
+        let value = match operator {
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => return Err(self.error(String::from("division by zero"))),
+            "/" => left / right,
+            "%" => left % right,
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            _ => return Err(self.error(format!("unknown operator `{}`", operator))),
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// compile the source and return the error message
+    fn error(source: &str) -> String {
+        match compile_source(source, Path::new("test.8o")) {
+            Ok(_) => String::from("no error"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn blocks_and_loops_patch_their_jumps() {
+        let program = compile_source(
+            "loop v0 += 1 while v0 != 5 again if v0 == 5 begin v1 := 1 else v1 := 2 end",
+            Path::new("test.8o"),
+        )
+        .unwrap();
+        let jumps: Vec<&[u8]> = [4, 6, 10, 14]
+            .iter()
+            .map(|&i| &program.rom[i..i + 2])
+            .collect();
+        // the exit of `while`, the jump of `again` and the jumps of `if` and `else`, patched at `else` and `end`
+        assert_eq!(
+            jumps,
+            [[0x12, 0x08], [0x12, 0x00], [0x12, 0x10], [0x12, 0x12]]
+        );
+    }
+
+    #[test]
+    fn jumps_beyond_0xfff_are_errors() {
+        let source = ":org 0xFFC if v0 == 1 begin v1 := 1 end";
+        assert_eq!(error(source), "test.8o:1: `end` is beyond 0xFFF");
+        let source = ":org 0xFFC if v0 == 1 begin v1 := 1 else v1 := 2 end";
+        assert_eq!(error(source), "test.8o:1: `else` is beyond 0xFFF");
+        let source = ":org 0xFFA loop while v0 != 1 again";
+        assert_eq!(error(source), "test.8o:1: `again` is beyond 0xFFF");
+        let source = ":org 0x1000 loop v0 += 1 again";
+        assert_eq!(error(source), "test.8o:1: `loop` is beyond 0xFFF");
+        let source = "jump far :org 0x1000 : far";
+        assert_eq!(error(source), "test.8o:1: `far` is beyond 0xFFF");
+    }
+
+    #[test]
+    fn org_below_the_program_start_is_an_error() {
+        assert_eq!(
+            error(":org 0x100 0x12"),
+            "test.8o:1: address 0x100 is out of range"
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// a color as red, green and blue
+pub type Rgb = [u8; 3];
+
+/// the colors the display is drawn with.
+/// the last two are those of the second bitplane of XO-CHIP, for a display of four colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub background: Rgb,   // the color of dark pixels
+    pub foreground: Rgb,   // the color of lit pixels, of the first bitplane
+    pub second_plane: Rgb, // the color of pixels lit only in the second bitplane
+    pub both_planes: Rgb,  // the color of pixels lit in both bitplanes
+}
+
+/// the named palettes in the order the hotkey switches between them
+const NAMED: [(&str, Palette); 6] = [
+    (
+        "default",
+        Palette {
+            background: [0x19, 0x14, 0x28],
+            foreground: [0xC8, 0xC8, 0xFF],
+            second_plane: [0xFF, 0x78, 0x78],
+            both_planes: [0xFF, 0xFF, 0xA0],
+        },
+    ),
+    (
+        "green",
+        Palette {
+            background: [0x00, 0x11, 0x00],
+            foreground: [0x33, 0xFF, 0x33],
+            second_plane: [0x1E, 0x8C, 0x1E],
+            both_planes: [0xB4, 0xFF, 0xB4],
+        },
+    ),
+    (
+        "amber",
+        Palette {
+            background: [0x1A, 0x0F, 0x00],
+            foreground: [0xFF, 0xB0, 0x00],
+            second_plane: [0xA0, 0x6E, 0x00],
+            both_planes: [0xFF, 0xE0, 0xA0],
+        },
+    ),
+    (
+        "lcd",
+        Palette {
+            background: [0x9B, 0xBC, 0x0F],
+            foreground: [0x0F, 0x38, 0x0F],
+            second_plane: [0x8B, 0xAC, 0x0F],
+            both_planes: [0x30, 0x62, 0x30],
+        },
+    ),
+    (
+        "high-contrast",
+        Palette {
+            background: [0x00, 0x00, 0x00],
+            foreground: [0xFF, 0xFF, 0xFF],
+            second_plane: [0xFF, 0xFF, 0x00],
+            both_planes: [0x00, 0xFF, 0xFF],
+        },
+    ),
+    // the colors of Okabe and Ito, told apart with any color vision deficiency
+    (
+        "colorblind",
+        Palette {
+            background: [0x00, 0x00, 0x00],
+            foreground: [0xE6, 0x9F, 0x00],
+            second_plane: [0x56, 0xB4, 0xE9],
+            both_planes: [0xF0, 0xE4, 0x42],
+        },
+    ),
+];
+
+impl Palette {
+    /// create the palette of the window
+    pub fn new() -> Palette {
+        NAMED[0].1
+    }
+
+    /// create the palette of white pixels on black, used for test images
+    pub fn monochrome() -> Palette {
+        Palette {
+            background: [0x00, 0x00, 0x00],
+            foreground: [0xFF, 0xFF, 0xFF],
+            second_plane: [0xFF, 0xFF, 0xFF],
+            both_planes: [0xFF, 0xFF, 0xFF],
+        }
+    }
+
+    /// return the names of the named palettes
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        NAMED.iter().map(|(name, _)| *name)
+    }
+
+    /// return the name of the palette, if it is a named one
+    pub fn name(&self) -> Option<&'static str> {
+        NAMED
+            .iter()
+            .find(|(_, palette)| palette == self)
+            .map(|(name, _)| *name)
+    }
+
+    /// return the named palette after this one, the first one after the last or a custom palette
+    pub fn next(&self) -> Palette {
+        let index = NAMED.iter().position(|(_, palette)| palette == self);
+        NAMED[index.map_or(0, |index| (index + 1) % NAMED.len())].1
+    }
+
+    /// return the color of a pixel
+    pub fn color(&self, lit: bool) -> Rgb {
+        if lit {
+            self.foreground
+        } else {
+            self.background
+        }
+    }
+
+    /// return the color of a pixel of a brightness, from the background at 0 to the foreground at 255
+    pub fn mix(&self, level: u8) -> Rgb {
+        let (level, dark) = (level as u32, 255 - level as u32);
+        [0, 1, 2].map(|c| {
+            ((self.background[c] as u32 * dark + self.foreground[c] as u32 * level) / 255) as u8
+        })
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::new()
+    }
+}
+
+/// parse a hex color, e.g. `#33FF33` or `33ff33`
+fn parse_color(s: &str) -> Option<Rgb> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |c: usize| u8::from_str_radix(&hex[c * 2..c * 2 + 2], 16).ok();
+    Some([channel(0)?, channel(1)?, channel(2)?])
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// parse the name of a palette, or 2 or 4 hex colors separated by commas: the background, the foreground
+    /// and the colors of the second bitplane, which are the foreground when they are left out
+    fn from_str(s: &str) -> Result<Palette, String> {
+        let s = s.trim();
+        if let Some((_, palette)) = NAMED.iter().find(|(name, _)| name.eq_ignore_ascii_case(s)) {
+            return Ok(*palette);
+        }
+        let colors = s
+            .split(',')
+            .map(|color| parse_color(color.trim()))
+            .collect::<Option<Vec<Rgb>>>();
+        match colors.as_deref() {
+            Some(&[background, foreground]) => Ok(Palette {
+                background,
+                foreground,
+                second_plane: foreground,
+                both_planes: foreground,
+            }),
+            Some(&[background, foreground, second_plane, both_planes]) => Ok(Palette {
+                background,
+                foreground,
+                second_plane,
+                both_planes,
+            }),
+            _ => Err(format!(
+                "unknown palette: {}, expected one of {} or 2 or 4 hex colors like #000000,#33FF33",
+                s,
+                Palette::names().collect::<Vec<_>>().join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Palette {
+    /// write the name of the palette, or its 4 colors as `Palette::from_str` reads them.
+    /// the colors are written without `#`, which starts a comment in a config file
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = self.name() {
+            return write!(f, "{}", name);
+        }
+        let colors = [
+            self.background,
+            self.foreground,
+            self.second_plane,
+            self.both_planes,
+        ];
+        let hex: Vec<String> = colors
+            .iter()
+            .map(|[r, g, b]| format!("{:02X}{:02X}{:02X}", r, g, b))
+            .collect();
+        write!(f, "{}", hex.join(","))
+    }
+}
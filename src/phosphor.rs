@@ -0,0 +1,70 @@
+use crate::framebuffer::Framebuffer;
+
+/// the brightness of a lit pixel
+pub const LIT: u8 = 0xFF;
+
+/// the persistence of the screen, it smooths the flicker of sprites erased and drawn again with XOR.
+/// a pixel turned off fades over some frames, and the last two frames may be ORed together
+#[derive(Debug, Clone)]
+pub struct Phosphor {
+    fade: u8,    // the frames a pixel turned off takes to fade out, 0 turns it off at once
+    blend: bool, // whether a pixel lit in the previous frame stays lit
+    previous: Framebuffer, // the picture of the previous frame
+    ages: Vec<u8>, // the frames since each pixel was lit, up to fade + 1
+    levels: Vec<u8>, // the brightness of each pixel, row by row
+}
+
+impl Phosphor {
+    /// create the phosphor of a dark screen
+    pub fn new(fade: u8, blend: bool) -> Phosphor {
+        let size = Framebuffer::width() * Framebuffer::height();
+        Phosphor {
+            fade,
+            blend,
+            previous: Framebuffer::new(),
+            ages: vec![fade.saturating_add(1); size],
+            levels: vec![0; size],
+        }
+    }
+
+    /// show the picture of the next frame, return whether the brightness of a pixel changed.
+    /// it is called once per frame, also when the picture did not change, for the pixels to fade
+    pub fn frame(&mut self, picture: &Framebuffer) -> bool {
+        let mut changed = false;
+        for y in 0..Framebuffer::height() {
+            for x in 0..Framebuffer::width() {
+                let index = y * Framebuffer::width() + x;
+                let lit = picture.pixel(x, y) || (self.blend && self.previous.pixel(x, y));
+                let age = &mut self.ages[index];
+                *age = if lit {
+                    0
+                } else {
+                    age.saturating_add(1).min(self.fade.saturating_add(1))
+                };
+                let span = self.fade as u32 + 1;
+                let level = (LIT as u32 * (span - *age as u32) / span) as u8;
+                changed |= self.levels[index] != level;
+                self.levels[index] = level;
+            }
+        }
+        self.previous = picture.clone();
+        changed
+    }
+
+    /// show the picture as it is, without the persistence of the frames before, e.g. while stepping in the debugger
+    pub fn show(&mut self, picture: &Framebuffer) {
+        self.previous = Framebuffer::new();
+        self.ages.fill(self.fade.saturating_add(1));
+        self.frame(picture);
+    }
+
+    /// return the brightness of a pixel, from 0 for dark to `LIT`
+    pub fn level(&self, x: usize, y: usize) -> u8 {
+        self.levels[y * Framebuffer::width() + x]
+    }
+
+    /// return the brightness of the pixels, row by row
+    pub fn levels(&self) -> &[u8] {
+        &self.levels
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::io;
+
+use crate::cfg::Cfg;
+use crate::chip8::display::Display;
+use crate::chip8::settings::Settings;
+use crate::chip8::CHIP8;
+use crate::error::Fault;
+use crate::lint::Lint;
+
+/// the frames each quirk profile runs, 20 seconds at 60 Hz
+const FRAMES: u64 = 1200;
+
+/// the keys tapped one after another, to get past the screens waiting for a key
+const IDLE_KEYS: [u8; 16] = [
+    0x5, 0x4, 0x6, 0x8, 0x2, 0xA, 0xF, 0x0, 0x1, 0x3, 0x7, 0x9, 0xB, 0xC, 0xD, 0xE,
+];
+
+/// the frames between two key taps, the first one is half of it
+const TAP_INTERVAL: u64 = 60;
+
+/// the frames a tapped key is held
+const TAP_LENGTH: u64 = 3;
+
+/// the share of lit pixels above which a screen is taken as garbled
+const MAX_LIT: f64 = 0.5;
+
+/// the seed of the random numbers, the same for every profile so that only the quirks differ
+const SEED: u64 = 0xC8;
+
+/// how a ROM behaved under a quirk profile
+pub struct Run {
+    pub settings: Settings,
+    pub fault: Option<(u64, Fault)>, // the frame the run stopped at and the fault
+    pub screens: usize,              // the number of distinct screens shown
+    pub lit: f64,                    // the largest share of lit pixels in the last second
+}
+
+impl Run {
+    /// run the ROM headless with scripted idle input
+    fn new(rom: &[u8], game_name: &str, settings: Settings) -> io::Result<Run> {
+        let mut chip8 = CHIP8::new(game_name);
+        chip8.set_settings(settings.clone());
+        chip8.seed(SEED);
+        chip8.load(rom)?;
+
+        let mut screens = HashSet::new();
+        let mut fault = None;
+        let mut lit: f64 = 0.0;
+        for frame in 0..FRAMES {
+            let tap = frame + TAP_INTERVAL / 2;
+            let key = IDLE_KEYS[(tap / TAP_INTERVAL) as usize % IDLE_KEYS.len()];
+            match tap % TAP_INTERVAL {
+                0 => chip8.press_key(key),
+                TAP_LENGTH => chip8.release_key(key),
+                _ => {}
+            }
+
+            if let Err(err) = chip8.run_frame() {
+                fault = Some((frame, err));
+                break;
+            }
+            screens.insert(chip8.display().hash());
+            if frame + 60 >= FRAMES {
+                lit = lit.max(chip8.display().lit() as f64 / Display::size() as f64);
+            }
+        }
+
+        Ok(Run {
+            settings,
+            fault,
+            screens: screens.len(),
+            lit,
+        })
+    }
+
+    /// return what went wrong in the run, None if it looks sane
+    pub fn problem(&self) -> Option<String> {
+        if let Some((frame, fault)) = self.fault {
+            Some(format!("{} in frame {}", fault, frame))
+        } else if self.screens <= 1 {
+            Some(String::from("the screen never changes"))
+        } else if self.lit > MAX_LIT {
+            Some(format!(
+                "garbled, {:.0}% of the screen is lit",
+                self.lit * 100.0
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// return the frames run until the end or a fault
+    fn frames(&self) -> u64 {
+        self.fault.map_or(FRAMES, |(frame, _)| frame)
+    }
+}
+
+/// the quirk flags of a profile, in the order of `Suggestion::values`
+fn flags(settings: &Settings) -> [bool; 4] {
+    [
+        settings.shift_vx_ignore_vy,
+        settings.increment_i_register,
+        settings.set_vf_when_overflow,
+        settings.vertical_wrap,
+    ]
+}
+
+/// the runs of a ROM under every quirk profile, and the profile chosen
+pub struct Detection {
+    runs: Vec<Run>,
+    chosen: usize,
+}
+
+impl Detection {
+    /// return the settings of the chosen profile
+    pub fn settings(&self) -> &Settings {
+        &self.runs[self.chosen].settings
+    }
+
+    /// check whether the chosen profile behaved sanely
+    pub fn is_sane(&self) -> bool {
+        self.runs[self.chosen].problem().is_none()
+    }
+
+    /// return a line per profile with how the ROM behaved, the chosen one is marked
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        writeln!(
+            report,
+            "  shift  load/store  index  vertical_wrap  ({} seconds each)",
+            FRAMES / 60
+        )
+        .unwrap();
+        for (index, run) in self.runs.iter().enumerate() {
+            let [shift, increment, overflow, wrap] = flags(&run.settings);
+            let result = run
+                .problem()
+                .unwrap_or(format!("ok, {} screens", run.screens));
+            writeln!(
+                report,
+                "{} {:<6} {:<11} {:<6} {:<14} {}",
+                if index == self.chosen { '*' } else { ' ' },
+                shift,
+                increment,
+                overflow,
+                wrap,
+                result
+            )
+            .unwrap();
+        }
+        report
+    }
+}
+
+/// run the ROM briefly under each combination of the quirk flags and choose the profile which behaves sanely.
+/// of the sane profiles, the one agreeing most with the quirk linter and then closest to the defaults is chosen
+pub fn detect(rom: &[u8], game_name: &str) -> io::Result<Detection> {
+    let suggested = Lint::new(&Cfg::new(rom)).suggestion().values();
+
+    let mut runs = vec![];
+    for profile in 0..16 {
+        let mut settings = Settings::new(game_name);
+        settings.shift_vx_ignore_vy = profile & 0b0001 != 0;
+        settings.increment_i_register = profile & 0b0010 != 0;
+        settings.set_vf_when_overflow = profile & 0b0100 != 0;
+        settings.vertical_wrap = profile & 0b1000 != 0;
+        runs.push(Run::new(rom, game_name, settings)?);
+    }
+
+    let rank = |run: &Run| {
+        let flags = flags(&run.settings);
+        let agreeing = flags
+            .iter()
+            .zip(suggested.iter())
+            .filter(|(flag, suggested)| Some(**flag) == **suggested)
+            .count();
+        let defaults = flags.iter().filter(|&&flag| !flag).count();
+        (run.problem().is_none(), run.frames(), agreeing, defaults)
+    };
+    // max_by_key returns the last of equal ranks, searching backwards keeps the first profile
+    let chosen = (0..runs.len())
+        .rev()
+        .max_by_key(|&index| rank(&runs[index]))
+        .unwrap_or(0);
+
+    Ok(Detection { runs, chosen })
+}
@@ -0,0 +1,184 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use gif::{Encoder, Frame, Repeat};
+
+use crate::filter::{self, Filters};
+use crate::framebuffer::Framebuffer;
+use crate::palette::Palette;
+
+/// the scale of a recording unless another is asked for
+pub const DEFAULT_SCALE: usize = 4;
+
+/// the frames of a recording per second, the rate of the delay timer
+const FRAME_RATE: u64 = 60;
+
+/// GIF players show delays shorter than this (in 1/100 second) slowly,
+/// so a screen shown for less is merged with the next one
+const MIN_DELAY: u64 = 2;
+
+/// a GIF delay is at most 65535/100 second, a screen shown for longer is split
+const MAX_DELAY: u64 = 60000;
+
+/// return the time of a frame in 1/100 second, rounded down so that the delays do not drift
+fn centiseconds(frame: u64) -> u64 {
+    frame * 100 / FRAME_RATE
+}
+
+/// where the frames are written
+enum Output {
+    Gif {
+        encoder: Encoder<BufWriter<File>>,
+        pending: Option<(Framebuffer, u64)>, // the screen not written yet and the frame it appeared at
+    },
+    Raw(BufWriter<File>), // RGB bytes of every frame, one after another
+}
+
+/// record the display a frame at a time, to an animated GIF (.gif) or raw RGB frames (any other extension)
+pub struct Recorder {
+    path: PathBuf,
+    palette: Palette,
+    filters: Filters,
+    scale: usize,
+    frames: u64, // the number of frames recorded
+    output: Output,
+}
+
+impl Recorder {
+    /// create the recording file, the display is scaled up through the filters
+    pub fn create(
+        path: &Path,
+        palette: &Palette,
+        filters: &Filters,
+        scale: usize,
+    ) -> io::Result<Recorder> {
+        let scale = filters.scale(scale.max(1));
+        let file = BufWriter::new(File::create(path)?);
+        let is_gif = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("gif"));
+        let output = if is_gif {
+            let (width, height) = (Framebuffer::width() * scale, Framebuffer::height() * scale);
+            // the colors of the pixels are indexed by whether they are lit, plus 2 if they are darkened
+            let colors = [
+                palette.background,
+                palette.foreground,
+                filter::shade(palette.background),
+                filter::shade(palette.foreground),
+            ]
+            .concat();
+            let mut encoder = Encoder::new(file, width as u16, height as u16, &colors)
+                .map_err(io::Error::other)?;
+            encoder
+                .set_repeat(Repeat::Infinite)
+                .map_err(io::Error::other)?;
+            Output::Gif {
+                encoder,
+                pending: None,
+            }
+        } else {
+            Output::Raw(file)
+        };
+
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            palette: *palette,
+            filters: *filters,
+            scale,
+            frames: 0,
+            output,
+        })
+    }
+
+    /// return the path of the recording
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// return the scale of the recording
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    /// return the number of frames recorded
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// record the screen of the next frame, called 60 times per emulated second
+    pub fn frame(&mut self, framebuffer: &Framebuffer) -> io::Result<()> {
+        let frame = self.frames;
+        self.frames += 1;
+        match &mut self.output {
+            Output::Raw(file) => {
+                file.write_all(&framebuffer.to_rgb(&self.palette, &self.filters, self.scale))
+            }
+            Output::Gif { encoder, pending } => match pending.take() {
+                // the screen is kept until it changes
+                Some((screen, start))
+                    if screen == *framebuffer
+                        && centiseconds(frame) - centiseconds(start) < MAX_DELAY =>
+                {
+                    *pending = Some((screen, start));
+                    Ok(())
+                }
+                // a screen shown too briefly is replaced by the next one
+                Some((_, start)) if centiseconds(frame) - centiseconds(start) < MIN_DELAY => {
+                    *pending = Some((framebuffer.clone(), start));
+                    Ok(())
+                }
+                Some((screen, start)) => {
+                    *pending = Some((framebuffer.clone(), frame));
+                    write_gif_frame(encoder, &screen, &self.filters, self.scale, start, frame)
+                }
+                None => {
+                    *pending = Some((framebuffer.clone(), frame));
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    /// write the last screen and close the recording
+    pub fn finish(self) -> io::Result<()> {
+        match self.output {
+            Output::Raw(mut file) => file.flush(),
+            Output::Gif {
+                mut encoder,
+                pending,
+            } => {
+                if let Some((screen, start)) = pending {
+                    let end = self.frames.max(start + 1);
+                    write_gif_frame(&mut encoder, &screen, &self.filters, self.scale, start, end)?;
+                }
+                encoder.into_inner()?.flush()
+            }
+        }
+    }
+}
+
+/// write a screen shown from the start frame until the end frame to the GIF
+fn write_gif_frame(
+    encoder: &mut Encoder<BufWriter<File>>,
+    screen: &Framebuffer,
+    filters: &Filters,
+    scale: usize,
+    start: u64,
+    end: u64,
+) -> io::Result<()> {
+    let pixels: Vec<u8> = screen
+        .filtered(filters, scale)
+        .iter()
+        .map(|&(lit, shaded)| lit as u8 + shaded as u8 * 2)
+        .collect();
+    let frame = Frame {
+        width: (Framebuffer::width() * scale) as u16,
+        height: (Framebuffer::height() * scale) as u16,
+        delay: (centiseconds(end) - centiseconds(start)).max(1) as u16,
+        buffer: Cow::Owned(pixels),
+        ..Frame::default()
+    };
+    encoder.write_frame(&frame).map_err(io::Error::other)
+}
@@ -0,0 +1,170 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::chip8::CHIP8;
+use crate::error::Fault;
+use crate::framebuffer::Framebuffer;
+
+/// how long a test runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Length {
+    Frames(u64), // frames of 1/60 second
+    Cycles(u64), // cpu cycles
+}
+
+/// a key held down for some frames of a test
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPress {
+    pub frame: u64,  // the frame the key is pressed at
+    pub key: u8,     // the key of the keypad, 0 to F
+    pub frames: u64, // the frames the key is held
+}
+
+/// the frames a key is held when the script does not say
+const DEFAULT_HOLD: u64 = 6;
+
+impl FromStr for KeyPress {
+    type Err = String;
+
+    /// parse `FRAME:KEY` or `FRAME:KEY:FRAMES`, the key is hexadecimal, e.g. `60:A` or `60:A:30`.
+    /// a key is held for one frame at least, else it would never be released
+    fn from_str(s: &str) -> Result<KeyPress, String> {
+        let invalid = || format!("invalid key press, expected FRAME:KEY[:FRAMES]: {}", s);
+        let fields: Vec<&str> = s.split(':').collect();
+        let (frame, key, frames) = match fields.as_slice() {
+            [frame, key] => (frame, key, None),
+            [frame, key, frames] => (frame, key, Some(frames)),
+            _ => return Err(invalid()),
+        };
+        let key = u8::from_str_radix(key, 16)
+            .ok()
+            .filter(|&key| key < 16)
+            .ok_or_else(invalid)?;
+        Ok(KeyPress {
+            frame: frame.parse().map_err(|_| invalid())?,
+            key,
+            frames: match frames {
+                Some(frames) => frames
+                    .parse()
+                    .ok()
+                    .filter(|&frames| frames > 0)
+                    .ok_or_else(invalid)?,
+                None => DEFAULT_HOLD,
+            },
+        })
+    }
+}
+
+/// the state of the machine at the end of a test
+pub struct Outcome {
+    pub frames: u64,
+    pub cycles: u64,
+    pub beeps: u64,           // the frames the beeper sounded
+    pub fault: Option<Fault>, // the fault which stopped the test early
+    pub framebuffer: Framebuffer,
+}
+
+/// run the loaded program headless for the length, pressing the keys at their frames
+pub fn run(chip8: &mut CHIP8, length: Length, keys: &[KeyPress]) -> Outcome {
+    let mut frame = 0;
+    let mut beeps = 0;
+    let mut fault = None;
+    'run: while length != Length::Frames(frame) {
+        for press in keys.iter() {
+            if press.frame == frame {
+                chip8.press_key(press.key);
+            } else if press.frame.saturating_add(press.frames) == frame {
+                chip8.release_key(press.key);
+            }
+        }
+
+        chip8.start_frame();
+        beeps += chip8.is_beeping() as u64;
+        for _ in 0..chip8.cycles_per_frame() {
+            if length == Length::Cycles(chip8.cycles()) {
+                break 'run;
+            }
+            if let Err(err) = chip8.step() {
+                fault = Some(err);
+                break 'run;
+            }
+        }
+        frame += 1;
+    }
+
+    Outcome {
+        frames: frame,
+        cycles: chip8.cycles(),
+        beeps,
+        fault,
+        framebuffer: Framebuffer::from_display(chip8.display()),
+    }
+}
+
+/// check the outcome of a test against the expected framebuffer, if there is one.
+/// return whether the test passed and its report
+pub fn check(outcome: &Outcome, expected: Option<&Framebuffer>) -> (bool, String) {
+    let mut report = String::new();
+    let mut passed = true;
+    if let Some(fault) = outcome.fault {
+        writeln!(report, "FAULT: {}.", fault).unwrap();
+        passed = false;
+    }
+    writeln!(
+        report,
+        "Ran {} frames, {} cycles, the beeper sounded for {} frames.",
+        outcome.frames, outcome.cycles, outcome.beeps
+    )
+    .unwrap();
+
+    match expected {
+        Some(expected) => {
+            let differences = outcome.framebuffer.differences(expected);
+            if differences.is_empty() {
+                writeln!(report, "PASS: the display matches the expected image.").unwrap();
+            } else {
+                let (x, y) = differences[0];
+                writeln!(
+                    report,
+                    "FAIL: {} pixels differ from the expected image, the first at ({}, {}), \
+                     + is lit but expected dark, - is dark but expected lit:",
+                    differences.len(),
+                    x,
+                    y
+                )
+                .unwrap();
+                report.push_str(&outcome.framebuffer.to_ascii_diff(expected));
+                passed = false;
+            }
+        }
+        None => report.push_str(&outcome.framebuffer.to_ascii()),
+    }
+    (passed, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_presses_parse() {
+        let press = |frame, key, frames| KeyPress { frame, key, frames };
+        assert_eq!("60:A".parse(), Ok(press(60, 0xA, DEFAULT_HOLD)));
+        assert_eq!("0:f:30".parse(), Ok(press(0, 0xF, 30)));
+        for invalid in [
+            "60", "60:G", "60:10", "x:A", "-1:A", "60:A:0", "60:A:x", "60:A:1:2",
+        ] {
+            assert!(invalid.parse::<KeyPress>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn key_presses_held_to_the_end_do_not_overflow() {
+        let mut chip8 = CHIP8::new("TEST");
+        chip8.load(&[0x12, 0x00]).unwrap(); // 0x200: JP 0x200
+        let keys = ["1:5:18446744073709551615".parse().unwrap()];
+        let outcome = run(&mut chip8, Length::Frames(3), &keys);
+        assert_eq!(outcome.frames, 3);
+        assert!(outcome.fault.is_none());
+    }
+}
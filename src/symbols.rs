@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::chip8::operation::Op;
+use crate::octo::Program;
+
+/// the farthest a symbol is used as the base of an address, e.g. `main+0x4`
+const MAX_OFFSET: u16 = 0x100;
+
+/// names of the addresses of a program
+#[derive(Default)]
+pub struct Symbols {
+    names: BTreeMap<u16, String>,
+    addresses: HashMap<String, u16>,
+}
+
+impl Symbols {
+    /// create an empty symbol table
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    /// load a symbol map
+    pub fn load(path: &Path) -> io::Result<Symbols> {
+        Symbols::parse(&fs::read_to_string(path)?)
+    }
+
+    /// parse a symbol map, each line is either `label = address` or an address and a label separated by spaces.
+    /// empty lines and lines starting with `#` or `;` are ignored, a label can not name two addresses
+    pub fn parse(text: &str) -> io::Result<Symbols> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut symbols = Symbols::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            let words: Vec<&str> = line
+                .split(|c: char| c.is_whitespace() || c == '=')
+                .filter(|w| !w.is_empty())
+                .collect();
+            let symbol = match words.as_slice() {
+                [name, address] if line.contains('=') => {
+                    parse_address(address).map(|address| (name, address))
+                }
+                // a name can look like a hexadecimal number too, an address with the 0x prefix is preferred
+                [first, second] => match (parse_address(first), parse_address(second)) {
+                    (Some(address), None) => Some((second, address)),
+                    (None, Some(address)) => Some((first, address)),
+                    (Some(address), Some(_)) if first.starts_with("0x") => Some((second, address)),
+                    (Some(_), Some(address)) => Some((first, address)),
+                    (None, None) => None,
+                },
+                _ => None,
+            };
+            let (name, address) = symbol
+                .ok_or_else(|| invalid(format!("invalid symbol at line {}: {}", i + 1, line)))?;
+            match symbols.addresses.get(*name) {
+                Some(&other) if other != address => {
+                    return Err(invalid(format!(
+                        "symbol {} at line {} is already defined at {:04X}",
+                        name,
+                        i + 1,
+                        other
+                    )));
+                }
+                _ => symbols.insert(name.to_string(), address),
+            }
+        }
+        Ok(symbols)
+    }
+
+    /// create the symbol table of a compiled Octo program
+    pub fn from_program(program: &Program) -> Symbols {
+        let mut symbols = Symbols::new();
+        program
+            .symbols
+            .iter()
+            .for_each(|(name, address)| symbols.insert(name.clone(), *address));
+        symbols
+    }
+
+    /// add a symbol, the first name of an address is the one it is shown with
+    pub fn insert(&mut self, name: String, address: u16) {
+        self.names.entry(address).or_insert_with(|| name.clone());
+        self.addresses.insert(name, address);
+    }
+
+    /// check whether the table is empty
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// return the name of the address, if a symbol is defined exactly there
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(|name| name.as_str())
+    }
+
+    /// describe the address by the nearest symbol before it, `draw_player` or `main+0x4`
+    pub fn locate(&self, address: u16) -> Option<String> {
+        let (&base, name) = self.names.range(..=address).next_back()?;
+        match address - base {
+            0 => Some(name.clone()),
+            offset if offset < MAX_OFFSET => Some(format!("{}+0x{:X}", name, offset)),
+            _ => None,
+        }
+    }
+
+    /// describe the address as `0204 <main+0x4>`, or only the address if no symbol is near
+    pub fn describe(&self, address: u16) -> String {
+        match self.locate(address) {
+            Some(location) => format!("{:04X} <{}>", address, location),
+            None => format!("{:04X}", address),
+        }
+    }
+
+    /// format an instruction with the names of the addresses it refers to
+    pub fn instruction(&self, op: Op) -> String {
+        let (mnemonic, address) = match op {
+            Op::SYS { address } => ("SYS", address),
+            Op::JP { address } => ("JP", address),
+            Op::CALL { address } => ("CALL", address),
+            Op::LDA { address } => ("LD I,", address),
+            Op::JPV { address } => ("JP V0,", address),
+            _ => return op.to_string(),
+        };
+        match self.name(address) {
+            Some(name) => format!("{} {}", mnemonic, name),
+            None => op.to_string(),
+        }
+    }
+
+    /// resolve an address expression: a hexadecimal address, a symbol, or a symbol plus or minus an offset
+    pub fn resolve(&self, expression: &str) -> Result<u16, String> {
+        let expression = expression.trim();
+        if let Some(&address) = self.addresses.get(expression) {
+            return Ok(address);
+        }
+
+        let (base, offset) = match expression.rfind(['+', '-']) {
+            Some(i) => {
+                let offset = parse_number(&expression[i + 1..])
+                    .ok_or_else(|| format!("invalid offset: {}", &expression[i + 1..]))?;
+                let offset = if expression[i..].starts_with('-') {
+                    -offset
+                } else {
+                    offset
+                };
+                (expression[..i].trim(), offset)
+            }
+            None => (expression, 0),
+        };
+
+        let base = match self.addresses.get(base) {
+            Some(&address) => address,
+            None => parse_address(base).ok_or_else(|| format!("unknown symbol: {}", base))?,
+        };
+        u16::try_from(base as i64 + offset).map_err(|_| format!("invalid address: {}", expression))
+    }
+}
+
+/// parse a hexadecimal address, with or without the 0x prefix
+pub fn parse_address(address: &str) -> Option<u16> {
+    let digits = address.trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// parse an offset, hexadecimal with the 0x prefix or decimal without it
+fn parse_number(number: &str) -> Option<i64> {
+    let number = number.trim();
+    match number.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => number.parse::<i64>().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_map_formats_are_parsed() {
+        // an Octo .sym file lists the address first, other maps have `label = address`
+        let octo = Symbols::parse("0x200 main\n0x20A draw_player\n\n# a comment\n").unwrap();
+        let assignments = Symbols::parse("; a comment\nmain = 0x200\ndraw_player = 20A\n").unwrap();
+        for symbols in [octo, assignments] {
+            assert_eq!(symbols.name(0x200), Some("main"));
+            assert_eq!(symbols.name(0x20A), Some("draw_player"));
+            assert_eq!(symbols.resolve("draw_player"), Ok(0x20A));
+        }
+
+        // a label which looks like a hexadecimal number is taken as the label next to a 0x address
+        let symbols = Symbols::parse("0x300 add\nbeef 0x310\n").unwrap();
+        assert_eq!(symbols.name(0x300), Some("add"));
+        assert_eq!(symbols.name(0x310), Some("beef"));
+
+        assert!(Symbols::parse("main\n").is_err());
+        assert!(Symbols::parse("main = 0x200 0x202\n").is_err());
+        assert!(Symbols::parse("main = nowhere\n").is_err());
+    }
+
+    #[test]
+    fn a_label_names_one_address() {
+        let err = Symbols::parse("main = 0x200\nmain = 0x210\n")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "symbol main at line 2 is already defined at 0200"
+        );
+
+        // the same definition twice is harmless, and an address keeps the first of its names
+        let symbols = Symbols::parse("main = 0x200\n0x200 main\nstart = 0x200\n").unwrap();
+        assert_eq!(symbols.name(0x200), Some("main"));
+        assert_eq!(symbols.resolve("start"), Ok(0x200));
+    }
+
+    #[test]
+    fn addresses_are_described_by_the_symbol_before_them() {
+        let symbols = Symbols::parse("main = 0x200\nsprite = 0x400\n").unwrap();
+        assert_eq!(symbols.describe(0x200), "0200 <main>");
+        assert_eq!(symbols.describe(0x204), "0204 <main+0x4>");
+        assert_eq!(symbols.describe(0x2FF), "02FF <main+0xFF>");
+        // too far from the symbol before, or before any symbol
+        assert_eq!(symbols.describe(0x300), "0300");
+        assert_eq!(symbols.describe(0x1FE), "01FE");
+        assert_eq!(symbols.describe(0x401), "0401 <sprite+0x1>");
+    }
+
+    #[test]
+    fn expressions_resolve_symbols_and_offsets() {
+        let symbols = Symbols::parse("main = 0x200\nsprite = 0x400\n").unwrap();
+        assert_eq!(symbols.resolve("main"), Ok(0x200));
+        assert_eq!(symbols.resolve(" main+4 "), Ok(0x204));
+        assert_eq!(symbols.resolve("sprite + 0x10"), Ok(0x410));
+        assert_eq!(symbols.resolve("sprite-0x2"), Ok(0x3FE));
+        assert_eq!(symbols.resolve("0x2A0"), Ok(0x2A0));
+        assert_eq!(symbols.resolve("2A0+2"), Ok(0x2A2));
+        assert_eq!(
+            symbols.resolve("nowhere"),
+            Err(String::from("unknown symbol: nowhere"))
+        );
+        assert_eq!(
+            symbols.resolve("main+x"),
+            Err(String::from("invalid offset: x"))
+        );
+        assert_eq!(
+            symbols.resolve("main-0x201"),
+            Err(String::from("invalid address: main-0x201"))
+        );
+    }
+}
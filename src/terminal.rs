@@ -0,0 +1,215 @@
+use std::io::{self, Stdout, Write};
+use std::time::Duration;
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{
+    Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor,
+};
+use crossterm::{cursor, queue, terminal};
+
+use crate::chip8::keymap::KeyMap;
+use crate::framebuffer::Framebuffer;
+use crate::manager::ManagerEvent;
+use crate::palette::{Palette, Rgb};
+use crate::phosphor::Phosphor;
+
+/// the display takes a character per column and two pixel rows per line, and a status line follows it
+const COLUMNS: u16 = 64;
+const LINES: u16 = 32 / 2 + 1;
+
+/// convert a palette color to a terminal color
+fn color([r, g, b]: Rgb) -> Color {
+    Color::Rgb { r, g, b }
+}
+
+/// the frontend running in a terminal, it draws with half block characters and reads keys in raw mode
+pub struct Terminal {
+    stdout: Stdout,
+    palette: Palette,
+    title: String,
+    releases: bool,                 // whether the terminal reports key releases
+    shown: Option<(Vec<u8>, bool)>, // the brightness of the pixels and the beep on the terminal
+}
+
+impl Terminal {
+    /// switch the terminal to raw mode and the alternate screen, the title is shown in the status line
+    pub fn new(title: &str, palette: Palette) -> io::Result<Terminal> {
+        let (columns, lines) = terminal::size()?;
+        if columns < COLUMNS || lines < LINES {
+            return Err(io::Error::other(format!(
+                "the terminal is {}x{}, at least {}x{} is needed",
+                columns, lines, COLUMNS, LINES
+            )));
+        }
+
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        queue!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+        // only some terminals report key releases, the others only repeat the presses of a held key
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if releases {
+            queue!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        stdout.flush()?;
+
+        Ok(Terminal {
+            stdout,
+            palette,
+            title: title.to_string(),
+            releases,
+            shown: None,
+        })
+    }
+
+    /// return the colors of the pixels
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// change the colors of the pixels, the whole screen is drawn again
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.shown = None;
+    }
+
+    /// check whether the terminal reports key releases
+    pub fn reports_releases(&self) -> bool {
+        self.releases
+    }
+
+    /// wait up to the timeout for an event(KeyDown, KeyUp, Screenshot, Record, Palette, Redraw, Quit, None)
+    pub fn poll_event(&mut self, keymap: &KeyMap, timeout: Duration) -> io::Result<ManagerEvent> {
+        if !event::poll(timeout)? {
+            return Ok(ManagerEvent::None);
+        }
+        let event = match event::read()? {
+            Event::Key(KeyEvent {
+                code: KeyCode::Esc,
+                kind: KeyEventKind::Press,
+                ..
+            }) => ManagerEvent::Quit,
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            }) => ManagerEvent::Quit,
+            Event::Key(KeyEvent {
+                code: KeyCode::F(12),
+                kind: KeyEventKind::Press,
+                ..
+            }) => ManagerEvent::Screenshot,
+            Event::Key(KeyEvent {
+                code: KeyCode::F(10),
+                kind: KeyEventKind::Press,
+                ..
+            }) => ManagerEvent::Record,
+            Event::Key(KeyEvent {
+                code: KeyCode::F(9),
+                kind: KeyEventKind::Press,
+                ..
+            }) => ManagerEvent::Palette,
+            Event::Key(KeyEvent {
+                code: KeyCode::Char(c),
+                kind,
+                ..
+            }) => match (keymap.char(c), kind) {
+                (Some(key), KeyEventKind::Release) => ManagerEvent::KeyUp(key),
+                (Some(key), _) => ManagerEvent::KeyDown(key),
+                (None, _) => ManagerEvent::None,
+            },
+            // the terminal may have cleared the screen
+            Event::Resize(..) => ManagerEvent::Redraw,
+            _ => ManagerEvent::None,
+        };
+        Ok(event)
+    }
+
+    /// draw the whole screen again, e.g. after messages were printed over it
+    pub fn refresh(&mut self) {
+        self.shown = None;
+    }
+
+    /// draw the screen and the status line, with the beep shown as a mark, if they changed.
+    /// a character shows two pixels, the upper one in its foreground color and the lower one in its background color
+    pub fn draw(&mut self, phosphor: &Phosphor, beep: bool) -> io::Result<()> {
+        if self.shown.as_ref().is_some_and(|(levels, shown_beep)| {
+            levels.as_slice() == phosphor.levels() && *shown_beep == beep
+        }) {
+            return Ok(());
+        }
+
+        if self.shown.is_none() {
+            queue!(
+                self.stdout,
+                ResetColor,
+                terminal::Clear(terminal::ClearType::All)
+            )?;
+        }
+        for line in 0..Framebuffer::height() / 2 {
+            queue!(self.stdout, cursor::MoveTo(0, line as u16))?;
+            let mut colors = None;
+            for x in 0..Framebuffer::width() {
+                let upper = self.palette.mix(phosphor.level(x, line * 2));
+                let lower = self.palette.mix(phosphor.level(x, line * 2 + 1));
+                // the colors are only sent when they change along the line
+                if colors != Some((upper, lower)) {
+                    queue!(
+                        self.stdout,
+                        SetForegroundColor(color(upper)),
+                        SetBackgroundColor(color(lower))
+                    )?;
+                    colors = Some((upper, lower));
+                }
+                queue!(self.stdout, Print('▀'))?;
+            }
+        }
+
+        let status = format!(
+            " {}  Esc quit  F9 palette  F10 record  F12 screenshot",
+            self.title
+        );
+        queue!(
+            self.stdout,
+            ResetColor,
+            cursor::MoveTo(0, LINES - 1),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            Print(status),
+        )?;
+        if beep {
+            queue!(
+                self.stdout,
+                cursor::MoveTo(COLUMNS - 8, LINES - 1),
+                SetAttribute(Attribute::Reverse),
+                Print(" ♪ BEEP "),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+        self.stdout.flush()?;
+
+        self.shown = Some((phosphor.levels().to_vec(), beep));
+        Ok(())
+    }
+}
+
+impl Drop for Terminal {
+    /// give the terminal back as it was
+    fn drop(&mut self) {
+        if self.releases {
+            let _ = queue!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = queue!(
+            self.stdout,
+            ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = self.stdout.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
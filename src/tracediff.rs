@@ -0,0 +1,336 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::Write;
+use std::io;
+
+use crate::chip8::display::Display;
+use crate::chip8::operation::{Op, OpCode};
+use crate::chip8::trace::{Changes, Record, TraceReader};
+
+/// what the comparison of two traces ignores
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiffOptions {
+    pub ignore_timers: bool, // the timers tick at different cycles in other emulators
+    pub context: usize,      // the number of common records shown before the divergence
+}
+
+/// a field of the machine state with a different value in the two traces
+pub struct Difference {
+    pub field: String,
+    pub left: String,
+    pub right: String,
+}
+
+/// the result of comparing two traces
+pub enum Outcome {
+    Identical(usize),  // both traces have the same records, and this many of them
+    LeftEnded(usize),  // the left trace is a prefix of the right one
+    RightEnded(usize), // the right trace is a prefix of the left one
+    Diverged(Box<Divergence>), // the traces differ at a record
+}
+
+/// the first record where two traces differ
+pub struct Divergence {
+    pub index: usize, // the number of the record, starting at 1
+    pub left: Record,
+    pub right: Record,
+    pub differences: Vec<Difference>,
+    pub previous: Option<Record>, // the last common record, its instruction gave different results
+    pub context: VecDeque<Record>, // the common records before the divergence, from the left trace
+}
+
+/// compare two traces record by record and find the first divergence.
+/// the cycle numbers are not compared, emulators count them differently
+pub fn diff(left: TraceReader, right: TraceReader, options: DiffOptions) -> io::Result<Outcome> {
+    let mut left = left;
+    let mut right = right;
+    let mut context: VecDeque<Record> = VecDeque::new();
+    let mut previous = None;
+    let mut index = 0;
+
+    loop {
+        let (l, r) = match (left.next().transpose()?, right.next().transpose()?) {
+            (Some(l), Some(r)) => (l, r),
+            (None, None) => return Ok(Outcome::Identical(index)),
+            (None, Some(_)) => return Ok(Outcome::LeftEnded(index)),
+            (Some(_), None) => return Ok(Outcome::RightEnded(index)),
+        };
+        index += 1;
+
+        let differences = differences(&l, &r, options);
+        if !differences.is_empty() {
+            return Ok(Outcome::Diverged(Box::new(Divergence {
+                index,
+                left: l,
+                right: r,
+                differences,
+                previous,
+                context,
+            })));
+        }
+
+        previous = Some(l.clone());
+        context.push_back(l);
+        if context.len() > options.context {
+            context.pop_front();
+        }
+    }
+}
+
+/// list the fields of two records which differ
+fn differences(left: &Record, right: &Record, options: DiffOptions) -> Vec<Difference> {
+    let mut differences = vec![];
+    let mut compare = |field: &str, l: String, r: String| {
+        if l != r {
+            differences.push(Difference {
+                field: field.to_string(),
+                left: l,
+                right: r,
+            });
+        }
+    };
+
+    compare(
+        "PC",
+        format!("{:04X}", left.pc),
+        format!("{:04X}", right.pc),
+    );
+    compare(
+        "OP",
+        format!("{:04X}", left.opcode),
+        format!("{:04X}", right.opcode),
+    );
+    compare("I", format!("{:04X}", left.i), format!("{:04X}", right.i));
+    compare("SP", format!("{:X}", left.sp), format!("{:X}", right.sp));
+    if !options.ignore_timers {
+        compare(
+            "DT",
+            format!("{:02X}", left.dt),
+            format!("{:02X}", right.dt),
+        );
+        compare(
+            "ST",
+            format!("{:02X}", left.st),
+            format!("{:02X}", right.st),
+        );
+    }
+    for reg in 0..16 {
+        compare(
+            &format!("V{:X}", reg),
+            format!("{:02X}", left.v[reg]),
+            format!("{:02X}", right.v[reg]),
+        );
+    }
+    // the hashes are only compared if both traces have them, followed by the changes which differ
+    if let (Some(l), Some(r)) = (left.memory, right.memory) {
+        compare("MEM", format!("{:08X}", l), format!("{:08X}", r));
+        if l != r {
+            let addresses: BTreeSet<u16> = [&left.changes, &right.changes]
+                .iter()
+                .flat_map(|changes| changes.memory.iter().map(|&(address, _)| address))
+                .collect();
+            for address in addresses {
+                let byte = |changes: &Changes| {
+                    changes
+                        .memory
+                        .iter()
+                        .find(|&&(a, _)| a == address)
+                        .map_or(String::from("--"), |(_, byte)| format!("{:02X}", byte))
+                };
+                compare(
+                    &format!("[{:03X}]", address),
+                    byte(&left.changes),
+                    byte(&right.changes),
+                );
+            }
+        }
+    }
+    if let (Some(l), Some(r)) = (left.display, right.display) {
+        compare("GFX", format!("{:08X}", l), format!("{:08X}", r));
+        if l != r {
+            let rows: BTreeSet<u8> = [&left.changes, &right.changes]
+                .iter()
+                .flat_map(|changes| changes.rows.iter().map(|&(row, _)| row))
+                .collect();
+            for row in rows {
+                let pixels = |changes: &Changes| {
+                    changes.rows.iter().find(|&&(r, _)| r == row).map_or(
+                        String::from("--"),
+                        |&(_, pixels)| {
+                            (0..Display::display_width())
+                                .rev()
+                                .map(|x| if pixels >> x & 1 == 1 { '#' } else { '.' })
+                                .collect()
+                        },
+                    )
+                };
+                compare(
+                    &format!("ROW{:02}", row),
+                    pixels(&left.changes),
+                    pixels(&right.changes),
+                );
+            }
+        }
+    }
+    differences
+}
+
+/// format the instruction of a record, e.g. `0248: 8E06 SHR VE, VE`
+fn instruction(record: &Record) -> String {
+    match Op::decode(&OpCode::new(record.opcode)) {
+        Some(op) => format!("{:04X}: {:04X} {}", record.pc, record.opcode, op),
+        None => format!("{:04X}: {:04X} ???", record.pc, record.opcode),
+    }
+}
+
+/// describe the result of a comparison
+pub fn report(outcome: &Outcome) -> String {
+    let divergence = match outcome {
+        Outcome::Identical(count) => {
+            return format!("The traces are identical, {} records.\n", count)
+        }
+        Outcome::LeftEnded(count) => {
+            return format!(
+                "The left trace ends after {} records, the right one goes on.\n",
+                count
+            )
+        }
+        Outcome::RightEnded(count) => {
+            return format!(
+                "The right trace ends after {} records, the left one goes on.\n",
+                count
+            )
+        }
+        Outcome::Diverged(divergence) => divergence,
+    };
+
+    let mut report = String::new();
+    writeln!(
+        report,
+        "The traces diverge at record {} (cycle {} on the left, {} on the right).",
+        divergence.index, divergence.left.cycle, divergence.right.cycle
+    )
+    .unwrap();
+
+    if !divergence.context.is_empty() {
+        writeln!(report, "\nThe common instructions before:").unwrap();
+        for record in divergence.context.iter() {
+            writeln!(report, "    {}", instruction(record)).unwrap();
+        }
+    }
+
+    writeln!(
+        report,
+        "\nThe state before the next instruction, left != right:"
+    )
+    .unwrap();
+    for difference in divergence.differences.iter() {
+        writeln!(
+            report,
+            "    {:<7}{} != {}",
+            difference.field, difference.left, difference.right
+        )
+        .unwrap();
+    }
+
+    match divergence.previous.as_ref() {
+        Some(previous) => {
+            writeln!(
+                report,
+                "\nThe instruction {} gave different results.",
+                instruction(previous)
+            )
+            .unwrap();
+            let fields = |prefix: &str| {
+                divergence
+                    .differences
+                    .iter()
+                    .any(|d| d.field.starts_with(prefix))
+            };
+            if fields("[") || fields("ROW") {
+                writeln!(
+                    report,
+                    "[ADDR] and ROWnn changed since the previous record, -- where a trace left them as they were."
+                )
+                .unwrap();
+            }
+            if fields("MEM") && !fields("[") {
+                writeln!(
+                    report,
+                    "The memory from 0x200 on differs, the traces only carry its hash."
+                )
+                .unwrap();
+            }
+            if fields("GFX") && !fields("ROW") {
+                writeln!(
+                    report,
+                    "The display differs, the traces only carry its hash."
+                )
+                .unwrap();
+            }
+        }
+        None => writeln!(report, "\nThe traces differ from the first record.").unwrap(),
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the record of a machine where the instruction before wrote memory and drew
+    fn record(changes: Changes) -> Record {
+        let line = "C=4 PC=0208 OP=1208 I=0000 SP=0 DT=00 ST=00 V=00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00";
+        let mut record = Record::parse_text(line).unwrap();
+        record.memory = Some(fnv1a_of(&changes));
+        record.display = Some(fnv1a_of(&changes));
+        record.changes = changes;
+        record
+    }
+
+    /// a hash which differs for different changes
+    fn fnv1a_of(changes: &Changes) -> u32 {
+        crate::chip8::trace::fnv1a(format!("{:?}", changes).into_bytes())
+    }
+
+    #[test]
+    fn differences_list_the_changed_bytes_and_rows() {
+        let left = record(Changes {
+            memory: vec![(0x300, 1), (0x301, 2)],
+            rows: vec![(30, 0xF0 << 56)],
+        });
+        let right = record(Changes {
+            memory: vec![(0x300, 1), (0x302, 2)],
+            rows: vec![(30, 0xF0 << 56), (0, 0xF0 << 56)],
+        });
+        let fields: Vec<(String, String, String)> =
+            differences(&left, &right, DiffOptions::default())
+                .into_iter()
+                .map(|d| (d.field, d.left, d.right))
+                .collect();
+        let field = |name: &str| fields.iter().find(|(f, _, _)| f == name).cloned();
+
+        assert!(field("MEM").is_some());
+        assert!(field("[300]").is_none());
+        assert_eq!(
+            field("[301]"),
+            Some(("[301]".into(), "02".into(), "--".into()))
+        );
+        assert_eq!(
+            field("[302]"),
+            Some(("[302]".into(), "--".into(), "02".into()))
+        );
+        assert!(field("GFX").is_some());
+        assert!(field("ROW30").is_none());
+        let row = format!("####{}", ".".repeat(60));
+        assert_eq!(field("ROW00"), Some(("ROW00".into(), "--".into(), row)));
+    }
+
+    #[test]
+    fn differences_skip_the_changes_when_the_hashes_are_equal() {
+        let mut left = record(Changes::default());
+        let right = left.clone();
+        left.changes.memory.push((0x300, 1));
+        assert!(differences(&left, &right, DiffOptions::default()).is_empty());
+    }
+}